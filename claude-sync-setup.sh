@@ -22,6 +22,7 @@ set -euo pipefail
 CLAUDE_DIR="$HOME/.claude"
 BACKUP_DIR="$HOME/.claude_backup"
 CONFIG_FILE="$HOME/.claude_sync_config"
+TOML_CONFIG_FILE="$HOME/.config/claude-sync/config.toml"
 
 # Will be set by prompt or config file
 DROPBOX_BASE=""
@@ -452,6 +453,18 @@ prompt_dropbox_location() {
     # Save for future use
     echo "DROPBOX_BASE=\"$DROPBOX_BASE\"" > "$CONFIG_FILE"
     log_success "Saved Dropbox location to $CONFIG_FILE"
+
+    # The daemon reads config.toml once it exists (it is created from
+    # ~/.claude_sync_config on first run)
+    if [[ -f "$TOML_CONFIG_FILE" ]]; then
+        if grep -q "^\(dropbox_\)\{0,1\}base *=" "$TOML_CONFIG_FILE"; then
+            sed -i.bak "s|^\(dropbox_\)\{0,1\}base *=.*|base = \"$DROPBOX_BASE\"|" "$TOML_CONFIG_FILE"
+            rm -f "$TOML_CONFIG_FILE.bak"
+            log_success "Updated base in $TOML_CONFIG_FILE"
+        else
+            log_warn "Set base under [paths] in $TOML_CONFIG_FILE"
+        fi
+    fi
     echo ""
 }
 
@@ -763,6 +776,12 @@ undo_pull() {
     local backup_path
     backup_path=$(cat "$LAST_BACKUP_FILE")
 
+    # The watch daemon points the marker at its latest snapshot manifest
+    if [[ "$backup_path" == *.json ]]; then
+        run_watch_backups undo
+        return
+    fi
+
     if [[ ! -d "$backup_path" ]]; then
         log_error "Backup not found: $backup_path"
         log_info "Run --backups to see available backups."
@@ -822,6 +841,16 @@ list_backups() {
         echo "  $0 --restore <backup_path>"
     fi
     echo ""
+
+    # Snapshots taken by the watch daemon
+    if [[ -x "$WATCH_BINARY" ]]; then
+        echo -e "${CYAN}Watch daemon snapshots:${NC}"
+        "$WATCH_BINARY" backups list || true
+        echo ""
+        log_info "To restore a snapshot, run:"
+        echo "  $0 --restore <snapshot_id>"
+        echo ""
+    fi
 }
 
 # ─────────────────────────────────────────────────────────────────────────────
@@ -838,6 +867,12 @@ restore_backup() {
         exit 1
     fi
 
+    # Anything but a path is a watch daemon snapshot id
+    if [[ "$backup_path" != */* && -x "$WATCH_BINARY" ]]; then
+        run_watch_backups restore "$backup_path"
+        return
+    fi
+
     if [[ ! -d "$backup_path" ]]; then
         log_error "Backup not found: $backup_path"
         list_backups
@@ -1056,7 +1091,7 @@ show_status() {
 
 usage() {
     cat << EOF
-Usage: $0 [--push | --pull | --status | --config | --backup | --undo | --backups | --restore <path>]
+Usage: $0 [--push | --pull | --status | --config | --backup | --undo | --backups | --restore <path|id>]
        $0 [--watch-start | --watch-stop | --watch-status | --watch-install]
 
 Options:
@@ -1065,9 +1100,10 @@ Options:
   --status         Show sync status and file differences
   --config         Reconfigure Dropbox folder location
   --backup         Create timestamped backup of ~/.claude
-  --undo           Restore previous state after a pull
-  --backups        List all available backups
-  --restore <path> Restore from a specific backup
+  --undo           Restore previous state after a pull (or the watch daemon's last sync)
+  --backups        List all available backups, including watch daemon snapshots
+  --restore <path|id>
+                   Restore from a specific backup or watch daemon snapshot
   --cleanup-backups [N] [--dry-run]
                     Remove redundant backups (keeps only unique synced files)
                     N: max unique backups to keep (default: 10)
@@ -1102,6 +1138,8 @@ EOF
 
 DAEMON_SCRIPT="$(dirname "${BASH_SOURCE[0]}")/claude-sync-daemon.sh"
 
+WATCH_BINARY="$(dirname "${BASH_SOURCE[0]}")/watch/target/release/claude-sync-watch"
+
 run_daemon_cmd() {
     if [[ ! -f "$DAEMON_SCRIPT" ]]; then
         log_error "Daemon script not found: $DAEMON_SCRIPT"
@@ -1110,6 +1148,17 @@ run_daemon_cmd() {
     "$DAEMON_SCRIPT" "$@"
 }
 
+# Snapshots taken by the watch daemon live in its own store
+# (<backups.dir>/.claude_backups), so they are handled by its backups command
+run_watch_backups() {
+    if [[ ! -x "$WATCH_BINARY" ]]; then
+        log_error "claude-sync-watch not built: $WATCH_BINARY"
+        log_info "Build it with: $DAEMON_SCRIPT build"
+        exit 1
+    fi
+    "$WATCH_BINARY" backups "$@"
+}
+
 main() {
     echo ""
     echo "━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━━"
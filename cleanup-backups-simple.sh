@@ -1,9 +1,12 @@
 #!/usr/bin/env bash
 # Simple backup cleanup - keeps most recent N backups, removes the rest
-# Also cleans up Dropbox conflict files
+# Also prunes the watch daemon's snapshots and cleans up Dropbox conflict files
 
 set -euo pipefail
 
+SCRIPT_DIR="$(cd "$(dirname "${BASH_SOURCE[0]}")" && pwd)"
+WATCH_BINARY="$SCRIPT_DIR/watch/target/release/claude-sync-watch"
+
 # Colors
 RED='\033[0;31m'
 GREEN='\033[0;32m'
@@ -25,19 +28,17 @@ log_info "Will keep: $MAX_KEEP most recent backups"
 [[ "$DRY_RUN" == "true" ]] && log_warn "DRY RUN MODE - no files will be deleted"
 
 # Count all backups
-total=$(ls -d "$HOME"/.claude_backup.* 2>/dev/null | wc -l | tr -d ' ')
+total=$(ls -d "$HOME"/.claude_backup.* 2>/dev/null | wc -l | tr -d ' ' || true)
 
 if [[ $total -eq 0 ]]; then
     log_info "No backups found."
-    exit 0
-fi
-
-log_info "Found $total backups"
-
-if [[ $total -le $MAX_KEEP ]]; then
+    # Don't exit - continue to snapshot and Dropbox conflict cleanup
+elif [[ $total -le $MAX_KEEP ]]; then
+    log_info "Found $total backups"
     log_success "Only $total backups exist (≤ $MAX_KEEP). Nothing to remove."
-    # Don't exit - continue to Dropbox conflict cleanup
+    # Don't exit - continue to snapshot and Dropbox conflict cleanup
 else
+    log_info "Found $total backups"
     to_remove=$((total - MAX_KEEP))
     log_info "Will remove: $to_remove oldest backups"
 
@@ -88,6 +89,19 @@ else
     fi
 fi
 
+# Prune the watch daemon's snapshots (<backups.dir>/.claude_backups), which
+# follow the [backups] retention policy in config.toml rather than N
+echo ""
+log_info "Checking watch daemon snapshots..."
+
+if [[ ! -x "$WATCH_BINARY" ]]; then
+    log_info "claude-sync-watch not built, skipping its snapshots."
+elif [[ "$DRY_RUN" == "true" ]]; then
+    "$WATCH_BINARY" backups prune --dry-run
+else
+    "$WATCH_BINARY" backups prune
+fi
+
 # Cleanup Dropbox conflict files
 echo ""
 log_info "Checking for Dropbox conflict files..."
@@ -17,6 +17,7 @@ set -euo pipefail
 PLIST_NAME="com.claude.sync-watch"
 PLIST_PATH="$HOME/Library/LaunchAgents/$PLIST_NAME.plist"
 CONFIG_FILE="$HOME/.claude_sync_config"
+TOML_CONFIG_FILE="$HOME/.config/claude-sync/config.toml"
 LOG_DIR="$HOME/.claude_sync_logs"
 LAST_BACKUP_FILE="$HOME/.claude_sync_last_backup"
 BACKUP_SYMLINK="$HOME/.claude_backup"
@@ -112,6 +113,26 @@ detect_dropbox() {
     echo "$dropbox_base"
 }
 
+# ─────────────────────────────────────────────────────────────────────────────
+# Detect Snapshot Store
+# ─────────────────────────────────────────────────────────────────────────────
+
+# The watch daemon keeps its snapshots in <backups.dir>/.claude_backups, where
+# backups.dir is set in config.toml (default ~)
+detect_backup_store() {
+    local backup_dir=""
+
+    if [[ -f "$TOML_CONFIG_FILE" ]]; then
+        backup_dir=$(awk '
+            /^ *\[/ { section = $1 }
+            section == "[backups]" && /^ *dir *=/ { sub(/^[^=]*= *"/, ""); sub(/".*/, ""); print; exit }
+        ' "$TOML_CONFIG_FILE" 2>/dev/null || true)
+    fi
+
+    backup_dir="${backup_dir:-$HOME}"
+    echo "${backup_dir/#\~/$HOME}/.claude_backups"
+}
+
 # ─────────────────────────────────────────────────────────────────────────────
 # Uninstall Steps
 # ─────────────────────────────────────────────────────────────────────────────
@@ -169,6 +190,7 @@ remove_config_files() {
     echo "─────────────────────────────────"
     
     remove_file "$CONFIG_FILE" "~/.claude_sync_config (Dropbox location config)" || true
+    remove_file "$TOML_CONFIG_FILE" "~/.config/claude-sync/config.toml (daemon config)" || true
     remove_file "$LAST_BACKUP_FILE" "~/.claude_sync_last_backup (undo marker)" || true
     remove_file "$HOME/.claude/.sync_state.json" "~/.claude/.sync_state.json (sync state)" || true
     remove_file "$BACKUP_SYMLINK" "~/.claude_backup (symlink to latest backup)" || true
@@ -214,9 +236,13 @@ remove_backups() {
         backup_files+=("$file")
     done < <(find "$HOME" -maxdepth 1 -type f -name ".claude.json.backup.*" -print0 2>/dev/null)
 
+    # The watch daemon's snapshot store
+    local store_count=0
+    [[ -d "$BACKUP_STORE" ]] && store_count=1
+
     local dir_count=${#backup_dirs[@]}
     local file_count=${#backup_files[@]}
-    local total_count=$((dir_count + file_count))
+    local total_count=$((dir_count + file_count + store_count))
 
     if [[ $total_count -eq 0 ]]; then
         log_info "No backup directories or files found"
@@ -224,10 +250,12 @@ remove_backups() {
     fi
 
     # Report what was found
+    [[ $store_count -gt 0 ]] && log_info "Found snapshot store: $BACKUP_STORE"
     [[ $dir_count -gt 0 ]] && log_info "Found $dir_count backup directory(s)"
     [[ $file_count -gt 0 ]] && log_info "Found $file_count backup file(s)"
 
     if [[ "$DRY_RUN" == "true" ]]; then
+        [[ $store_count -gt 0 ]] && log_dry "$BACKUP_STORE (watch daemon snapshots)"
         if [[ $dir_count -gt 0 ]]; then
             if [[ $dir_count -gt 10 ]]; then
                 log_dry "${backup_dirs[0]} ... and $((dir_count - 1)) more directories"
@@ -246,6 +274,11 @@ remove_backups() {
         local deleted=0
         local last_update=$SECONDS
 
+        if [[ $store_count -gt 0 ]]; then
+            rm -rf "$BACKUP_STORE"
+            deleted=$((deleted + 1))
+        fi
+
         # Remove directories with progress
         if [[ $dir_count -gt 0 ]]; then
             for backup in "${backup_dirs[@]}"; do
@@ -268,8 +301,9 @@ remove_backups() {
         fi
 
         printf "\r\033[K"
+        [[ $store_count -gt 0 ]] && log_success "Removed snapshot store: $BACKUP_STORE"
         [[ $dir_count -gt 0 ]] && log_success "Removed $dir_count backup directories"
-        [[ $file_count -gt 0 ]] && log_success "Removed $file_count backup files"
+        [[ $file_count -gt 0 ]] && log_success "Removed $file_count backup files" || true
     else
         log_info "Keeping backups"
     fi
@@ -400,6 +434,9 @@ verify_uninstall() {
     else
         log_success "Backups removed"
     fi
+    if [[ -d "$BACKUP_STORE" ]]; then
+        log_warn "Snapshot store still exists: $BACKUP_STORE"
+    fi
     
     # Check processes
     if pgrep -f "claude-sync-watch" >/dev/null 2>&1; then
@@ -430,18 +467,18 @@ Complete uninstall script for claude-mac-sync.
 Options:
   --force           Remove everything without prompts
   --dry-run         Show what would be removed without removing
-  --keep-backups    Keep ~/.claude_backup.* directories
+  --keep-backups    Keep ~/.claude_backup.* directories and daemon snapshots
   --keep-dropbox    Keep ~/Dropbox/ClaudeCodeSync directory
   --help, -h        Show this help message
 
 What gets removed:
   1. LaunchAgent daemon (com.claude.sync-watch)
   2. Launchd plist (~/.Library/LaunchAgents/com.claude.sync-watch.plist)
-  3. Configuration file (~/.claude_sync_config)
+  3. Configuration files (~/.claude_sync_config, ~/.config/claude-sync/config.toml)
   4. Sync state files (~/.claude/.sync_state.json, ~/.claude/.sync.pid)
   5. Last backup marker (~/.claude_sync_last_backup)
   6. Log directory (~/.claude_sync_logs/)
-  7. Backup directories (~/.claude_backup.*)
+  7. Backup directories (~/.claude_backup.*) and daemon snapshots (~/.claude_backups/)
   8. Dropbox sync directory (~/Dropbox/ClaudeCodeSync/) [optional]
   9. Built binary and cache (watch/target/)
 
@@ -512,6 +549,9 @@ main() {
         fi
     fi
     
+    # Before config.toml, which may move it, is removed
+    BACKUP_STORE=$(detect_backup_store)
+
     # Run uninstall steps
     stop_daemon
     remove_launchd_plist
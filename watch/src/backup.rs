@@ -0,0 +1,498 @@
+//! Content-addressed store of pre-sync backups
+//!
+//! A backup used to be a full copy of ~/.claude (including projects/, todos/,
+//! debug/ and file-history, often hundreds of MB) taken before every sync.
+//! Now it is a snapshot: a small manifest listing each synced file's checksum,
+//! stored under `<backups.dir>/.claude_backups/snapshots/`, with the contents
+//! kept once per checksum under `objects/`. Snapshots sharing a file share its
+//! blob, so they are cheap and can be kept much longer.
+//!
+//! `~/.claude_sync_last_backup` holds the path of the latest snapshot's
+//! manifest, which is what the next backup is compared against.
+
+use crate::config::{Config, RetentionConfig};
+use crate::filter::SyncSet;
+use crate::hashcache;
+use crate::remote::{file_mode, write_atomic};
+use anyhow::{bail, Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// A synced file as recorded in a snapshot
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnapshotFile {
+    /// SHA-256 of the content (the name of its blob)
+    pub sha256: String,
+    /// Size in bytes
+    pub size: u64,
+    /// Unix permission bits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+/// The synced files of ~/.claude at one point in time
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// Identifier (creation time, `YYYYmmdd_HHMMSS`)
+    pub id: String,
+    /// When the snapshot was taken
+    pub created: DateTime<Local>,
+    /// Files by path relative to ~/.claude
+    pub files: BTreeMap<String, SnapshotFile>,
+}
+
+/// Snapshot manifests plus the blobs they refer to
+pub struct BackupStore {
+    dir: PathBuf,
+    last_backup_file: PathBuf,
+}
+
+impl BackupStore {
+    /// Open the store in the configured backup directory
+    pub fn new(config: &Config) -> Self {
+        Self {
+            dir: config.backup_dir.join(".claude_backups"),
+            last_backup_file: config.claude_dir.with_file_name(".claude_sync_last_backup"),
+        }
+    }
+
+    /// Path of the blob for a checksum
+    fn object_path(&self, sha256: &str) -> PathBuf {
+        let (prefix, rest) = sha256.split_at(sha256.len().min(2));
+        self.dir.join("objects").join(prefix).join(rest)
+    }
+
+    /// Path of a snapshot's manifest
+    fn snapshot_path(&self, id: &str) -> PathBuf {
+        self.dir.join("snapshots").join(format!("{}.json", id))
+    }
+
+    /// Store content, returning its checksum
+    fn put(&self, content: &[u8]) -> Result<String> {
+        let sha256 = format!("{:x}", Sha256::digest(content));
+        let path = self.object_path(&sha256);
+        if !path.exists() {
+            write_atomic(&path, content, None).with_context(|| format!("Failed to write backup blob: {:?}", path))?;
+        }
+
+        Ok(sha256)
+    }
+
+    /// Record the synced files under `root`, storing their contents if
+    /// `store` is set
+    fn scan(&self, root: &Path, sync_set: &SyncSet, store: bool) -> Result<BTreeMap<String, SnapshotFile>> {
+        let mut files = BTreeMap::new();
+
+        for rel_path in sync_set.collect_files(root) {
+            let path = root.join(&rel_path);
+            let metadata = fs::metadata(&path).with_context(|| format!("Failed to back up {:?}", path))?;
+            let mut file = SnapshotFile {
+                sha256: hashcache::sha256_file(&path).with_context(|| format!("Failed to back up {:?}", path))?,
+                size: metadata.len(),
+                mode: file_mode(&metadata),
+            };
+
+            // Only read files whose content isn't stored yet
+            if store && !self.object_path(&file.sha256).exists() {
+                let content = fs::read(&path).with_context(|| format!("Failed to back up {:?}", path))?;
+                file.sha256 = self.put(&content)?;
+                file.size = content.len() as u64;
+            }
+
+            files.insert(rel_path, file);
+        }
+
+        Ok(files)
+    }
+
+    /// Snapshot the synced files under `root`
+    ///
+    /// If nothing changed since the latest snapshot, that one is returned
+    /// instead of taking a new one. The flag tells whether the snapshot is new.
+    pub fn snapshot(&self, root: &Path, sync_set: &SyncSet) -> Result<(Snapshot, bool)> {
+        let files = self.scan(root, sync_set, true)?;
+
+        if let Some(last) = self.last() {
+            if last.files == files {
+                log::info!("Synced files unchanged since last backup, skipping new backup");
+                return Ok((last, false));
+            }
+        }
+
+        let created = Local::now();
+        let base_id = created.format("%Y%m%d_%H%M%S").to_string();
+        let mut id = base_id.clone();
+        let mut n = 1;
+        while self.snapshot_path(&id).exists() {
+            n += 1;
+            id = format!("{}_{}", base_id, n);
+        }
+
+        let snapshot = Snapshot { id, created, files };
+        log::info!("Creating backup: {} ({} files)", snapshot.id, snapshot.files.len());
+        self.save(&snapshot)?;
+
+        // Save path for undo capability
+        self.set_last(&snapshot.id)?;
+
+        Ok((snapshot, true))
+    }
+
+    /// Write a snapshot's manifest
+    fn save(&self, snapshot: &Snapshot) -> Result<()> {
+        let path = self.snapshot_path(&snapshot.id);
+        write_atomic(&path, serde_json::to_string_pretty(snapshot)?.as_bytes(), None)
+            .with_context(|| format!("Failed to write backup manifest: {:?}", path))
+    }
+
+    /// Load a snapshot by id
+    pub fn load(&self, id: &str) -> Result<Snapshot> {
+        let path = self.snapshot_path(id);
+        let content = fs::read_to_string(&path).with_context(|| format!("Backup not found: {}", id))?;
+        serde_json::from_str(&content).with_context(|| format!("Failed to parse backup manifest: {:?}", path))
+    }
+
+    /// All snapshots, oldest first (unreadable manifests are skipped with a
+    /// warning)
+    pub fn list(&self) -> Result<Vec<Snapshot>> {
+        let (snapshots, unreadable) = self.read_manifests();
+        for e in unreadable {
+            log::warn!("Skipping backup: {:#}", e);
+        }
+        Ok(snapshots)
+    }
+
+    /// All snapshots, oldest first, and the errors of manifests that
+    /// couldn't be read
+    fn read_manifests(&self) -> (Vec<Snapshot>, Vec<anyhow::Error>) {
+        let mut snapshots = Vec::new();
+        let mut unreadable = Vec::new();
+        let Ok(entries) = fs::read_dir(self.dir.join("snapshots")) else {
+            return (snapshots, unreadable);
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let snapshot = fs::read_to_string(&path)
+                    .with_context(|| format!("Failed to read backup manifest: {:?}", path))
+                    .and_then(|content| {
+                        serde_json::from_str::<Snapshot>(&content)
+                            .with_context(|| format!("Failed to parse backup manifest: {:?}", path))
+                    });
+                match snapshot {
+                    Ok(snapshot) => snapshots.push(snapshot),
+                    Err(e) => unreadable.push(e),
+                }
+            }
+        }
+
+        snapshots.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.id.cmp(&b.id)));
+        (snapshots, unreadable)
+    }
+
+    /// Point `~/.claude_sync_last_backup` at a snapshot
+    pub fn set_last(&self, id: &str) -> Result<()> {
+        fs::write(&self.last_backup_file, self.snapshot_path(id).to_string_lossy().as_bytes())?;
+        Ok(())
+    }
+
+    /// Id of the snapshot `~/.claude_sync_last_backup` points to
+    pub fn last_id(&self) -> Option<String> {
+        let stored = fs::read_to_string(&self.last_backup_file).ok()?;
+        let id = Path::new(stored.trim()).file_stem()?.to_str()?.to_string();
+        if self.snapshot_path(&id) != Path::new(stored.trim()) {
+            // Left over from a directory backup
+            return None;
+        }
+        Some(id)
+    }
+
+    /// The snapshot `~/.claude_sync_last_backup` points to
+    fn last(&self) -> Option<Snapshot> {
+        self.load(&self.last_id()?).ok()
+    }
+
+    /// Add a file to a snapshot unless it already has one at that path
+    ///
+    /// Used to keep a copy of files deleted by a sync (e.g. a file deleted
+    /// locally is removed from the shared copy, and saved here first).
+    pub fn add_file(&self, snapshot: &mut Snapshot, rel_path: &str, content: &[u8], mode: Option<u32>) -> Result<()> {
+        if snapshot.files.contains_key(rel_path) {
+            return Ok(());
+        }
+
+        let sha256 = self.put(content)?;
+        snapshot.files.insert(
+            rel_path.to_string(),
+            SnapshotFile {
+                sha256,
+                size: content.len() as u64,
+                mode,
+            },
+        );
+
+        self.save(snapshot)
+    }
+
+    /// Record the synced files under `root` as they are now, without storing
+    /// anything
+    pub fn current(&self, root: &Path, sync_set: &SyncSet) -> Result<BTreeMap<String, SnapshotFile>> {
+        self.scan(root, sync_set, false)
+    }
+
+    /// Check if the synced files under `root` are exactly those of a snapshot
+    pub fn matches(&self, snapshot: &Snapshot, root: &Path, sync_set: &SyncSet) -> bool {
+        self.current(root, sync_set)
+            .is_ok_and(|files| files == snapshot.files)
+    }
+
+    /// Read a file's content from its blob
+    pub fn read(&self, file: &SnapshotFile) -> Result<Vec<u8>> {
+        let path = self.object_path(&file.sha256);
+        let content = fs::read(&path).with_context(|| format!("Backup blob is missing: {:?}", path))?;
+        if format!("{:x}", Sha256::digest(&content)) != file.sha256 {
+            bail!("Backup blob is corrupt: {:?}", path);
+        }
+        Ok(content)
+    }
+
+    /// Delete a snapshot, and the blobs no other snapshot refers to
+    pub fn remove(&self, id: &str) -> Result<()> {
+        self.remove_all(&[id])
+    }
+
+    /// Delete snapshots, and the blobs no remaining snapshot refers to
+    fn remove_all(&self, ids: &[&str]) -> Result<()> {
+        let last_id = self.last_id();
+        for id in ids {
+            let path = self.snapshot_path(id);
+            fs::remove_file(&path).with_context(|| format!("Failed to remove backup: {:?}", path))?;
+        }
+
+        // Clear last backup marker if it pointed to a removed backup
+        if last_id.is_some_and(|last_id| ids.contains(&last_id.as_str())) {
+            let _ = fs::remove_file(&self.last_backup_file);
+        }
+
+        match self.read_manifests() {
+            (snapshots, unreadable) if unreadable.is_empty() => self.collect_garbage(&snapshots),
+            (_, unreadable) => {
+                // Their blobs are unknown, so keep everything
+                log::warn!("Not removing unused backup blobs: {:#}", unreadable[0]);
+                Ok(())
+            }
+        }
+    }
+
+    /// Snapshots the retention policy doesn't keep, oldest first
+    pub fn expired(&self, policy: &RetentionConfig) -> Result<Vec<Snapshot>> {
+        let mut snapshots = self.list()?;
+        snapshots.reverse();
+        let protected = self.last_id();
+
+        // Newest first: the count rules pick snapshots to keep...
+        let mut keep = vec![false; snapshots.len()];
+        for (i, snapshot) in snapshots.iter().enumerate() {
+            keep[i] = i < policy.keep_last || protected.as_deref() == Some(snapshot.id.as_str());
+        }
+        for (count, format) in [
+            (policy.keep_hourly, "%Y%m%d%H"),
+            (policy.keep_daily, "%Y%m%d"),
+            (policy.keep_weekly, "%G%V"),
+        ] {
+            let mut buckets = HashSet::new();
+            for (i, snapshot) in snapshots.iter().enumerate() {
+                if buckets.len() >= count {
+                    break;
+                }
+                // The newest snapshot in each period
+                if buckets.insert(snapshot.created.format(format).to_string()) {
+                    keep[i] = true;
+                }
+            }
+        }
+
+        // ...then the oldest of those go until the rest fit the size limit
+        if policy.max_size_mb > 0 {
+            let limit = policy.max_size_mb * 1024 * 1024;
+            let mut counted = HashSet::new();
+            let mut total = 0;
+            let mut order: Vec<usize> = (0..snapshots.len()).filter(|&i| keep[i]).collect();
+            order.sort_by_key(|&i| protected.as_deref() != Some(snapshots[i].id.as_str()));
+
+            for i in order {
+                let added: u64 = snapshots[i]
+                    .files
+                    .values()
+                    .filter(|f| !counted.contains(f.sha256.as_str()))
+                    .map(|f| f.size)
+                    .sum();
+                let is_protected = protected.as_deref() == Some(snapshots[i].id.as_str());
+                if total + added > limit && !is_protected {
+                    keep[i] = false;
+                    continue;
+                }
+                total += added;
+                counted.extend(snapshots[i].files.values().map(|f| f.sha256.as_str()));
+            }
+        }
+
+        let mut expired: Vec<Snapshot> = snapshots
+            .iter()
+            .zip(&keep)
+            .filter(|(_, &keep)| !keep)
+            .map(|(snapshot, _)| snapshot.clone())
+            .collect();
+        expired.reverse();
+        Ok(expired)
+    }
+
+    /// Apply the retention policy, returning the snapshots removed
+    pub fn prune(&self, policy: &RetentionConfig) -> Result<Vec<Snapshot>> {
+        let expired = self.expired(policy)?;
+        if !expired.is_empty() {
+            let ids: Vec<&str> = expired.iter().map(|s| s.id.as_str()).collect();
+            self.remove_all(&ids)?;
+        }
+        Ok(expired)
+    }
+
+    /// Remove blobs that none of `snapshots` refers to
+    fn collect_garbage(&self, snapshots: &[Snapshot]) -> Result<()> {
+        let referenced: HashSet<&str> = snapshots
+            .iter()
+            .flat_map(|s| s.files.values().map(|f| f.sha256.as_str()))
+            .collect();
+
+        let Ok(prefixes) = fs::read_dir(self.dir.join("objects")) else {
+            return Ok(());
+        };
+
+        for prefix in prefixes.flatten() {
+            let prefix_name = prefix.file_name().to_string_lossy().to_string();
+            let Ok(blobs) = fs::read_dir(prefix.path()) else {
+                continue;
+            };
+
+            for blob in blobs.flatten() {
+                let sha256 = format!("{}{}", prefix_name, blob.file_name().to_string_lossy());
+                if !referenced.contains(sha256.as_str()) {
+                    fs::remove_file(blob.path()).ok();
+                }
+            }
+
+            // Only succeeds once the prefix directory is empty
+            fs::remove_dir(prefix.path()).ok();
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use chrono::TimeZone;
+
+    const MB: u64 = 1024 * 1024;
+
+    /// Snapshot `id` taken on January `day`, 2026 at `hour`:`minute`, with a
+    /// 1 MB blob of its own and a 1 MB blob all snapshots share
+    fn saved(store: &BackupStore, id: &str, (day, hour, minute): (u32, u32, u32)) {
+        let file = |sha256: &str| SnapshotFile { sha256: sha256.to_string(), size: MB, mode: None };
+        let snapshot = Snapshot {
+            id: id.to_string(),
+            created: Local.with_ymd_and_hms(2026, 1, day, hour, minute, 0).unwrap(),
+            files: BTreeMap::from([("own".to_string(), file(id)), ("shared".to_string(), file("shared"))]),
+        };
+        store.save(&snapshot).unwrap();
+    }
+
+    fn policy(keep_last: usize, hourly: usize, daily: usize, weekly: usize, max_size_mb: u64) -> RetentionConfig {
+        RetentionConfig {
+            keep_last,
+            keep_hourly: hourly,
+            keep_daily: daily,
+            keep_weekly: weekly,
+            max_size_mb,
+        }
+    }
+
+    #[test]
+    fn retention_policy() {
+        // Thursday of ISO week 1, then Monday and Tuesday of week 2
+        let snapshots = [
+            ("a", (1, 9, 0)),
+            ("b", (5, 9, 0)),
+            ("c", (5, 18, 0)),
+            ("d", (6, 9, 0)),
+            ("e", (6, 9, 30)),
+            ("f", (6, 10, 15)),
+        ];
+        let cases = [
+            (policy(0, 0, 0, 0, 0), None, "abcdef"),
+            (policy(2, 0, 0, 0, 0), None, "abcd"),
+            (policy(10, 0, 0, 0, 0), None, ""),
+            // The newest snapshot of each of the latest hours, days, weeks
+            (policy(0, 2, 0, 0, 0), None, "abcd"),
+            (policy(0, 3, 0, 0, 0), None, "abd"),
+            (policy(0, 0, 2, 0, 0), None, "abde"),
+            (policy(0, 0, 0, 2, 0), None, "bcde"),
+            (policy(1, 0, 3, 0, 0), None, "bde"),
+            // The last backup is kept whatever the rules say
+            (policy(0, 0, 0, 0, 0), Some("c"), "abdef"),
+            (policy(1, 0, 0, 0, 0), Some("a"), "bcde"),
+            // Oldest go first; shared blobs count once
+            (policy(10, 0, 0, 0, 3), None, "abcd"),
+            (policy(10, 0, 0, 0, 3), Some("a"), "bcde"),
+            (policy(10, 0, 0, 0, 1), Some("a"), "bcdef"),
+            (policy(10, 0, 0, 0, 100), None, ""),
+        ];
+
+        for (policy, last, expected) in cases {
+            let home = TempDir::new();
+            let config = Config::for_test(home.path(), "[paths]\nprovider = \"s3\"\n\n[s3]\nbucket = \"b\"\n");
+            let store = BackupStore::new(&config);
+            for (id, created) in snapshots {
+                saved(&store, id, created);
+            }
+            if let Some(id) = last {
+                store.set_last(id).unwrap();
+            }
+
+            let expired: String = store.expired(&policy).unwrap().into_iter().map(|s| s.id).collect();
+            assert_eq!(expired, expected, "{:?}, last backup {:?}", policy, last);
+        }
+    }
+
+    #[test]
+    fn unreadable_manifests_are_skipped() {
+        let home = TempDir::new();
+        let config = Config::for_test(home.path(), "[paths]\nprovider = \"s3\"\n\n[s3]\nbucket = \"b\"\n");
+        let store = BackupStore::new(&config);
+        let sync_set = SyncSet::new(&config);
+        home.write(".claude/settings.json", "{}");
+        let (first, _) = store.snapshot(&config.claude_dir, &sync_set).unwrap();
+        home.write(".claude/settings.json", "{\"model\": \"opus\"}");
+        let (second, _) = store.snapshot(&config.claude_dir, &sync_set).unwrap();
+
+        // Cut short while it was written, or edited by hand
+        let broken = store.snapshot_path(&first.id);
+        fs::write(&broken, "{\"id\": ").unwrap();
+
+        let ids: Vec<String> = store.list().unwrap().into_iter().map(|s| s.id).collect();
+        assert_eq!(ids, [second.id.as_str()]);
+
+        // The broken manifest's blobs may still be needed
+        store.remove(&second.id).unwrap();
+        assert!(store.list().unwrap().is_empty());
+        fs::write(&broken, serde_json::to_string(&first).unwrap()).unwrap();
+        assert_eq!(store.read(&first.files["settings.json"]).unwrap(), b"{}");
+    }
+}
@@ -0,0 +1,128 @@
+//! Local store of last-synced file contents, keyed by SHA-256
+//!
+//! `SyncState` only records checksums. Showing a diff against the baseline
+//! (or merging against it) needs the content too, so we keep a copy of each
+//! synced file's content under ~/.claude/.sync_baseline/. Blobs are shared
+//! between paths with identical content and pruned once nothing refers to them.
+
+use crate::state::SyncState;
+use anyhow::{Context, Result};
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+
+/// Files larger than this are not kept in the baseline store
+const BASELINE_MAX_SIZE: u64 = 1024 * 1024;
+
+/// Content-addressed store of baseline file contents
+pub struct BaselineStore {
+    dir: PathBuf,
+}
+
+impl BaselineStore {
+    /// Create a store rooted at `dir`
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir }
+    }
+
+    /// Path of the blob for a checksum
+    fn blob_path(&self, sha256: &str) -> PathBuf {
+        let (prefix, rest) = sha256.split_at(sha256.len().min(2));
+        self.dir.join(prefix).join(rest)
+    }
+
+    /// Check if content for a checksum is stored
+    pub fn contains(&self, sha256: &str) -> bool {
+        self.blob_path(sha256).exists()
+    }
+
+    /// Read stored content for a checksum
+    pub fn get(&self, sha256: &str) -> Option<Vec<u8>> {
+        fs::read(self.blob_path(sha256)).ok()
+    }
+
+    /// Store content, returning its checksum
+    pub fn put(&self, content: &[u8]) -> Result<String> {
+        let sha256 = format!("{:x}", Sha256::digest(content));
+        if (content.len() as u64) > BASELINE_MAX_SIZE || self.contains(&sha256) {
+            return Ok(sha256);
+        }
+
+        let path = self.blob_path(&sha256);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&path, content)
+            .with_context(|| format!("Failed to write baseline blob: {:?}", path))?;
+
+        Ok(sha256)
+    }
+
+    /// Store content if it matches the expected checksum
+    pub fn put_checked(&self, content: &[u8], expected_sha256: &str) -> Result<()> {
+        if self.contains(expected_sha256) || (content.len() as u64) > BASELINE_MAX_SIZE {
+            return Ok(());
+        }
+
+        if format!("{:x}", Sha256::digest(content)) != expected_sha256 {
+            // File changed since it was hashed; the next sync will catch up
+            return Ok(());
+        }
+
+        self.put(content).map(|_| ())
+    }
+
+    /// Make sure every baseline in `state` has its content stored, then drop
+    /// blobs nothing refers to anymore
+    ///
+    /// `read_shared` returns the shared (sync directory) form of a synced
+    /// file, which is what baseline checksums describe.
+    pub fn refresh(&self, state: &SyncState, read_shared: impl Fn(&str) -> Option<Vec<u8>>) -> Result<()> {
+        for (rel_path, file_state) in &state.files {
+            if self.contains(&file_state.sha256) {
+                continue;
+            }
+            if let Some(content) = read_shared(rel_path) {
+                if let Err(e) = self.put_checked(&content, &file_state.sha256) {
+                    log::debug!("Could not store baseline for {}: {}", rel_path, e);
+                }
+            }
+        }
+
+        let referenced: HashSet<&str> = state
+            .files
+            .values()
+            .map(|f| f.sha256.as_str())
+            .chain(state.conflicts.values().flat_map(|c| c.checksums()))
+            .collect();
+
+        self.prune(&referenced)
+    }
+
+    /// Remove blobs that are not referenced
+    fn prune(&self, referenced: &HashSet<&str>) -> Result<()> {
+        let Ok(prefixes) = fs::read_dir(&self.dir) else {
+            return Ok(());
+        };
+
+        for prefix in prefixes.flatten() {
+            let prefix_name = prefix.file_name().to_string_lossy().to_string();
+            let Ok(blobs) = fs::read_dir(prefix.path()) else {
+                continue;
+            };
+
+            for blob in blobs.flatten() {
+                let sha256 = format!("{}{}", prefix_name, blob.file_name().to_string_lossy());
+                if !referenced.contains(sha256.as_str()) {
+                    fs::remove_file(blob.path()).ok();
+                }
+            }
+
+            // Only succeeds once the prefix directory is empty
+            fs::remove_dir(prefix.path()).ok();
+        }
+
+        Ok(())
+    }
+}
@@ -1,11 +1,88 @@
 //! Configuration loading for claude-sync-watch
+//!
+//! Configuration lives in ~/.config/claude-sync/config.toml:
+//!
+//! ```toml
+//! [paths]
+//! provider = "dropbox"
+//! base = "~/Dropbox"
+//! folder = "ClaudeCodeSync"
+//!
+//! [timing]
+//! debounce_secs = 3.0
+//! max_batch_secs = 10.0
+//! full_sync_secs = 3600.0
+//!
+//! [conflicts]
+//! strategy = "newest"
+//! json_merge_files = ["settings.json", "mcp.json"]
+//!
+//! [sync]
+//! files = ["settings.json", "mcp.json", "CLAUDE.md"]
+//! dirs = ["skills", "plugins"]
+//! include = []
+//! exclude = []
+//! path_rewrite_extensions = ["json", "md", "sh"]
+//! max_delete_percent = 50
+//!
+//! [git]
+//! remote = "origin"
+//! url = "git@github.com:me/claude-config.git"
+//! branch = "main"
+//! poll_secs = 60.0
+//!
+//! [s3]
+//! endpoint = "http://localhost:9000"
+//! bucket = "claude-sync"
+//! region = "us-east-1"
+//! path_style = true
+//! poll_secs = 30.0
+//!
+//! [webdav]
+//! url = "https://cloud.example.com/remote.php/dav/files/me"
+//! username = "me"
+//! poll_secs = 30.0
+//!
+//! [sftp]
+//! host = "homeserver"
+//! path = "~"
+//! poll_secs = 30.0
+//!
+//! [encryption]
+//! enabled = false
+//! key = "keychain"
+//! key_file = "~/.config/claude-sync/encryption.key"
+//! encrypt_names = false
+//!
+//! [secrets]
+//! mode = "warn"
+//! store = "~/.config/claude-sync/secrets.json"
+//!
+//! [backups]
+//! dir = "~"
+//! keep_last = 10
+//! keep_hourly = 24
+//! keep_daily = 7
+//! keep_weekly = 4
+//! max_size_mb = 100
+//!
+//! [logging]
+//! level = "info"
+//! ```
+//!
+//! Every key is optional. Unknown keys and values of the wrong type are
+//! errors. If only the legacy bash-style ~/.claude_sync_config exists, it is
+//! converted to config.toml once.
 
-use anyhow::{Context, Result};
+use crate::target::{ProviderKind, SyncTarget};
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Conflict resolution strategy
-#[derive(Debug, Clone, Copy, Default)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum ConflictStrategy {
     /// Use the newest file (by mtime)
     #[default]
@@ -14,126 +91,588 @@ pub enum ConflictStrategy {
     Local,
     /// Prefer remote over local
     Remote,
+    /// Use the newest file, but save the other side as a conflict copy
+    #[serde(alias = "keep-both", alias = "keepboth")]
+    KeepBoth,
+}
+
+/// Log level names accepted in the config file
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum LogLevel {
+    Debug,
+    #[default]
+    Info,
+    Warn,
+    Error,
+}
+
+impl From<LogLevel> for log::Level {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Debug => log::Level::Debug,
+            LogLevel::Info => log::Level::Info,
+            LogLevel::Warn => log::Level::Warn,
+            LogLevel::Error => log::Level::Error,
+        }
+    }
 }
 
 /// Configuration for the sync daemon
 #[derive(Debug, Clone)]
 pub struct Config {
-    /// Base Dropbox directory (e.g., ~/Dropbox)
-    pub dropbox_base: PathBuf,
-    /// Claude sync directory in Dropbox (e.g., ~/Dropbox/ClaudeCodeSync)
-    pub dropbox_claude_dir: PathBuf,
+    /// Cloud-synced folder holding the shared copy
+    pub target: SyncTarget,
+    /// Name of the sync directory (the object key prefix for S3, a
+    /// collection under the WebDAV URL, a directory under the SSH path)
+    pub folder: String,
     /// Local Claude config directory (~/.claude)
     pub claude_dir: PathBuf,
     /// Debounce time in seconds before triggering sync
     pub debounce_secs: f64,
     /// Maximum batch time in seconds
     pub max_batch_secs: f64,
+    /// Seconds between full scans of both sides (other syncs only look at
+    /// the paths the watchers reported)
+    pub full_sync_secs: f64,
     /// Conflict resolution strategy
     pub conflict_strategy: ConflictStrategy,
-    /// Log level (reserved for future use)
-    #[allow(dead_code)]
+    /// Log level used when --log-level is not given
     pub log_level: log::Level,
     /// Files to sync
     pub sync_files: Vec<String>,
     /// Directories to sync
     pub sync_dirs: Vec<String>,
+    /// If not empty, only files matching these gitignore-style patterns are synced
+    pub include: Vec<String>,
+    /// Gitignore-style patterns of files and directories not to sync
+    pub exclude: Vec<String>,
+    /// JSON files merged key by key when both sides changed
+    pub json_merge_files: Vec<String>,
+    /// Extensions of text files whose home directory paths are rewritten to
+    /// `${HOME}` in the shared copy (empty disables rewriting)
+    pub path_rewrite_extensions: Vec<String>,
+    /// Largest share of tracked files (in percent) one sync may delete
+    pub max_delete_percent: u32,
+    /// Apply deletions even if they look like a missing or replaced side
+    /// (`--allow-mass-delete`, not read from the config file)
+    pub allow_mass_delete: bool,
+    /// Directory holding the `.claude_backups` snapshot store
+    pub backup_dir: PathBuf,
+    /// Which backup snapshots to keep
+    pub retention: RetentionConfig,
+    /// Git backend settings (used with `provider = "git"`)
+    pub git: GitConfig,
+    /// Object storage settings (used with `provider = "s3"`)
+    pub s3: S3Config,
+    /// WebDAV server settings (used with `provider = "webdav"`)
+    pub webdav: WebdavConfig,
+    /// SSH server settings (used with `provider = "sftp"`)
+    pub sftp: SftpConfig,
+    /// Client-side encryption of the shared copy
+    pub encryption: EncryptionConfig,
+    /// What to do about secrets in files being pushed
+    pub secrets: SecretsConfig,
+}
+
+/// Which backup snapshots to keep
+///
+/// A snapshot is kept if any of the count rules keeps it; the oldest of those
+/// are then dropped until the total size fits `max_size_mb`. The snapshot
+/// `~/.claude_sync_last_backup` points to is always kept.
+#[derive(Debug, Clone)]
+pub struct RetentionConfig {
+    /// Keep the most recent snapshots
+    pub keep_last: usize,
+    /// Keep the newest snapshot in each of the most recent hours that have one
+    pub keep_hourly: usize,
+    /// Same, per day
+    pub keep_daily: usize,
+    /// Same, per week
+    pub keep_weekly: usize,
+    /// Limit on the size of the kept snapshots' contents (0 for no limit)
+    pub max_size_mb: u64,
+}
+
+/// Git backend settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GitConfig {
+    /// Remote to pull from and push to
+    pub remote: String,
+    /// Remote URL, used to clone the work tree or add the remote if missing
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    /// Branch shared by all machines
+    pub branch: String,
+    /// How often the daemon checks the remote for new commits
+    pub poll_secs: f64,
+}
+
+impl Default for GitConfig {
+    fn default() -> Self {
+        Self {
+            remote: "origin".to_string(),
+            url: None,
+            branch: "main".to_string(),
+            poll_secs: 60.0,
+        }
+    }
+}
+
+/// S3-compatible object storage settings
+///
+/// Objects are stored under `<bucket>/<paths.folder>/`. Credentials not set
+/// here are read from `AWS_ACCESS_KEY_ID`, `AWS_SECRET_ACCESS_KEY` and
+/// `AWS_SESSION_TOKEN`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct S3Config {
+    /// Service URL (defaults to AWS S3 in `region`)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    pub bucket: String,
+    pub region: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub access_key_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub secret_access_key: Option<String>,
+    /// Address the bucket as `<endpoint>/<bucket>` rather than
+    /// `<bucket>.<endpoint host>` (needed by MinIO and most self-hosted stores)
+    pub path_style: bool,
+    /// How often the daemon lists the bucket for changes
+    pub poll_secs: f64,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        Self {
+            endpoint: None,
+            bucket: String::new(),
+            region: "us-east-1".to_string(),
+            access_key_id: None,
+            secret_access_key: None,
+            path_style: true,
+            poll_secs: 30.0,
+        }
+    }
+}
+
+/// WebDAV server settings
+///
+/// Files are stored under `<url>/<paths.folder>/`. A password not set here is
+/// read from `CLAUDE_SYNC_WEBDAV_PASSWORD`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WebdavConfig {
+    /// Collection the sync directory is created in (for Nextcloud,
+    /// `https://<host>/remote.php/dav/files/<user>`)
+    pub url: String,
+    /// User for HTTP Basic authentication (none if not set)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub username: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// How often the daemon checks the server for changes
+    pub poll_secs: f64,
+}
+
+impl Default for WebdavConfig {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            username: None,
+            password: None,
+            poll_secs: 30.0,
+        }
+    }
+}
+
+/// SSH server settings
+///
+/// Files are stored under `<path>/<paths.folder>/` on the server. ssh reads
+/// the user, key and other options for the host from ~/.ssh/config.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SftpConfig {
+    /// Host as given to ssh: a `Host` from ~/.ssh/config or `[user@]hostname`
+    pub host: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    /// Directory holding the sync directory (relative paths and `~` start at
+    /// the home directory)
+    pub path: String,
+    /// How often the daemon checks the server for changes
+    pub poll_secs: f64,
+}
+
+impl Default for SftpConfig {
+    fn default() -> Self {
+        Self {
+            host: String::new(),
+            port: None,
+            path: "~".to_string(),
+            poll_secs: 30.0,
+        }
+    }
+}
+
+/// Where the encryption key is kept on this machine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum KeySource {
+    /// macOS keychain
+    Keychain,
+    /// Key file readable only by the user
+    File,
+}
+
+impl Default for KeySource {
+    fn default() -> Self {
+        if cfg!(target_os = "macos") {
+            KeySource::Keychain
+        } else {
+            KeySource::File
+        }
+    }
+}
+
+/// Client-side encryption settings
+///
+/// All machines sharing a sync directory must use the same setting. The key
+/// is derived from a passphrase by `claude-sync-watch init-encryption`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EncryptionConfig {
+    pub enabled: bool,
+    pub key: KeySource,
+    /// Used with `key = "file"`
+    pub key_file: String,
+    /// Store files under names derived from their paths, so the file layout
+    /// isn't visible in the shared copy either
+    pub encrypt_names: bool,
+}
+
+impl Default for EncryptionConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            key: KeySource::default(),
+            key_file: "~/.config/claude-sync/encryption.key".to_string(),
+            encrypt_names: false,
+        }
+    }
+}
+
+/// What to do when a file being pushed contains secrets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SecretsMode {
+    /// Don't scan
+    Off,
+    /// Push anyway, with a warning
+    #[default]
+    Warn,
+    /// Don't push the file
+    Block,
+    /// Push `${secret:<name>}` references instead, keeping the values in the
+    /// local secrets store
+    Redact,
+}
+
+/// Secret scanning settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SecretsConfig {
+    pub mode: SecretsMode,
+    /// Local file holding redacted values (name -> value)
+    pub store: String,
+}
+
+impl Default for SecretsConfig {
+    fn default() -> Self {
+        Self {
+            mode: SecretsMode::default(),
+            store: "~/.config/claude-sync/secrets.json".to_string(),
+        }
+    }
+}
+
+/// On-disk layout of config.toml
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FileConfig {
+    paths: PathsSection,
+    timing: TimingSection,
+    conflicts: ConflictsSection,
+    sync: SyncSection,
+    git: GitConfig,
+    s3: S3Config,
+    webdav: WebdavConfig,
+    sftp: SftpConfig,
+    encryption: EncryptionConfig,
+    secrets: SecretsConfig,
+    backups: BackupsSection,
+    logging: LoggingSection,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct PathsSection {
+    /// Detected from the folders present if not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    provider: Option<ProviderKind>,
+    /// Provider root folder, detected from the provider's usual locations if
+    /// not set
+    #[serde(alias = "dropbox_base", skip_serializing_if = "Option::is_none")]
+    base: Option<String>,
+    /// Sync directory inside the provider folder
+    folder: String,
+}
+
+impl Default for PathsSection {
+    fn default() -> Self {
+        Self {
+            provider: None,
+            base: None,
+            folder: "ClaudeCodeSync".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct TimingSection {
+    debounce_secs: f64,
+    max_batch_secs: f64,
+    full_sync_secs: f64,
+}
+
+impl Default for TimingSection {
+    fn default() -> Self {
+        Self {
+            debounce_secs: 3.0,
+            max_batch_secs: 10.0,
+            full_sync_secs: 3600.0,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConflictsSection {
+    strategy: ConflictStrategy,
+    json_merge_files: Vec<String>,
+}
+
+impl Default for ConflictsSection {
+    fn default() -> Self {
+        Self {
+            strategy: ConflictStrategy::Newest,
+            json_merge_files: strings(&["settings.json", "mcp.json"]),
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SyncSection {
+    files: Vec<String>,
+    dirs: Vec<String>,
+    include: Vec<String>,
+    exclude: Vec<String>,
+    path_rewrite_extensions: Vec<String>,
+    max_delete_percent: u32,
+}
+
+impl Default for SyncSection {
+    fn default() -> Self {
+        Self {
+            files: strings(&["settings.json", "mcp.json", "CLAUDE.md"]),
+            dirs: strings(&["skills", "plugins"]),
+            include: Vec::new(),
+            exclude: Vec::new(),
+            path_rewrite_extensions: strings(&[
+                "json", "md", "sh", "py", "js", "ts", "txt", "toml", "yaml", "yml",
+            ]),
+            max_delete_percent: 50,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct BackupsSection {
+    dir: String,
+    keep_last: usize,
+    keep_hourly: usize,
+    keep_daily: usize,
+    keep_weekly: usize,
+    max_size_mb: u64,
+}
+
+impl Default for BackupsSection {
+    fn default() -> Self {
+        Self {
+            dir: "~".to_string(),
+            keep_last: 10,
+            keep_hourly: 24,
+            keep_daily: 7,
+            keep_weekly: 4,
+            max_size_mb: 100,
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct LoggingSection {
+    level: LogLevel,
 }
 
 impl Config {
-    /// Load configuration from ~/.claude_sync_config
+    /// Path of the config file (~/.config/claude-sync/config.toml)
+    pub fn config_path() -> Result<PathBuf> {
+        let home = dirs::home_dir().context("Could not determine home directory")?;
+        Ok(home.join(".config").join("claude-sync").join("config.toml"))
+    }
+
+    /// Load configuration from config.toml, migrating ~/.claude_sync_config
+    /// on first run
     pub fn load() -> Result<Self> {
         let home = dirs::home_dir().context("Could not determine home directory")?;
-        let config_path = home.join(".claude_sync_config");
+        let config_path = Self::config_path()?;
+        let legacy_path = home.join(".claude_sync_config");
 
-        let mut dropbox_base: Option<PathBuf> = None;
-        let mut debounce_secs = 3.0;
-        let mut max_batch_secs = 10.0;
-        let mut conflict_strategy = ConflictStrategy::Newest;
-        let mut log_level = log::Level::Info;
+        let file_config = if config_path.exists() {
+            if is_newer(&legacy_path, &config_path) {
+                log::warn!(
+                    "{:?} changed after it was migrated and is ignored; edit {:?} instead",
+                    legacy_path,
+                    config_path
+                );
+            }
 
-        // Parse bash-style KEY="value" config file
-        if config_path.exists() {
             let content = fs::read_to_string(&config_path)
                 .with_context(|| format!("Failed to read config file: {:?}", config_path))?;
+            toml::from_str(&content)
+                .with_context(|| format!("Invalid config file {:?}", config_path))?
+        } else if legacy_path.exists() {
+            return migrate_legacy(&legacy_path, &config_path, &home);
+        } else {
+            FileConfig::default()
+        };
 
-            for line in content.lines() {
-                let line = line.trim();
-                if line.is_empty() || line.starts_with('#') {
-                    continue;
-                }
+        Self::from_file_config(file_config, &home)
+            .with_context(|| format!("Invalid config file {:?}", config_path))
+    }
 
-                if let Some((key, value)) = line.split_once('=') {
-                    let key = key.trim();
-                    // Remove surrounding quotes
-                    let value = value.trim().trim_matches('"').trim_matches('\'');
-
-                    match key {
-                        "DROPBOX_BASE" => {
-                            let expanded = shellexpand::tilde(value);
-                            dropbox_base = Some(PathBuf::from(expanded.as_ref()));
-                        }
-                        "DEBOUNCE_SECS" => {
-                            if let Ok(v) = value.parse() {
-                                debounce_secs = v;
-                            }
-                        }
-                        "MAX_BATCH_SECS" => {
-                            if let Ok(v) = value.parse() {
-                                max_batch_secs = v;
-                            }
-                        }
-                        "CONFLICT_STRATEGY" => match value.to_lowercase().as_str() {
-                            "local" => conflict_strategy = ConflictStrategy::Local,
-                            "remote" => conflict_strategy = ConflictStrategy::Remote,
-                            _ => conflict_strategy = ConflictStrategy::Newest,
-                        },
-                        "LOG_LEVEL" => match value.to_lowercase().as_str() {
-                            "debug" => log_level = log::Level::Debug,
-                            "warn" => log_level = log::Level::Warn,
-                            "error" => log_level = log::Level::Error,
-                            _ => log_level = log::Level::Info,
-                        },
-                        _ => {}
-                    }
-                }
+    /// Check values and resolve paths
+    fn from_file_config(file: FileConfig, home: &Path) -> Result<Self> {
+        for (key, value) in [
+            ("timing.debounce_secs", file.timing.debounce_secs),
+            ("timing.max_batch_secs", file.timing.max_batch_secs),
+            ("timing.full_sync_secs", file.timing.full_sync_secs),
+            ("git.poll_secs", file.git.poll_secs),
+            ("s3.poll_secs", file.s3.poll_secs),
+            ("webdav.poll_secs", file.webdav.poll_secs),
+            ("sftp.poll_secs", file.sftp.poll_secs),
+        ] {
+            if !value.is_finite() || value <= 0.0 {
+                bail!("{} must be a positive number of seconds, got {}", key, value);
             }
         }
 
-        // Try to detect Dropbox location if not configured
-        let dropbox_base = dropbox_base.or_else(|| {
-            let candidates = [
-                home.join("Dropbox"),
-                home.join("Library/CloudStorage/Dropbox"),
-                PathBuf::from("/Users/Shared/Dropbox"),
-            ];
-            candidates.into_iter().find(|p| p.exists())
-        });
-
-        let dropbox_base =
-            dropbox_base.context("Dropbox location not configured. Run claude-sync-setup.sh --config first.")?;
+        for dir in &file.sync.dirs {
+            if dir.trim_matches('/').is_empty() {
+                bail!("sync.dirs must not contain an empty directory name");
+            }
+        }
+        if file.sync.max_delete_percent > 100 {
+            bail!("sync.max_delete_percent must be at most 100, got {}", file.sync.max_delete_percent);
+        }
 
-        if !dropbox_base.exists() {
-            anyhow::bail!("Dropbox directory does not exist: {:?}", dropbox_base);
+        let folder = file.paths.folder.trim_matches('/');
+        if folder.is_empty() {
+            bail!("paths.folder must not be empty");
         }
 
-        let dropbox_claude_dir = dropbox_base.join("ClaudeCodeSync");
+        let base = match &file.paths.base {
+            Some(base) => Some(PathBuf::from(shellexpand::tilde("paths.base", base, home)?.as_ref())),
+            None => None,
+        };
+        let target = SyncTarget::resolve(file.paths.provider, base, folder, home)?;
+        if target.kind == ProviderKind::S3 && file.s3.bucket.is_empty() {
+            bail!("s3.bucket must be set for provider \"s3\"");
+        }
+        if target.kind == ProviderKind::Webdav && file.webdav.url.is_empty() {
+            bail!("webdav.url must be set for provider \"webdav\"");
+        }
+        if target.kind == ProviderKind::Sftp && (file.sftp.host.is_empty() || file.sftp.host.starts_with('-')) {
+            bail!("sftp.host must be set to a host name for provider \"sftp\"");
+        }
+        if file.encryption.enabled && file.encryption.key == KeySource::Keychain && !cfg!(target_os = "macos") {
+            bail!("encryption.key = \"keychain\" needs macOS; use \"file\"");
+        }
         let claude_dir = home.join(".claude");
+        let backup_dir = PathBuf::from(shellexpand::tilde("backups.dir", &file.backups.dir, home)?.as_ref());
+        let git_url = match &file.git.url {
+            Some(url) => Some(shellexpand::tilde("git.url", url, home)?.to_string()),
+            None => None,
+        };
+        let key_file = shellexpand::tilde("encryption.key_file", &file.encryption.key_file, home)?.to_string();
+        let secrets_store = shellexpand::tilde("secrets.store", &file.secrets.store, home)?.to_string();
 
         Ok(Config {
-            dropbox_base,
-            dropbox_claude_dir,
+            target,
+            folder: folder.to_string(),
             claude_dir,
-            debounce_secs,
-            max_batch_secs,
-            conflict_strategy,
-            log_level,
-            sync_files: vec![
-                "settings.json".to_string(),
-                "mcp.json".to_string(),
-                "CLAUDE.md".to_string(),
-            ],
-            sync_dirs: vec!["skills".to_string(), "plugins".to_string()],
+            debounce_secs: file.timing.debounce_secs,
+            max_batch_secs: file.timing.max_batch_secs,
+            full_sync_secs: file.timing.full_sync_secs,
+            conflict_strategy: file.conflicts.strategy,
+            log_level: file.logging.level.into(),
+            sync_files: file.sync.files,
+            sync_dirs: file
+                .sync
+                .dirs
+                .iter()
+                .map(|d| d.trim_matches('/').to_string())
+                .collect(),
+            include: file.sync.include,
+            exclude: file.sync.exclude,
+            json_merge_files: file.conflicts.json_merge_files,
+            path_rewrite_extensions: file
+                .sync
+                .path_rewrite_extensions
+                .iter()
+                .map(|e| e.trim_start_matches('.').to_string())
+                .collect(),
+            max_delete_percent: file.sync.max_delete_percent,
+            allow_mass_delete: false,
+            backup_dir,
+            retention: RetentionConfig {
+                keep_last: file.backups.keep_last,
+                keep_hourly: file.backups.keep_hourly,
+                keep_daily: file.backups.keep_daily,
+                keep_weekly: file.backups.keep_weekly,
+                max_size_mb: file.backups.max_size_mb,
+            },
+            git: GitConfig {
+                url: git_url,
+                ..file.git
+            },
+            s3: file.s3,
+            webdav: file.webdav,
+            sftp: file.sftp,
+            encryption: EncryptionConfig {
+                key_file,
+                ..file.encryption
+            },
+            secrets: SecretsConfig {
+                store: secrets_store,
+                ..file.secrets
+            },
         })
     }
 
@@ -149,24 +688,334 @@ impl Config {
         self.claude_dir.join(".sync_state.json")
     }
 
+    /// Get path for the local baseline content store (stored in ~/.claude, not Dropbox)
+    pub fn local_baseline_dir(&self) -> PathBuf {
+        self.claude_dir.join(".sync_baseline")
+    }
+
+    /// Get path for this machine's override file (stored in ~/.claude, not Dropbox)
+    pub fn local_overrides_path(&self) -> PathBuf {
+        self.claude_dir
+            .join(".sync_overrides")
+            .join(format!("{}.json", Self::machine_id()))
+    }
+
+    /// Get path for the cached S3 object metadata (stored in ~/.claude, not
+    /// in the bucket)
+    pub fn local_s3_index_path(&self) -> PathBuf {
+        self.claude_dir.join(".sync_s3_index.json")
+    }
+
+    /// Get path for the cached WebDAV file versions (stored in ~/.claude, not
+    /// on the server)
+    pub fn local_webdav_index_path(&self) -> PathBuf {
+        self.claude_dir.join(".sync_webdav_index.json")
+    }
+
+    /// Get path for the cached checksums of files on the SSH server (stored
+    /// in ~/.claude, not on the server)
+    pub fn local_sftp_index_path(&self) -> PathBuf {
+        self.claude_dir.join(".sync_sftp_index.json")
+    }
+
+    /// Get path for the checksums and paths of decrypted files (stored in
+    /// ~/.claude, not Dropbox)
+    pub fn local_encryption_index_path(&self) -> PathBuf {
+        self.claude_dir.join(".sync_encryption_index.json")
+    }
+
+    /// Get path for the cached checksums of local files (stored in ~/.claude,
+    /// not Dropbox)
+    pub fn local_hash_cache_path(&self) -> PathBuf {
+        self.claude_dir.join(".sync_hash_cache.json")
+    }
+
+    /// How often to check a remote that can't be watched for changes (None
+    /// if it can be watched)
+    pub fn poll_secs(&self) -> Option<f64> {
+        match self.target.kind {
+            ProviderKind::Git => Some(self.git.poll_secs),
+            ProviderKind::S3 => Some(self.s3.poll_secs),
+            ProviderKind::Webdav => Some(self.webdav.poll_secs),
+            ProviderKind::Sftp => Some(self.sftp.poll_secs),
+            _ => None,
+        }
+    }
+
     /// Get path for local process lock file (stored in ~/.claude, not Dropbox)
     pub fn local_lock_path(&self) -> PathBuf {
         self.claude_dir.join(".sync.pid")
     }
+
+    /// Get path for the lock held around each sync (stored in ~/.claude, not
+    /// Dropbox)
+    pub fn local_sync_lock_path(&self) -> PathBuf {
+        self.claude_dir.join(".sync.lock")
+    }
+}
+
+/// Convert the legacy bash-style KEY="value" config file to config.toml
+///
+/// Nothing is written unless every value converts and checks out, so a typo
+/// is reported rather than replaced by a default for good. The legacy file is
+/// left in place for the shell helpers, which still read DROPBOX_BASE from it.
+fn migrate_legacy(legacy_path: &Path, config_path: &Path, home: &Path) -> Result<Config> {
+    let content = fs::read_to_string(legacy_path)
+        .with_context(|| format!("Failed to read config file: {:?}", legacy_path))?;
+    let cannot_migrate = || format!("Cannot migrate {:?} to {:?}", legacy_path, config_path);
+    let file_config = parse_legacy(&content).with_context(cannot_migrate)?;
+
+    let toml_text = toml::to_string_pretty(&file_config).context("Failed to serialize config")?;
+    let config = Config::from_file_config(file_config, home).with_context(cannot_migrate)?;
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create config directory: {:?}", parent))?;
+    }
+    fs::write(
+        config_path,
+        format!(
+            "# claude-sync configuration (migrated from {})\n\n{}",
+            legacy_path.display(),
+            toml_text
+        ),
+    )
+    .with_context(|| format!("Failed to write config file: {:?}", config_path))?;
+
+    log::info!("Migrated {:?} to {:?}", legacy_path, config_path);
+    Ok(config)
+}
+
+/// Parse the legacy bash-style config
+///
+/// Values that can't be carried over are errors; unknown keys and lines that
+/// aren't assignments are skipped with a warning.
+fn parse_legacy(content: &str) -> Result<FileConfig> {
+    let mut file_config = FileConfig::default();
+
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            log::warn!("Legacy config: ignoring line {:?}", line);
+            continue;
+        };
+        let key = key.trim().trim_start_matches("export ").trim();
+        // Remove surrounding quotes
+        let value = value.trim().trim_matches('"').trim_matches('\'');
+
+        match key {
+            "DROPBOX_BASE" => {
+                file_config.paths.provider = Some(ProviderKind::Dropbox);
+                file_config.paths.base = Some(value.to_string());
+            }
+            "DEBOUNCE_SECS" | "MAX_BATCH_SECS" => match value.parse() {
+                Ok(v) if key == "DEBOUNCE_SECS" => file_config.timing.debounce_secs = v,
+                Ok(v) => file_config.timing.max_batch_secs = v,
+                Err(_) => bail!("{} is not a number: {:?}", key, value),
+            },
+            "CONFLICT_STRATEGY" => match value.to_lowercase().as_str() {
+                "newest" => file_config.conflicts.strategy = ConflictStrategy::Newest,
+                "local" => file_config.conflicts.strategy = ConflictStrategy::Local,
+                "remote" => file_config.conflicts.strategy = ConflictStrategy::Remote,
+                "keep_both" | "keep-both" | "keepboth" => {
+                    file_config.conflicts.strategy = ConflictStrategy::KeepBoth
+                }
+                _ => bail!(
+                    "Unknown CONFLICT_STRATEGY {:?} (use newest, local, remote or keep_both)",
+                    value
+                ),
+            },
+            "SYNC_FILES" => file_config.sync.files = split_list(value),
+            "SYNC_DIRS" => file_config.sync.dirs = split_list(value),
+            "INCLUDE" => file_config.sync.include = split_list(value),
+            "EXCLUDE" => file_config.sync.exclude = split_list(value),
+            "PATH_REWRITE_EXTENSIONS" => file_config.sync.path_rewrite_extensions = split_list(value),
+            "LOG_LEVEL" => match value.to_lowercase().as_str() {
+                "debug" => file_config.logging.level = LogLevel::Debug,
+                "info" => file_config.logging.level = LogLevel::Info,
+                "warn" => file_config.logging.level = LogLevel::Warn,
+                "error" => file_config.logging.level = LogLevel::Error,
+                _ => bail!("Unknown LOG_LEVEL {:?} (use debug, info, warn or error)", value),
+            },
+            _ => log::warn!("Legacy config: ignoring unknown key {}", key),
+        }
+    }
+
+    Ok(file_config)
+}
+
+/// Check if `path` exists and was modified after `than`
+fn is_newer(path: &Path, than: &Path) -> bool {
+    let mtime = |p: &Path| fs::metadata(p).and_then(|m| m.modified()).ok();
+    matches!((mtime(path), mtime(than)), (Some(a), Some(b)) if a > b)
+}
+
+/// Split a comma- or whitespace-separated config value into items
+fn split_list(value: &str) -> Vec<String> {
+    value
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|item| !item.is_empty())
+        .map(|item| item.to_string())
+        .collect()
+}
+
+/// Owned copies of string literals
+fn strings(items: &[&str]) -> Vec<String> {
+    items.iter().map(|s| s.to_string()).collect()
+}
+
+#[cfg(test)]
+impl Config {
+    /// Configuration read from `toml` for a test home directory, with
+    /// backups and the secrets store kept under it
+    pub fn for_test(home: &Path, toml: &str) -> Self {
+        let mut config = Self::from_file_config(toml::from_str(toml).unwrap(), home).unwrap();
+        config.backup_dir = home.to_path_buf();
+        config.secrets.store = home.join(".secrets.json").to_string_lossy().to_string();
+        config
+    }
 }
 
 // Simple tilde expansion since we don't want to add another dependency
 mod shellexpand {
+    use anyhow::{bail, Result};
     use std::borrow::Cow;
+    use std::path::Path;
 
-    pub fn tilde(path: &str) -> Cow<'_, str> {
-        if path.starts_with('~') {
-            if let Some(home) = dirs::home_dir() {
-                let rest = path.strip_prefix('~').unwrap_or("");
-                let rest = rest.strip_prefix('/').unwrap_or(rest);
-                return Cow::Owned(home.join(rest).to_string_lossy().to_string());
-            }
+    /// Expand a leading `~` in the setting `key` to `home`; other users' home
+    /// directories (`~name`) are not supported
+    pub fn tilde<'a>(key: &str, path: &'a str, home: &Path) -> Result<Cow<'a, str>> {
+        let Some(rest) = path.strip_prefix('~') else {
+            return Ok(Cow::Borrowed(path));
+        };
+        if rest.is_empty() {
+            return Ok(Cow::Owned(home.to_string_lossy().to_string()));
+        }
+        let Some(rest) = rest.strip_prefix('/') else {
+            bail!("{} = {:?}: ~user paths are not supported, give the full path", key, path);
+        };
+        Ok(Cow::Owned(home.join(rest).to_string_lossy().to_string()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    const S3: &str = "[paths]\nprovider = \"s3\"\n\n[s3]\nbucket = \"b\"\n";
+
+    fn parse(home: &Path, toml: &str) -> Result<Config> {
+        Config::from_file_config(toml::from_str(toml)?, home)
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let home = TempDir::new();
+        for toml in [
+            "[paths]\nprovider = \"s3\"\nbukcet = \"b\"\n",
+            "[timing]\ndebounce = 1.0\n",
+            "[nonsense]\n",
+        ] {
+            let err = parse(home.path(), toml).unwrap_err();
+            assert!(format!("{:#}", err).contains("unknown"), "{}: {:#}", toml, err);
+        }
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        let home = TempDir::new();
+        assert!(parse(home.path(), S3).is_ok());
+        for (extra, message) in [
+            ("[timing]\ndebounce_secs = -1.0\n", "timing.debounce_secs"),
+            ("[timing]\nmax_batch_secs = 0.0\n", "timing.max_batch_secs"),
+            ("[sync]\nmax_delete_percent = 101\n", "sync.max_delete_percent"),
+            ("[sync]\ndirs = [\"skills\", \"\"]\n", "sync.dirs"),
+            ("[conflicts]\nstrategy = \"coin_flip\"\n", "unknown variant"),
+            ("[backups]\ndir = \"~bob/backups\"\n", "~user paths"),
+        ] {
+            let err = parse(home.path(), &format!("{}\n{}", S3, extra)).unwrap_err();
+            assert!(format!("{:#}", err).contains(message), "{}: {:#}", extra, err);
+        }
+    }
+
+    #[test]
+    fn tilde_expansion() {
+        let home = Path::new("/home/me");
+        for (path, expanded) in [
+            ("~", "/home/me"),
+            ("~/x/y", "/home/me/x/y"),
+            ("/abs/path", "/abs/path"),
+            ("rel~", "rel~"),
+        ] {
+            assert_eq!(shellexpand::tilde("k", path, home).unwrap(), expanded, "{}", path);
+        }
+        assert!(shellexpand::tilde("k", "~bob/x", home).is_err());
+    }
+
+    #[test]
+    fn legacy_values_are_converted() {
+        let file = parse_legacy(
+            "# comment\nexport DROPBOX_BASE=\"/db\"\nDEBOUNCE_SECS=1.5\nMAX_BATCH_SECS='20'\n\
+             CONFLICT_STRATEGY=keep-both\nSYNC_DIRS=\"skills, agents\"\nLOG_LEVEL=DEBUG\n\
+             NEW_KEY=x\nnot an assignment\n",
+        )
+        .unwrap();
+        assert_eq!(file.paths.provider, Some(ProviderKind::Dropbox));
+        assert_eq!(file.paths.base.as_deref(), Some("/db"));
+        assert_eq!(file.timing.debounce_secs, 1.5);
+        assert_eq!(file.timing.max_batch_secs, 20.0);
+        assert_eq!(file.conflicts.strategy, ConflictStrategy::KeepBoth);
+        assert_eq!(file.sync.dirs, ["skills", "agents"]);
+        assert!(matches!(file.logging.level, LogLevel::Debug));
+    }
+
+    #[test]
+    fn bad_legacy_values_are_rejected() {
+        for (line, message) in [
+            ("DEBOUNCE_SECS=3s", "DEBOUNCE_SECS"),
+            ("MAX_BATCH_SECS=", "MAX_BATCH_SECS"),
+            ("CONFLICT_STRATEGY=mine", "CONFLICT_STRATEGY"),
+            ("LOG_LEVEL=loud", "LOG_LEVEL"),
+        ] {
+            let err = parse_legacy(line).unwrap_err();
+            assert!(err.to_string().contains(message), "{}: {}", line, err);
+        }
+    }
+
+    #[test]
+    fn legacy_config_is_migrated() {
+        let home = TempDir::new();
+        fs::create_dir(home.path().join("Dropbox")).unwrap();
+        let legacy = home.write(".claude_sync_config", "DROPBOX_BASE=\"~/Dropbox\"\nDEBOUNCE_SECS=1.5\n");
+        let config_path = home.path().join(".config/claude-sync/config.toml");
+
+        let config = migrate_legacy(&legacy, &config_path, home.path()).unwrap();
+        assert_eq!(config.target.base, home.path().join("Dropbox"));
+        assert_eq!(config.debounce_secs, 1.5);
+
+        // The written file reads back to the same settings
+        let written = parse(home.path(), &fs::read_to_string(&config_path).unwrap()).unwrap();
+        assert_eq!(written.target.base, config.target.base);
+        assert_eq!(written.debounce_secs, 1.5);
+    }
+
+    #[test]
+    fn invalid_legacy_config_is_not_migrated() {
+        let home = TempDir::new();
+        fs::create_dir(home.path().join("Dropbox")).unwrap();
+        let config_path = home.path().join(".config/claude-sync/config.toml");
+        for content in [
+            "DROPBOX_BASE=\"~/Dropbox\"\nDEBOUNCE_SECS=fast\n",
+            "DROPBOX_BASE=\"~/Dropbox\"\nDEBOUNCE_SECS=-2\n",
+            "DROPBOX_BASE=\"~/Nowhere\"\n",
+        ] {
+            let legacy = home.write(".claude_sync_config", content);
+            assert!(migrate_legacy(&legacy, &config_path, home.path()).is_err(), "{}", content);
+            assert!(!config_path.exists(), "{}", content);
         }
-        Cow::Borrowed(path)
     }
 }
@@ -0,0 +1,163 @@
+//! Recognizing file events caused by the sync engine's own writes
+//!
+//! Every file a sync writes into a watched directory comes back as a change
+//! event and would schedule another sync. The engine records what it wrote;
+//! an event whose file still has that content (or is still gone, for a
+//! deletion) is an echo the watcher can drop.
+
+use crate::hashcache;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long a write is remembered (events are handled after the sync that
+/// caused them finishes)
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// Files recently written or deleted by the sync engine
+#[derive(Default)]
+pub struct SelfWrites {
+    /// Path -> checksum of the content written (None: deleted), and when
+    writes: RefCell<HashMap<PathBuf, (Option<String>, Instant)>>,
+}
+
+impl SelfWrites {
+    /// Record that `content` was written to `path`
+    pub fn record(&self, path: &Path, content: &[u8]) {
+        let sha256 = format!("{:x}", Sha256::digest(content));
+        self.insert(path, Some(sha256));
+    }
+
+    /// Record a write whose bytes on disk aren't known to the caller (e.g.
+    /// encrypted by the remote)
+    pub fn record_file(&self, path: &Path) {
+        if let Some(sha256) = sha256_file(path) {
+            self.insert(path, Some(sha256));
+        }
+    }
+
+    /// Record that `path` was deleted
+    pub fn record_deletion(&self, path: &Path) {
+        self.insert(path, None);
+    }
+
+    fn insert(&self, path: &Path, sha256: Option<String>) {
+        self.writes
+            .borrow_mut()
+            .insert(path.to_path_buf(), (sha256, Instant::now()));
+    }
+
+    /// Check if an event for `path` is explained by a recorded write
+    ///
+    /// A directory counts as an echo if the engine wrote files into it (it
+    /// may have created it) or deleted files from it (it may have removed it
+    /// as empty).
+    pub fn is_echo(&self, path: &Path) -> bool {
+        let mut writes = self.writes.borrow_mut();
+        writes.retain(|_, (_, at)| at.elapsed() < WINDOW);
+
+        if let Some((sha256, _)) = writes.get(path) {
+            return match sha256 {
+                Some(sha256) => sha256_file(path).is_some_and(|current| current == *sha256),
+                None => !path.exists(),
+            };
+        }
+
+        // Not recorded: only a directory created or emptied by the engine
+        let is_dir = path.is_dir();
+        if !is_dir && path.exists() {
+            return false;
+        }
+        writes
+            .iter()
+            .any(|(written, (sha256, _))| written.starts_with(path) && sha256.is_some() == is_dir)
+    }
+}
+
+fn sha256_file(path: &Path) -> Option<String> {
+    hashcache::sha256_file(path).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs;
+
+    #[test]
+    fn recorded_writes_are_echoes() {
+        let home = TempDir::new();
+        let writes = SelfWrites::default();
+        let path = home.write("dir/settings.json", "{}");
+        writes.record(&path, b"{}");
+        assert!(writes.is_echo(&path));
+        // Every event for the write is dropped, not just the first
+        assert!(writes.is_echo(&path));
+        // ...as is one for the directory it created
+        assert!(writes.is_echo(&home.path().join("dir")));
+        assert!(!writes.is_echo(&home.path().join("other")));
+    }
+
+    #[test]
+    fn edits_after_the_write_are_not_echoes() {
+        let home = TempDir::new();
+        let writes = SelfWrites::default();
+        let path = home.write("settings.json", "{}");
+        writes.record(&path, b"{}");
+
+        fs::write(&path, "{\"model\": \"opus\"}").unwrap();
+        assert!(!writes.is_echo(&path));
+        fs::remove_file(&path).unwrap();
+        assert!(!writes.is_echo(&path));
+    }
+
+    #[test]
+    fn repeated_writes_are_matched_against_the_latest() {
+        let home = TempDir::new();
+        let writes = SelfWrites::default();
+        let path = home.write("settings.json", "{}");
+        writes.record(&path, b"{}");
+        fs::write(&path, "{\"a\": 1}").unwrap();
+        writes.record(&path, b"{\"a\": 1}");
+        assert!(writes.is_echo(&path));
+
+        // Put back to the first content by someone else
+        fs::write(&path, "{}").unwrap();
+        assert!(!writes.is_echo(&path));
+    }
+
+    #[test]
+    fn deletions() {
+        let home = TempDir::new();
+        let writes = SelfWrites::default();
+        let path = home.write("dir/settings.json", "{}");
+        fs::remove_file(&path).unwrap();
+        writes.record_deletion(&path);
+        assert!(writes.is_echo(&path));
+
+        // A directory the engine removed as empty, but not one it still has
+        fs::remove_dir(home.path().join("dir")).unwrap();
+        assert!(writes.is_echo(&home.path().join("dir")));
+        fs::create_dir(home.path().join("dir")).unwrap();
+        assert!(!writes.is_echo(&home.path().join("dir")));
+
+        // Recreated since
+        fs::write(&path, "{}").unwrap();
+        assert!(!writes.is_echo(&path));
+    }
+
+    #[test]
+    fn writes_expire() {
+        let home = TempDir::new();
+        let writes = SelfWrites::default();
+        let path = home.write("settings.json", "{}");
+        writes.record(&path, b"{}");
+
+        let long_ago = Instant::now().checked_sub(WINDOW).unwrap();
+        writes.writes.borrow_mut().get_mut(&path).unwrap().1 = long_ago;
+        assert!(!writes.is_echo(&path));
+        assert!(writes.writes.borrow().is_empty());
+    }
+}
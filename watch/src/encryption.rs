@@ -0,0 +1,918 @@
+//! Client-side encryption of the shared copy
+//!
+//! With `encryption.enabled`, every file is encrypted before it is written to
+//! the shared copy and decrypted after it is read, so the sync provider only
+//! ever stores ciphertext. Checksums compared by the engine are those of the
+//! plaintext.
+//!
+//! A file is stored as `CSE1`, a random 24-byte nonce and the
+//! XChaCha20-Poly1305 encryption of its relative path (u16 length first) and
+//! content. Keeping the path inside lets file names be encrypted too and
+//! stops one file being swapped for another. With `encryption.encrypt_names`,
+//! files are stored as `encrypted/<keyed hash of the path>`; a local index
+//! maps them back without decrypting files that haven't changed.
+//!
+//! The 256-bit key is derived from a passphrase with Argon2id. The salt and a
+//! check value live in `.claude-sync-encryption.json` in the shared copy, so
+//! every machine derives the same key from the same passphrase and a wrong
+//! passphrase is caught. `init-encryption` derives the key and keeps it in the
+//! macOS keychain or a key file.
+
+use crate::config::{Config, KeySource};
+use crate::filter::SyncSet;
+use crate::remote::{self, Remote, RemoteFile};
+use crate::state::FileState;
+use anyhow::{anyhow, bail, Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chacha20poly1305::aead::rand_core::RngCore;
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng, Payload};
+use chacha20poly1305::{XChaCha20Poly1305, XNonce};
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use zeroize::Zeroizing;
+
+/// Key derivation parameters, stored unencrypted in the shared copy
+pub const PARAMS_FILE: &str = ".claude-sync-encryption.json";
+
+/// Directory holding files with encrypted names
+pub const NAMES_DIR: &str = "encrypted";
+
+/// Start of every encrypted file (format version 1)
+const MAGIC: &[u8] = b"CSE1";
+
+const NONCE_LEN: usize = 24;
+
+/// Plaintext encrypted into the check value
+const CHECK_TEXT: &[u8] = b"claude-sync-watch";
+
+/// Keychain item holding the key
+const KEYCHAIN_SERVICE: &str = "claude-sync-watch";
+const KEYCHAIN_ACCOUNT: &str = "encryption-key";
+
+/// Encrypts and decrypts files with the shared key
+pub struct Cipher {
+    aead: XChaCha20Poly1305,
+    /// Key for hashing file names, None if names aren't encrypted
+    name_key: Option<Zeroizing<Vec<u8>>>,
+}
+
+impl Cipher {
+    /// Set up encryption with the derived key (separate subkeys for contents
+    /// and names)
+    fn new(key: &[u8], encrypt_names: bool) -> Self {
+        let content_key = Zeroizing::new(hmac_sha256(key, b"content"));
+        Self {
+            aead: XChaCha20Poly1305::new_from_slice(&content_key).expect("key is 32 bytes"),
+            name_key: encrypt_names.then(|| Zeroizing::new(hmac_sha256(key, b"names"))),
+        }
+    }
+
+    /// Load this machine's key
+    pub fn load(config: &Config) -> Result<Self> {
+        let key = load_key(config)?;
+        Ok(Self::new(&key, config.encryption.encrypt_names))
+    }
+
+    /// Name of a file in the shared copy
+    pub fn remote_name(&self, rel_path: &str) -> String {
+        match &self.name_key {
+            Some(name_key) => format!("{}/{}", NAMES_DIR, &hex(&hmac_sha256(name_key, rel_path.as_bytes()))[..32]),
+            None => rel_path.to_string(),
+        }
+    }
+
+    pub fn encrypt(&self, rel_path: &str, content: &[u8]) -> Result<Vec<u8>> {
+        let path_len = u16::try_from(rel_path.len()).context("Path too long to encrypt")?;
+        let mut plaintext = Zeroizing::new(Vec::with_capacity(2 + rel_path.len() + content.len()));
+        plaintext.extend_from_slice(&path_len.to_be_bytes());
+        plaintext.extend_from_slice(rel_path.as_bytes());
+        plaintext.extend_from_slice(content);
+
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .aead
+            .encrypt(&nonce, Payload { msg: &plaintext, aad: MAGIC })
+            .map_err(|_| anyhow!("Failed to encrypt {}", rel_path))?;
+
+        let mut data = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        data.extend_from_slice(MAGIC);
+        data.extend_from_slice(&nonce);
+        data.extend_from_slice(&ciphertext);
+        Ok(data)
+    }
+
+    /// Decrypt a file, returning the relative path stored in it and its content
+    pub fn decrypt(&self, data: &[u8]) -> Result<(String, Vec<u8>)> {
+        if !is_encrypted(data) || data.len() < MAGIC.len() + NONCE_LEN {
+            bail!("not an encrypted file");
+        }
+        let (nonce, ciphertext) = data[MAGIC.len()..].split_at(NONCE_LEN);
+        let plaintext = Zeroizing::new(
+            self.aead
+                .decrypt(XNonce::from_slice(nonce), Payload { msg: ciphertext, aad: MAGIC })
+                .map_err(|_| anyhow!("wrong key or damaged file"))?,
+        );
+
+        let path_end = plaintext
+            .get(..2)
+            .map(|len| 2 + u16::from_be_bytes([len[0], len[1]]) as usize)
+            .filter(|&end| end <= plaintext.len())
+            .context("damaged file")?;
+        let rel_path = String::from_utf8(plaintext[2..path_end].to_vec()).context("damaged file")?;
+        Ok((rel_path, plaintext[path_end..].to_vec()))
+    }
+}
+
+/// Check if content was written by `Cipher::encrypt`
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Salt, check value and naming scheme shared by all machines
+#[derive(Debug, Serialize, Deserialize)]
+struct KeyParams {
+    /// Argon2id memory cost in KiB
+    memory_kib: u32,
+    iterations: u32,
+    parallelism: u32,
+    salt: String,
+    /// `CHECK_TEXT` encrypted with the key
+    check: String,
+    #[serde(default)]
+    encrypt_names: bool,
+}
+
+impl KeyParams {
+    fn derive(&self, passphrase: &str) -> Result<Zeroizing<Vec<u8>>> {
+        let salt = BASE64_STANDARD.decode(&self.salt).context("Invalid salt")?;
+        let params = Params::new(self.memory_kib, self.iterations, self.parallelism, Some(32))
+            .map_err(|e| anyhow!("Invalid key derivation parameters: {}", e))?;
+        let mut key = Zeroizing::new(vec![0u8; 32]);
+        Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+            .hash_password_into(passphrase.as_bytes(), &salt, &mut key)
+            .map_err(|e| anyhow!("Failed to derive key: {}", e))?;
+        Ok(key)
+    }
+
+    /// Read the parameters from the shared copy, None if it isn't encrypted
+    fn load(remote: &dyn Remote) -> Result<Option<Self>> {
+        if remote.stat(PARAMS_FILE)?.is_none() {
+            return Ok(None);
+        }
+        let params = serde_json::from_slice(&remote.read(PARAMS_FILE)?)
+            .with_context(|| format!("Invalid {} in {}", PARAMS_FILE, remote.name()))?;
+        Ok(Some(params))
+    }
+
+    fn save(&self, remote: &dyn Remote) -> Result<()> {
+        let expected = remote.stat(PARAMS_FILE)?;
+        remote.write(PARAMS_FILE, serde_json::to_string_pretty(self)?.as_bytes(), None, expected.as_ref())?;
+        Ok(())
+    }
+
+    /// Check if the check value was encrypted with the cipher's key
+    fn matches(&self, cipher: &Cipher) -> bool {
+        BASE64_STANDARD
+            .decode(&self.check)
+            .ok()
+            .and_then(|check| cipher.decrypt(&check).ok())
+            .is_some_and(|(_, content)| content == CHECK_TEXT)
+    }
+}
+
+/// Shared copy whose files are encrypted before they reach another backend
+pub struct EncryptedRemote {
+    inner: Box<dyn Remote>,
+    cipher: Cipher,
+    claude_dir: PathBuf,
+    index: DecryptedIndex,
+    /// Relative path -> stored file last seen, for the backend's own checks
+    /// in writes and deletes
+    seen: RefCell<HashMap<String, RemoteFile>>,
+}
+
+impl EncryptedRemote {
+    pub fn new(config: &Config, inner: Box<dyn Remote>) -> Result<Self> {
+        let cipher = Cipher::load(config)?;
+        let params = KeyParams::load(inner.as_ref())?.with_context(|| {
+            format!("{} is not encrypted yet; run claude-sync-watch init-encryption", inner.location())
+        })?;
+        if !params.matches(&cipher) {
+            bail!("This machine's encryption key doesn't match {}; run claude-sync-watch init-encryption again", inner.location());
+        }
+        // A machine expecting the other naming scheme would see every file
+        // as deleted
+        if params.encrypt_names != config.encryption.encrypt_names {
+            bail!(
+                "{} is set up with encryption.encrypt_names = {}; use the same setting on this machine or run claude-sync-watch init-encryption to convert it",
+                inner.location(),
+                params.encrypt_names
+            );
+        }
+
+        Ok(Self {
+            inner,
+            cipher,
+            claude_dir: config.claude_dir.clone(),
+            index: DecryptedIndex::load(config.local_encryption_index_path()),
+            seen: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Checksum and path of a stored file, decrypting it only if it changed
+    /// since it was last seen
+    fn decrypted(&self, name: &str, stored: &RemoteFile) -> Result<Decrypted> {
+        if let Some(decrypted) = self.index.get(name, &stored.state.sha256) {
+            return Ok(decrypted);
+        }
+
+        let data = self.inner.read(name)?;
+        let (rel_path, content) = self
+            .cipher
+            .decrypt(&data)
+            .with_context(|| format!("Failed to decrypt {} in {}", name, self.inner.name()))?;
+        if self.cipher.remote_name(&rel_path) != name {
+            bail!("{} in {} holds {}, not the file stored under that name", name, self.inner.name(), rel_path);
+        }
+
+        let decrypted = Decrypted {
+            stored_sha256: format!("{:x}", Sha256::digest(&data)),
+            rel_path,
+            sha256: format!("{:x}", Sha256::digest(&content)),
+            size: content.len() as u64,
+        };
+        self.index.remember(name, decrypted.clone());
+        Ok(decrypted)
+    }
+
+    /// The file as the engine sees it, with the checksum of the plaintext
+    fn plain_file(&self, stored: RemoteFile, decrypted: &Decrypted) -> RemoteFile {
+        let file = RemoteFile {
+            state: FileState {
+                sha256: decrypted.sha256.clone(),
+                mtime: stored.state.mtime,
+                size: decrypted.size,
+            },
+            version: stored.version.clone(),
+            mode: stored.mode,
+        };
+        self.seen.borrow_mut().insert(decrypted.rel_path.clone(), stored);
+        file
+    }
+
+    /// The stored file a write or delete expects, provided it is still the
+    /// version the caller saw
+    fn stored_expected(&self, rel_path: &str, expected: &RemoteFile) -> Result<RemoteFile> {
+        if !self.seen.borrow().contains_key(rel_path) {
+            self.stat(rel_path)?;
+        }
+        let seen = self.seen.borrow();
+        let stored = seen
+            .get(rel_path)
+            .filter(|stored| {
+                self.index
+                    .get(&self.cipher.remote_name(rel_path), &stored.state.sha256)
+                    .is_some_and(|decrypted| decrypted.sha256 == expected.state.sha256)
+            })
+            .with_context(|| format!("{} changed in {} since it was read", rel_path, self.inner.name()))?;
+        Ok(stored.clone())
+    }
+
+    /// Sync set for listing the backend
+    fn stored_sync_set(&self, sync_set: &SyncSet) -> SyncSet {
+        match self.cipher.name_key {
+            Some(_) => SyncSet::for_dir(NAMES_DIR),
+            None => sync_set.clone(),
+        }
+    }
+}
+
+impl Remote for EncryptedRemote {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn location(&self) -> String {
+        format!("{} (encrypted)", self.inner.location())
+    }
+
+    fn local_dir(&self) -> Option<&Path> {
+        self.inner.local_dir()
+    }
+
+    fn local_path(&self, rel_path: &str) -> Option<PathBuf> {
+        self.inner.local_path(&self.cipher.remote_name(rel_path))
+    }
+
+    fn list(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, RemoteFile>> {
+        let mut files = BTreeMap::new();
+        let mut entries = HashMap::new();
+        self.seen.borrow_mut().clear();
+
+        for (name, stored) in self.inner.list(&self.stored_sync_set(sync_set))? {
+            if self.inner.is_conflict_copy(&name) {
+                continue;
+            }
+            let decrypted = if self.inner.is_pending(&name) {
+                // Can't be read yet; the engine skips it by its last known path
+                match self.index.last(&name) {
+                    Some(decrypted) => decrypted,
+                    None => continue,
+                }
+            } else {
+                self.decrypted(&name, &stored)?
+            };
+
+            entries.insert(name, decrypted.clone());
+            if sync_set.is_synced(&self.claude_dir, &decrypted.rel_path, false) {
+                let file = self.plain_file(stored, &decrypted);
+                files.insert(decrypted.rel_path, file);
+            }
+        }
+
+        self.index.replace(entries);
+        Ok(files)
+    }
+
+    fn stat(&self, rel_path: &str) -> Result<Option<RemoteFile>> {
+        let name = self.cipher.remote_name(rel_path);
+        let Some(stored) = self.inner.stat(&name)? else {
+            self.seen.borrow_mut().remove(rel_path);
+            return Ok(None);
+        };
+        let decrypted = self.decrypted(&name, &stored)?;
+        if decrypted.rel_path != rel_path {
+            bail!("{} in {} holds {}", name, self.inner.name(), decrypted.rel_path);
+        }
+        Ok(Some(self.plain_file(stored, &decrypted)))
+    }
+
+    fn read(&self, rel_path: &str) -> Result<Vec<u8>> {
+        let name = self.cipher.remote_name(rel_path);
+        let (stored_path, content) = self
+            .cipher
+            .decrypt(&self.inner.read(&name)?)
+            .with_context(|| format!("Failed to decrypt {} in {}", name, self.inner.name()))?;
+        if stored_path != rel_path {
+            bail!("{} in {} holds {}", name, self.inner.name(), stored_path);
+        }
+        Ok(content)
+    }
+
+    fn write(
+        &self,
+        rel_path: &str,
+        content: &[u8],
+        mode: Option<u32>,
+        expected: Option<&RemoteFile>,
+    ) -> Result<RemoteFile> {
+        let stored_expected = expected
+            .map(|expected| self.stored_expected(rel_path, expected))
+            .transpose()?;
+
+        let name = self.cipher.remote_name(rel_path);
+        let data = self.cipher.encrypt(rel_path, content)?;
+        let stored = self.inner.write(&name, &data, mode, stored_expected.as_ref())?;
+
+        // The engine verifies the plaintext checksum returned here, so only
+        // return it if the ciphertext arrived intact
+        let stored_sha256 = format!("{:x}", Sha256::digest(&data));
+        if stored.state.sha256 != stored_sha256 {
+            bail!("{} was not stored intact in {}", name, self.inner.name());
+        }
+
+        let decrypted = Decrypted {
+            stored_sha256,
+            rel_path: rel_path.to_string(),
+            sha256: format!("{:x}", Sha256::digest(content)),
+            size: content.len() as u64,
+        };
+        self.index.remember(&name, decrypted.clone());
+        Ok(self.plain_file(stored, &decrypted))
+    }
+
+    fn delete(&self, rel_path: &str, expected: &RemoteFile) -> Result<()> {
+        let stored_expected = self.stored_expected(rel_path, expected)?;
+        let name = self.cipher.remote_name(rel_path);
+        self.inner.delete(&name, &stored_expected)?;
+        self.seen.borrow_mut().remove(rel_path);
+        self.index.forget(&name);
+        Ok(())
+    }
+
+    fn is_pending(&self, rel_path: &str) -> bool {
+        self.inner.is_pending(&self.cipher.remote_name(rel_path))
+    }
+
+    fn has_changes(&self, sync_set: &SyncSet) -> Result<bool> {
+        self.inner.has_changes(&self.stored_sync_set(sync_set))
+    }
+}
+
+/// What a stored file decrypted to
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Decrypted {
+    /// Checksum of the ciphertext
+    stored_sha256: String,
+    rel_path: String,
+    /// Checksum and size of the plaintext
+    sha256: String,
+    size: u64,
+}
+
+/// Stored name -> decrypted path and checksum, kept in a local file so only
+/// new versions have to be downloaded and decrypted
+struct DecryptedIndex {
+    path: PathBuf,
+    entries: RefCell<HashMap<String, Decrypted>>,
+}
+
+impl DecryptedIndex {
+    fn load(path: PathBuf) -> Self {
+        let entries = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            entries: RefCell::new(entries),
+        }
+    }
+
+    /// The entry for a stored file, if it still has this checksum
+    fn get(&self, name: &str, stored_sha256: &str) -> Option<Decrypted> {
+        self.last(name).filter(|d| d.stored_sha256 == stored_sha256)
+    }
+
+    /// The entry for a stored file, whatever its version
+    fn last(&self, name: &str) -> Option<Decrypted> {
+        self.entries.borrow().get(name).cloned()
+    }
+
+    fn remember(&self, name: &str, decrypted: Decrypted) {
+        self.entries.borrow_mut().insert(name.to_string(), decrypted);
+        self.save();
+    }
+
+    fn forget(&self, name: &str) {
+        if self.entries.borrow_mut().remove(name).is_some() {
+            self.save();
+        }
+    }
+
+    /// Replace the index with the files of a full listing
+    fn replace(&self, entries: HashMap<String, Decrypted>) {
+        if *self.entries.borrow() != entries {
+            *self.entries.borrow_mut() = entries;
+            self.save();
+        }
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(&*self.entries.borrow())
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(fs::write(&self.path, content)?));
+        if let Err(e) = result {
+            log::warn!("Failed to save index {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Set up this machine's key: derive it from the passphrase (creating the
+/// shared salt on first use), store it, then convert files stored in
+/// plaintext or under the other naming scheme
+pub fn init(config: &Config) -> Result<()> {
+    if !config.encryption.enabled {
+        bail!("Set encryption.enabled = true in {:?} first", Config::config_path()?);
+    }
+
+    let remote = remote::open_backend(config)?;
+    let (mut params, key) = match KeyParams::load(remote.as_ref())? {
+        Some(params) => {
+            let passphrase = read_passphrase("Passphrase: ")?;
+            let key = params.derive(&passphrase)?;
+            if !params.matches(&Cipher::new(&key, false)) {
+                bail!("Wrong passphrase: the shared copy was encrypted with a different one");
+            }
+            (params, key)
+        }
+        None => {
+            let passphrase = read_passphrase("New passphrase: ")?;
+            if passphrase.chars().count() < 8 {
+                bail!("Use a passphrase of at least 8 characters");
+            }
+            if std::env::var_os("CLAUDE_SYNC_PASSPHRASE").is_none()
+                && *read_passphrase("Repeat passphrase: ")? != *passphrase
+            {
+                bail!("Passphrases don't match");
+            }
+
+            let mut salt = [0u8; 16];
+            OsRng.fill_bytes(&mut salt);
+            let mut params = KeyParams {
+                memory_kib: 65536,
+                iterations: 3,
+                parallelism: 1,
+                salt: BASE64_STANDARD.encode(salt),
+                check: String::new(),
+                encrypt_names: config.encryption.encrypt_names,
+            };
+            let key = params.derive(&passphrase)?;
+            params.check = BASE64_STANDARD.encode(Cipher::new(&key, false).encrypt("", CHECK_TEXT)?);
+            params.save(remote.as_ref())?;
+            (params, key)
+        }
+    };
+
+    store_key(config, &key)?;
+    match config.encryption.key {
+        KeySource::Keychain => println!("Stored the encryption key in the keychain"),
+        KeySource::File => println!("Stored the encryption key in {}", config.encryption.key_file),
+    }
+
+    // Switch the naming scheme first, so other machines stop syncing until
+    // they use it too
+    if params.encrypt_names != config.encryption.encrypt_names {
+        params.encrypt_names = config.encryption.encrypt_names;
+        params.save(remote.as_ref())?;
+    }
+
+    let converted = convert_existing(config, remote.as_ref(), &Cipher::new(&key, config.encryption.encrypt_names))?;
+    if converted > 0 {
+        println!("Encrypted {} file(s) already in {}", converted, remote.name());
+    }
+    Ok(())
+}
+
+/// Encrypt files stored in plaintext and move files stored under the other
+/// naming scheme, returning how many were converted
+fn convert_existing(config: &Config, remote: &dyn Remote, cipher: &Cipher) -> Result<usize> {
+    let mut stored = remote.list(&SyncSet::new(config))?;
+    stored.append(&mut remote.list(&SyncSet::for_dir(NAMES_DIR))?);
+
+    let mut count = 0;
+    for (name, file) in stored {
+        if remote.is_conflict_copy(&name) || remote.is_pending(&name) {
+            continue;
+        }
+
+        let data = remote.read(&name)?;
+        let (rel_path, content) = if is_encrypted(&data) {
+            cipher
+                .decrypt(&data)
+                .with_context(|| format!("Failed to decrypt {} in {}", name, remote.name()))?
+        } else if name.starts_with(&format!("{}/", NAMES_DIR)) {
+            continue;
+        } else {
+            (name.clone(), data.clone())
+        };
+
+        let target = cipher.remote_name(&rel_path);
+        if target == name {
+            if !is_encrypted(&data) {
+                remote.write(&name, &cipher.encrypt(&rel_path, &content)?, file.mode, Some(&file))?;
+                count += 1;
+            }
+        } else {
+            let existing = remote.stat(&target)?;
+            remote.write(&target, &cipher.encrypt(&rel_path, &content)?, file.mode, existing.as_ref())?;
+            remote.delete(&name, &file)?;
+            count += 1;
+        }
+    }
+    Ok(count)
+}
+
+/// Fail if the shared copy is encrypted but this machine isn't set up for it
+pub fn check_unencrypted(remote: &dyn Remote) -> Result<()> {
+    if remote.stat(PARAMS_FILE)?.is_some() {
+        bail!(
+            "{} holds encrypted files; set encryption.enabled = true and run claude-sync-watch init-encryption",
+            remote.location()
+        );
+    }
+    Ok(())
+}
+
+/// Prompt for the passphrase without echo (or take it from
+/// `CLAUDE_SYNC_PASSPHRASE` for scripted setup)
+fn read_passphrase(prompt: &str) -> Result<Zeroizing<String>> {
+    if let Ok(passphrase) = std::env::var("CLAUDE_SYNC_PASSPHRASE") {
+        return Ok(Zeroizing::new(passphrase));
+    }
+    let passphrase = rpassword::prompt_password(prompt).context("Failed to read passphrase")?;
+    Ok(Zeroizing::new(passphrase))
+}
+
+fn load_key(config: &Config) -> Result<Zeroizing<Vec<u8>>> {
+    let encoded = match config.encryption.key {
+        KeySource::Keychain => {
+            let output = Command::new("security")
+                .args(["find-generic-password", "-s", KEYCHAIN_SERVICE, "-a", KEYCHAIN_ACCOUNT, "-w"])
+                .output()
+                .context("Failed to run security")?;
+            if !output.status.success() {
+                bail!("No encryption key in the keychain; run claude-sync-watch init-encryption");
+            }
+            Zeroizing::new(String::from_utf8_lossy(&output.stdout).into_owned())
+        }
+        KeySource::File => {
+            let path = Path::new(&config.encryption.key_file);
+            if !path.exists() {
+                bail!("Encryption key file {:?} not found; run claude-sync-watch init-encryption", path);
+            }
+            warn_if_readable_by_others(path);
+            Zeroizing::new(fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?)
+        }
+    };
+
+    let key = Zeroizing::new(BASE64_STANDARD.decode(encoded.trim()).unwrap_or_default());
+    if key.len() != 32 {
+        bail!("Invalid encryption key; run claude-sync-watch init-encryption again");
+    }
+    Ok(key)
+}
+
+fn store_key(config: &Config, key: &[u8]) -> Result<()> {
+    let encoded = Zeroizing::new(BASE64_STANDARD.encode(key));
+    match config.encryption.key {
+        KeySource::Keychain => {
+            // Passed on stdin rather than the command line, where other
+            // processes could see it
+            let mut child = Command::new("security")
+                .arg("-i")
+                .stdin(Stdio::piped())
+                .stdout(Stdio::null())
+                .spawn()
+                .context("Failed to run security")?;
+            let command = Zeroizing::new(format!(
+                "add-generic-password -U -s {} -a {} -w {}\n",
+                KEYCHAIN_SERVICE, KEYCHAIN_ACCOUNT, *encoded
+            ));
+            if let Some(mut stdin) = child.stdin.take() {
+                stdin.write_all(command.as_bytes())?;
+            }
+            if !child.wait()?.success() {
+                bail!("Failed to store the encryption key in the keychain");
+            }
+        }
+        KeySource::File => {
+            let path = Path::new(&config.encryption.key_file);
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            remote::write_private(path, format!("{}\n", *encoded).as_bytes())
+                .with_context(|| format!("Failed to write {:?}", path))?;
+        }
+    }
+    Ok(())
+}
+
+fn warn_if_readable_by_others(path: &Path) {
+    let mode = fs::metadata(path).ok().and_then(|m| remote::file_mode(&m));
+    if mode.is_some_and(|mode| mode & 0o077 != 0) {
+        log::warn!("Encryption key file {:?} can be read by other users; run chmod 600 on it", path);
+    }
+}
+
+fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remote::FolderRemote;
+    use crate::testutil::TempDir;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    /// Config for an encrypted folder remote at `home`/share/Sync, with
+    /// `KEY` in a key file
+    fn encrypted_config(home: &TempDir, encrypt_names: bool) -> Config {
+        let share = home.path().join("share");
+        fs::create_dir_all(share.join("Sync")).unwrap();
+        let key_file = home.path().join("encryption.key");
+        let config = Config::for_test(
+            home.path(),
+            &format!(
+                "[paths]\nprovider = \"directory\"\nbase = {:?}\nfolder = \"Sync\"\n\n\
+                 [encryption]\nenabled = true\nkey = \"file\"\nkey_file = {:?}\nencrypt_names = {}\n",
+                share.to_string_lossy(),
+                key_file.to_string_lossy(),
+                encrypt_names
+            ),
+        );
+        store_key(&config, &KEY).unwrap();
+        config
+    }
+
+    /// Cheap key derivation parameters, checked against `cipher`
+    fn key_params(cipher: &Cipher, encrypt_names: bool) -> KeyParams {
+        KeyParams {
+            memory_kib: 64,
+            iterations: 1,
+            parallelism: 1,
+            salt: BASE64_STANDARD.encode([1u8; 16]),
+            check: BASE64_STANDARD.encode(cipher.encrypt("", CHECK_TEXT).unwrap()),
+            encrypt_names,
+        }
+    }
+
+    /// Set up the shared copy for `config` and open it encrypted
+    fn encrypted_remote(config: &Config) -> EncryptedRemote {
+        let inner = FolderRemote::new(config.target.clone());
+        key_params(&Cipher::new(&KEY, false), config.encryption.encrypt_names)
+            .save(&inner)
+            .unwrap();
+        EncryptedRemote::new(config, Box::new(inner)).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let cipher = Cipher::new(&KEY, false);
+        let data = cipher.encrypt("skills/review/SKILL.md", b"Review the diff\n").unwrap();
+        assert!(is_encrypted(&data));
+        assert!(!data.windows(6).any(|w| w == b"Review"));
+        assert!(!data.windows(6).any(|w| w == b"skills"));
+
+        let (rel_path, content) = cipher.decrypt(&data).unwrap();
+        assert_eq!(rel_path, "skills/review/SKILL.md");
+        assert_eq!(content, b"Review the diff\n");
+
+        // A fresh nonce every time
+        assert_ne!(cipher.encrypt("skills/review/SKILL.md", b"Review the diff\n").unwrap(), data);
+        let (_, empty) = cipher.decrypt(&cipher.encrypt("CLAUDE.md", b"").unwrap()).unwrap();
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn tampered_files_are_rejected() {
+        let cipher = Cipher::new(&KEY, false);
+        let data = cipher.encrypt("settings.json", b"{}").unwrap();
+
+        for i in [MAGIC.len(), MAGIC.len() + NONCE_LEN, data.len() - 1] {
+            let mut tampered = data.clone();
+            tampered[i] ^= 1;
+            assert!(cipher.decrypt(&tampered).is_err(), "byte {} flipped", i);
+        }
+        assert!(cipher.decrypt(&data[..data.len() - 1]).is_err());
+        assert!(cipher.decrypt(&data[..MAGIC.len() + 3]).is_err());
+        assert!(cipher.decrypt(b"{}").is_err());
+
+        let mut unmarked = data.clone();
+        unmarked[0] = b'X';
+        assert!(cipher.decrypt(&unmarked).is_err());
+    }
+
+    #[test]
+    fn wrong_key_is_rejected() {
+        let data = Cipher::new(&KEY, false).encrypt("settings.json", b"{}").unwrap();
+        let error = Cipher::new(&[8; 32], false).decrypt(&data).unwrap_err();
+        assert_eq!(error.to_string(), "wrong key or damaged file");
+    }
+
+    #[test]
+    fn key_params_check_the_key() {
+        let cipher = Cipher::new(&KEY, false);
+        let params = key_params(&cipher, false);
+        assert!(params.matches(&cipher));
+        assert!(params.matches(&Cipher::new(&KEY, true)));
+        assert!(!params.matches(&Cipher::new(&[8; 32], false)));
+
+        // Something else encrypted with the right key
+        let other = KeyParams {
+            check: BASE64_STANDARD.encode(cipher.encrypt("", b"other").unwrap()),
+            ..key_params(&cipher, false)
+        };
+        assert!(!other.matches(&cipher));
+        let garbage = KeyParams {
+            check: "not base64!".to_string(),
+            ..key_params(&cipher, false)
+        };
+        assert!(!garbage.matches(&cipher));
+
+        // The same passphrase gives the same key
+        let key = params.derive("correct horse").unwrap();
+        assert_eq!(key, params.derive("correct horse").unwrap());
+        assert_ne!(key, params.derive("wrong horse").unwrap());
+        let derived = Cipher::new(&key, false);
+        let params = KeyParams {
+            check: BASE64_STANDARD.encode(derived.encrypt("", CHECK_TEXT).unwrap()),
+            ..params
+        };
+        assert!(params.matches(&Cipher::new(&params.derive("correct horse").unwrap(), false)));
+        assert!(!params.matches(&Cipher::new(&params.derive("wrong horse").unwrap(), false)));
+    }
+
+    #[test]
+    fn encrypted_remote_round_trip() {
+        let home = TempDir::new();
+        let config = encrypted_config(&home, false);
+        let remote = encrypted_remote(&config);
+
+        let written = remote.write("settings.json", b"{\"model\": \"opus\"}", None, None).unwrap();
+        assert_eq!(written.state.sha256, format!("{:x}", Sha256::digest(b"{\"model\": \"opus\"}")));
+        let stored = fs::read(config.target.dir.join("settings.json")).unwrap();
+        assert!(is_encrypted(&stored));
+
+        assert_eq!(remote.read("settings.json").unwrap(), b"{\"model\": \"opus\"}");
+        let listed = remote.list(&SyncSet::new(&config)).unwrap();
+        assert_eq!(listed.keys().collect::<Vec<_>>(), ["settings.json"]);
+        assert_eq!(listed["settings.json"].state.sha256, written.state.sha256);
+    }
+
+    #[test]
+    fn swapped_files_are_rejected() {
+        for encrypt_names in [false, true] {
+            let home = TempDir::new();
+            let config = encrypted_config(&home, encrypt_names);
+            let remote = encrypted_remote(&config);
+            remote.write("settings.json", b"{}", None, None).unwrap();
+            remote.write("CLAUDE.md", b"Be brief\n", None, None).unwrap();
+
+            // Someone with access to the shared copy swaps the two files
+            let settings = remote.local_path("settings.json").unwrap();
+            let claude_md = remote.local_path("CLAUDE.md").unwrap();
+            let settings_data = fs::read(&settings).unwrap();
+            fs::copy(&claude_md, &settings).unwrap();
+            fs::write(&claude_md, settings_data).unwrap();
+
+            assert!(remote.read("settings.json").is_err(), "encrypt_names = {}", encrypt_names);
+            assert!(remote.stat("CLAUDE.md").is_err(), "encrypt_names = {}", encrypt_names);
+            assert!(remote.list(&SyncSet::new(&config)).is_err(), "encrypt_names = {}", encrypt_names);
+        }
+    }
+
+    #[test]
+    fn remote_names_hide_paths() {
+        let cipher = Cipher::new(&KEY, true);
+        let name = cipher.remote_name("skills/review/SKILL.md");
+        assert!(name.starts_with("encrypted/"));
+        assert_eq!(name.len(), "encrypted/".len() + 32);
+        assert_eq!(name, cipher.remote_name("skills/review/SKILL.md"));
+        assert_ne!(name, cipher.remote_name("CLAUDE.md"));
+        assert_ne!(name, Cipher::new(&[8; 32], true).remote_name("skills/review/SKILL.md"));
+        assert_eq!(Cipher::new(&KEY, false).remote_name("CLAUDE.md"), "CLAUDE.md");
+    }
+
+    #[test]
+    fn existing_files_are_converted() {
+        let home = TempDir::new();
+        let config = encrypted_config(&home, false);
+        let remote = FolderRemote::new(config.target.clone());
+        home.write("share/Sync/settings.json", "{}");
+        home.write("share/Sync/skills/review/SKILL.md", "Review the diff\n");
+
+        // Plaintext files are encrypted in place
+        let cipher = Cipher::new(&KEY, false);
+        assert_eq!(convert_existing(&config, &remote, &cipher).unwrap(), 2);
+        let stored = remote.read("skills/review/SKILL.md").unwrap();
+        assert_eq!(
+            cipher.decrypt(&stored).unwrap(),
+            ("skills/review/SKILL.md".to_string(), b"Review the diff\n".to_vec())
+        );
+        assert_eq!(convert_existing(&config, &remote, &cipher).unwrap(), 0);
+
+        // Switching to encrypted names moves them
+        let cipher = Cipher::new(&KEY, true);
+        assert_eq!(convert_existing(&config, &remote, &cipher).unwrap(), 2);
+        assert!(remote.stat("settings.json").unwrap().is_none());
+        let name = cipher.remote_name("settings.json");
+        assert_eq!(cipher.decrypt(&remote.read(&name).unwrap()).unwrap().1, b"{}");
+        assert_eq!(convert_existing(&config, &remote, &cipher).unwrap(), 0);
+
+        // ...and back
+        let cipher = Cipher::new(&KEY, false);
+        assert_eq!(convert_existing(&config, &remote, &cipher).unwrap(), 2);
+        assert!(remote.list(&SyncSet::for_dir(NAMES_DIR)).unwrap().is_empty());
+        assert_eq!(cipher.decrypt(&remote.read("settings.json").unwrap()).unwrap().1, b"{}");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_round_trip() {
+        let home = TempDir::new();
+        let config = encrypted_config(&home, false);
+        assert_eq!(*load_key(&config).unwrap(), KEY);
+        let mode = remote::file_mode(&fs::metadata(&config.encryption.key_file).unwrap());
+        assert_eq!(mode, Some(0o600));
+
+        fs::write(&config.encryption.key_file, "c2hvcnQ=\n").unwrap();
+        assert!(load_key(&config).is_err());
+    }
+}
@@ -0,0 +1,576 @@
+//! Which files are synced: sync roots plus include/exclude patterns
+//!
+//! The sync set starts from `sync.files` (single files) and `sync.dirs`
+//! (directory trees) relative to ~/.claude, then:
+//! - `sync.exclude` patterns drop matching files and directories
+//! - `sync.include` patterns, when given, restrict synced files to those matching
+//!
+//! Patterns use gitignore syntax: `*` and `?` don't cross `/`, `**` does, a
+//! trailing `/` only matches directories, a pattern with a `/` is anchored to
+//! ~/.claude and one without matches a name at any depth, and `!` re-includes.
+//!
+//! A `.claudesyncignore` file anywhere inside a synced directory adds patterns
+//! relative to its own directory. Like `.gitignore`, the closest file wins,
+//! then `sync.exclude`, then the built-in `DEFAULT_IGNORE` list; nothing inside an
+//! ignored directory can be re-included.
+
+use crate::config::Config;
+use crate::state::CONFLICT_COPY_MARKER;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+
+/// Name of per-directory ignore files
+pub const IGNORE_FILE_NAME: &str = ".claudesyncignore";
+
+/// Patterns applied before `sync.exclude` and ignore files, which can override them
+const DEFAULT_IGNORE: &[&str] = &[
+    ".*",
+    "!.mcp.json",
+    "!.claude-plugin/",
+    "!.claudesyncignore",
+    "__pycache__/",
+    "*.pyc",
+    "*.tmp",
+    "*~",
+];
+
+/// A single gitignore-style pattern
+#[derive(Debug, Clone)]
+struct Pattern {
+    glob: String,
+    /// Pattern contains a `/`, so it matches the full relative path
+    anchored: bool,
+    /// Pattern ends with `/`, so it only matches directories
+    dir_only: bool,
+    /// Pattern starts with `!` (re-include)
+    negated: bool,
+}
+
+impl Pattern {
+    fn parse(raw: &str) -> Option<Self> {
+        let mut glob = raw.trim();
+        if glob.is_empty() || glob.starts_with('#') {
+            return None;
+        }
+
+        let negated = glob.starts_with('!');
+        if negated {
+            glob = &glob[1..];
+        }
+
+        let dir_only = glob.ends_with('/');
+        let glob = glob.trim_end_matches('/');
+        let anchored = glob.contains('/');
+        let glob = glob.trim_start_matches('/');
+
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Self {
+            glob: glob.to_string(),
+            anchored,
+            dir_only,
+            negated,
+        })
+    }
+
+    /// Check if the pattern matches a path (relative, `/`-separated)
+    fn matches(&self, rel_path: &str, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+
+        if self.anchored {
+            glob_match(self.glob.as_bytes(), rel_path.as_bytes())
+        } else {
+            let name = rel_path.rsplit('/').next().unwrap_or(rel_path);
+            glob_match(self.glob.as_bytes(), name.as_bytes())
+        }
+    }
+}
+
+/// Ordered list of patterns where the last match wins
+#[derive(Debug, Clone, Default)]
+pub struct PatternList {
+    patterns: Vec<Pattern>,
+}
+
+impl PatternList {
+    /// Compile a list of raw patterns (blank and `#` lines are skipped)
+    pub fn new<S: AsRef<str>>(raw: &[S]) -> Self {
+        Self {
+            patterns: raw.iter().filter_map(|p| Pattern::parse(p.as_ref())).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    /// Load patterns from an ignore file; a missing file means no patterns
+    fn load(path: &Path) -> Self {
+        match fs::read_to_string(path) {
+            Ok(content) => Self::new(&content.lines().collect::<Vec<_>>()),
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Check a single path (not its ancestors): Some(true) if the last
+    /// matching pattern is a plain pattern, Some(false) if it is negated
+    fn check(&self, rel_path: &str, is_dir: bool) -> Option<bool> {
+        self.patterns
+            .iter()
+            .rev()
+            .find(|p| p.matches(rel_path, is_dir))
+            .map(|p| !p.negated)
+    }
+
+    /// Check a path and its ancestor directories, like gitignore: once a
+    /// directory matches, everything below it does too
+    pub fn matches_path(&self, rel_path: &str, is_dir: bool) -> bool {
+        let mut end = 0;
+        while let Some(offset) = rel_path[end..].find('/') {
+            end += offset;
+            if self.check(&rel_path[..end], true) == Some(true) {
+                return true;
+            }
+            end += 1;
+        }
+
+        self.check(rel_path, is_dir) == Some(true)
+    }
+}
+
+/// Patterns from a `.claudesyncignore`, relative to the directory holding it
+struct DirIgnore {
+    dir: String,
+    patterns: PatternList,
+}
+
+/// The set of synced paths
+#[derive(Debug, Clone)]
+pub struct SyncSet {
+    files: Vec<String>,
+    dirs: Vec<String>,
+    include: PatternList,
+    exclude: PatternList,
+    defaults: PatternList,
+}
+
+impl SyncSet {
+    /// Build the sync set from configuration
+    pub fn new(config: &Config) -> Self {
+        Self {
+            files: config.sync_files.clone(),
+            dirs: config.sync_dirs.clone(),
+            include: PatternList::new(&config.include),
+            exclude: PatternList::new(&config.exclude),
+            defaults: PatternList::new(DEFAULT_IGNORE),
+        }
+    }
+
+    /// Everything in one directory, for names that patterns can't match
+    /// (encrypted file names)
+    pub fn for_dir(dir: &str) -> Self {
+        Self {
+            files: Vec::new(),
+            dirs: vec![dir.to_string()],
+            include: PatternList::new::<&str>(&[]),
+            exclude: PatternList::new::<&str>(&[]),
+            defaults: PatternList::new(DEFAULT_IGNORE),
+        }
+    }
+
+    /// Configured single files
+    pub fn files(&self) -> &[String] {
+        &self.files
+    }
+
+    /// Check if a relative path (file or directory) is part of the sync set,
+    /// reading `.claudesyncignore` files under `root` (~/.claude or the sync
+    /// directory)
+    pub fn is_synced(&self, root: &Path, rel_path: &str, is_dir: bool) -> bool {
+        let rel_path = rel_path.trim_matches('/');
+        if !self.in_roots(rel_path) {
+            return false;
+        }
+
+        self.is_synced_with(rel_path, is_dir, &self.load_ignores(root, rel_path))
+    }
+
+    /// Check if a path is a configured file, a configured directory or
+    /// something inside one
+    fn in_roots(&self, rel_path: &str) -> bool {
+        self.files.iter().any(|f| f == rel_path)
+            || self.dirs.iter().any(|d| {
+                rel_path == d
+                    || rel_path
+                        .strip_prefix(d.as_str())
+                        .is_some_and(|rest| rest.starts_with('/'))
+            })
+    }
+
+    /// Load the ignore files that apply to `rel_path`: those in synced
+    /// directories above it, outermost first
+    fn load_ignores(&self, root: &Path, rel_path: &str) -> Vec<DirIgnore> {
+        let Some(sync_dir) = self.dirs.iter().find(|d| {
+            rel_path
+                .strip_prefix(d.as_str())
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        }) else {
+            return Vec::new();
+        };
+
+        let mut ignores = Vec::new();
+        let mut dir = sync_dir.clone();
+        let mut rest = &rel_path[sync_dir.len()..];
+        loop {
+            let patterns = PatternList::load(&root.join(&dir).join(IGNORE_FILE_NAME));
+            if !patterns.is_empty() {
+                ignores.push(DirIgnore {
+                    dir: dir.clone(),
+                    patterns,
+                });
+            }
+
+            // Descend one component, stopping before the path itself
+            rest = rest.trim_start_matches('/');
+            match rest.find('/') {
+                Some(end) => {
+                    dir = format!("{}/{}", dir, &rest[..end]);
+                    rest = &rest[end..];
+                }
+                None => break,
+            }
+        }
+
+        ignores
+    }
+
+    /// Check a path against roots and patterns, given the ignore files above it
+    fn is_synced_with(&self, rel_path: &str, is_dir: bool, ignores: &[DirIgnore]) -> bool {
+        if rel_path.split('/').any(|c| c.contains(CONFLICT_COPY_MARKER)) {
+            return false;
+        }
+
+        // Like gitignore: once a directory is ignored, so is everything below
+        let mut end = 0;
+        while let Some(offset) = rel_path[end..].find('/') {
+            end += offset;
+            if self.is_excluded(&rel_path[..end], true, ignores) {
+                return false;
+            }
+            end += 1;
+        }
+        if self.is_excluded(rel_path, is_dir, ignores) {
+            return false;
+        }
+
+        // The include list only restricts files; directories are walked so
+        // files inside them can match
+        is_dir || self.include.is_empty() || self.include.matches_path(rel_path, false)
+    }
+
+    /// Check a single path: the closest ignore file with a matching pattern
+    /// decides, then `sync.exclude`, then the defaults
+    fn is_excluded(&self, rel_path: &str, is_dir: bool, ignores: &[DirIgnore]) -> bool {
+        for ignore in ignores.iter().rev() {
+            let Some(sub_path) = rel_path
+                .strip_prefix(ignore.dir.as_str())
+                .and_then(|rest| rest.strip_prefix('/'))
+            else {
+                continue;
+            };
+            if let Some(excluded) = ignore.patterns.check(sub_path, is_dir) {
+                return excluded;
+            }
+        }
+
+        self.exclude
+            .check(rel_path, is_dir)
+            .or_else(|| self.defaults.check(rel_path, is_dir))
+            .unwrap_or(false)
+    }
+
+    /// Collect relative paths of all synced files under `root` (~/.claude or
+    /// the sync directory)
+    pub fn collect_files(&self, root: &Path) -> BTreeSet<String> {
+        let mut rel_paths = BTreeSet::new();
+
+        for file_name in &self.files {
+            if root.join(file_name).is_file() && self.is_synced_with(file_name, false, &[]) {
+                rel_paths.insert(file_name.clone());
+            }
+        }
+
+        for dir_name in &self.dirs {
+            let mut ignores = Vec::new();
+            self.collect_dir_files(&root.join(dir_name), dir_name, &mut ignores, &mut rel_paths);
+        }
+
+        rel_paths
+    }
+
+    /// Recursively collect synced files under a directory
+    fn collect_dir_files(
+        &self,
+        dir: &Path,
+        prefix: &str,
+        ignores: &mut Vec<DirIgnore>,
+        rel_paths: &mut BTreeSet<String>,
+    ) {
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+
+        let patterns = PatternList::load(&dir.join(IGNORE_FILE_NAME));
+        let pushed = !patterns.is_empty();
+        if pushed {
+            ignores.push(DirIgnore {
+                dir: prefix.to_string(),
+                patterns,
+            });
+        }
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let rel_path = format!("{}/{}", prefix, entry.file_name().to_string_lossy());
+
+            if path.is_file() {
+                if self.is_synced_with(&rel_path, false, ignores) {
+                    rel_paths.insert(rel_path);
+                }
+            } else if path.is_dir() && self.is_synced_with(&rel_path, true, ignores) {
+                // Recurse into subdirectory
+                self.collect_dir_files(&path, &rel_path, ignores, rel_paths);
+            }
+        }
+
+        if pushed {
+            ignores.pop();
+        }
+    }
+}
+
+/// Match a glob against text: `*` and `?` don't cross `/`, `**` does
+fn glob_match(pattern: &[u8], text: &[u8]) -> bool {
+    match pattern {
+        [] => text.is_empty(),
+        [b'*', b'*', b'/', rest @ ..] => {
+            // Zero or more leading directories
+            glob_match(rest, text)
+                || text
+                    .iter()
+                    .enumerate()
+                    .any(|(i, &c)| c == b'/' && glob_match(rest, &text[i + 1..]))
+        }
+        [b'*', b'*', rest @ ..] => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+        [b'*', rest @ ..] => {
+            let segment_end = text.iter().position(|&c| c == b'/').unwrap_or(text.len());
+            (0..=segment_end).any(|i| glob_match(rest, &text[i..]))
+        }
+        [b'?', rest @ ..] => matches!(text, [c, ..] if *c != b'/') && glob_match(rest, &text[1..]),
+        [p, rest @ ..] => matches!(text, [c, ..] if c == p) && glob_match(rest, &text[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    fn sync_set(include: &[&str], exclude: &[&str]) -> SyncSet {
+        SyncSet {
+            files: vec!["settings.json".to_string()],
+            dirs: vec!["skills".to_string(), "plugins".to_string()],
+            include: PatternList::new(include),
+            exclude: PatternList::new(exclude),
+            defaults: PatternList::new(DEFAULT_IGNORE),
+        }
+    }
+
+    fn ignore(dir: &str, patterns: &[&str]) -> DirIgnore {
+        DirIgnore {
+            dir: dir.to_string(),
+            patterns: PatternList::new(patterns),
+        }
+    }
+
+    #[test]
+    fn glob_match_cases() {
+        for (glob, text, expected) in [
+            ("*.md", "README.md", true),
+            ("*.md", "docs/README.md", false),
+            ("?.md", "a.md", true),
+            ("?.md", "ab.md", false),
+            ("a?b", "a/b", false),
+            ("skills/*", "skills/review", true),
+            ("skills/*", "skills/review/SKILL.md", false),
+            ("skills/**", "skills/review/SKILL.md", true),
+            ("**/SKILL.md", "SKILL.md", true),
+            ("**/SKILL.md", "skills/review/SKILL.md", true),
+            ("**/SKILL.md", "skills/review/MY_SKILL.md", false),
+            ("a/**/b", "a/b", true),
+            ("a/**/b", "a/x/y/b", true),
+            ("a/**/b", "a/xb", false),
+            ("*", "", true),
+            ("", "x", false),
+        ] {
+            assert_eq!(glob_match(glob.as_bytes(), text.as_bytes()), expected, "{} ~ {}", glob, text);
+        }
+    }
+
+    #[test]
+    fn pattern_matches_cases() {
+        for (raw, rel_path, is_dir, expected) in [
+            // Unanchored: a name at any depth
+            ("*.pyc", "plugins/x/__init__.pyc", false, true),
+            ("node_modules", "plugins/x/node_modules", true, true),
+            ("node_modules", "plugins/x/node_modules", false, true),
+            // Anchored by a `/` anywhere but the end
+            ("/CLAUDE.md", "CLAUDE.md", false, true),
+            ("/CLAUDE.md", "skills/CLAUDE.md", false, false),
+            ("skills/draft", "skills/draft", true, true),
+            ("skills/draft", "plugins/skills/draft", true, false),
+            ("draft", "plugins/skills/draft", true, true),
+            ("**/draft", "plugins/skills/draft", true, true),
+            ("**/draft", "draft", false, true),
+            // Trailing `/`: directories only, and not anchoring
+            ("cache/", "plugins/cache", true, true),
+            ("cache/", "plugins/cache", false, false),
+            ("plugins/cache/", "plugins/cache", true, true),
+            ("plugins/cache/", "x/plugins/cache", true, false),
+            // `!` only flips the result, the match is the same
+            ("!*.md", "notes.md", false, true),
+            ("!/.mcp.json", ".mcp.json", false, true),
+        ] {
+            let pattern = Pattern::parse(raw).unwrap();
+            assert_eq!(pattern.matches(rel_path, is_dir), expected, "{} ~ {}", raw, rel_path);
+        }
+
+        assert!(Pattern::parse("!*.md").unwrap().negated);
+        for raw in ["", "  ", "# comment", "/", "!"] {
+            assert!(Pattern::parse(raw).is_none(), "{:?}", raw);
+        }
+    }
+
+    #[test]
+    fn last_matching_pattern_wins() {
+        let list = PatternList::new(&["*.log", "!keep.log", "logs/"]);
+        assert_eq!(list.check("debug.log", false), Some(true));
+        assert_eq!(list.check("keep.log", false), Some(false));
+        assert_eq!(list.check("notes.md", false), None);
+
+        // Everything under a matching directory matches, even if re-included
+        assert!(list.matches_path("a/logs/x.md", false));
+        assert!(list.matches_path("logs/keep.log", false));
+        assert!(!list.matches_path("a/keep.log", false));
+    }
+
+    #[test]
+    fn defaults_and_exclude() {
+        let set = sync_set(&[], &["*.log", "!keep.log", "drafts/", "/skills/old"]);
+        for (rel_path, is_dir, expected) in [
+            ("skills/review/SKILL.md", false, true),
+            ("skills/.DS_Store", false, false),
+            ("plugins/.mcp.json", false, true),
+            ("plugins/x/.claude-plugin", true, true),
+            ("plugins/x/__pycache__/a.py", false, false),
+            ("skills/a.tmp", false, false),
+            ("skills/debug.log", false, false),
+            ("skills/keep.log", false, true),
+            ("skills/drafts", true, false),
+            ("skills/drafts/x.md", false, false),
+            ("skills/x/drafts", false, true),
+            ("skills/old/SKILL.md", false, false),
+            ("plugins/skills/old/SKILL.md", false, true),
+            ("skills/review/SKILL.conflict-copy-mac-20250101.md", false, false),
+        ] {
+            assert_eq!(set.is_synced_with(rel_path, is_dir, &[]), expected, "{}", rel_path);
+        }
+    }
+
+    #[test]
+    fn include_restricts_files_only() {
+        let set = sync_set(&["*.md", "plugins/**/*.json"], &["README.md"]);
+        for (rel_path, is_dir, expected) in [
+            ("skills/review", true, true),
+            ("skills/review/SKILL.md", false, true),
+            ("skills/review/run.sh", false, false),
+            ("plugins/x/config/plugin.json", false, true),
+            ("skills/x/plugin.json", false, false),
+            ("skills/README.md", false, false),
+        ] {
+            assert_eq!(set.is_synced_with(rel_path, is_dir, &[]), expected, "{}", rel_path);
+        }
+    }
+
+    #[test]
+    fn closest_ignore_file_wins() {
+        let set = sync_set(&[], &["*.draft"]);
+        let ignores = [
+            ignore("skills", &["*.bak", "!*.draft", "scratch/"]),
+            ignore("skills/review", &["!*.bak", "notes.md"]),
+        ];
+        for (rel_path, expected) in [
+            // The inner file re-includes what the outer one ignores
+            ("skills/review/a.bak", true),
+            ("skills/other/a.bak", false),
+            // The outer file overrides sync.exclude
+            ("skills/other/a.draft", true),
+            // Patterns are relative to the ignore file's directory
+            ("skills/review/notes.md", false),
+            ("skills/notes.md", true),
+            // Unanchored directory patterns apply at any depth below
+            ("skills/review/x/scratch/a.md", false),
+        ] {
+            assert_eq!(set.is_synced_with(rel_path, false, &ignores), expected, "{}", rel_path);
+        }
+    }
+
+    #[test]
+    fn nothing_is_reincluded_under_an_ignored_directory() {
+        let set = sync_set(&[], &["build/", "!build/keep.md"]);
+        let ignores = [ignore("skills", &["!build/keep.md", "!keep.md"])];
+        assert!(!set.is_synced_with("skills/build/keep.md", false, &[]));
+        assert!(!set.is_synced_with("skills/build/keep.md", false, &ignores));
+        assert!(!set.is_synced_with("skills/.hidden/keep.md", false, &ignores));
+    }
+
+    #[test]
+    fn ignore_files_are_read_from_disk() {
+        let root = TempDir::new();
+        root.write("skills/.claudesyncignore", "*.bak\nprivate/\n");
+        root.write("skills/review/.claudesyncignore", "!*.bak\n");
+        for file in [
+            "settings.json",
+            "notes.md",
+            "skills/a.bak",
+            "skills/review/SKILL.md",
+            "skills/review/b.bak",
+            "skills/private/secret.md",
+        ] {
+            root.write(file, "x");
+        }
+        let set = sync_set(&[], &[]);
+
+        assert!(set.is_synced(root.path(), "skills/review/b.bak", false));
+        assert!(!set.is_synced(root.path(), "skills/a.bak", false));
+        assert!(!set.is_synced(root.path(), "skills/private", true));
+        assert!(!set.is_synced(root.path(), "notes.md", false));
+
+        let files: Vec<String> = set.collect_files(root.path()).into_iter().collect();
+        assert_eq!(
+            files,
+            [
+                "settings.json",
+                "skills/.claudesyncignore",
+                "skills/review/.claudesyncignore",
+                "skills/review/SKILL.md",
+                "skills/review/b.bak",
+            ]
+        );
+    }
+}
@@ -0,0 +1,466 @@
+//! Git repository backend
+//!
+//! With `provider = "git"`, the sync directory lives in a git working tree
+//! instead of a cloud folder. Each sync first merges the other machines'
+//! commits from the remote, then commits what it copied (authored by this
+//! machine) and pushes. Files that conflict in the merge get the merge base
+//! as their baseline, so the engine sees them as changed on both sides and
+//! resolves them with the configured conflict strategy.
+//!
+//! Git runs as a subprocess, so the user's credentials, SSH config and hooks
+//! apply.
+
+use crate::config::{Config, GitConfig};
+use anyhow::{bail, Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A file both sides changed in a merge
+#[derive(Debug)]
+pub struct MergeConflict {
+    /// Path relative to the sync directory
+    pub rel_path: String,
+    /// Content at the merge base (None if the file didn't exist there)
+    pub base: Option<Vec<u8>>,
+}
+
+/// Outcome of merging the remote branch
+#[derive(Debug, Default)]
+pub struct PullResult {
+    /// The merge brought in new commits
+    pub updated: bool,
+    /// Files changed on both sides; the working tree has the remote version
+    pub conflicts: Vec<MergeConflict>,
+}
+
+/// Git working tree holding the sync directory
+pub struct GitRepo {
+    work_tree: PathBuf,
+    /// Sync directory relative to the work tree ("" for the root)
+    prefix: String,
+    remote: String,
+    branch: String,
+    author: String,
+}
+
+impl GitRepo {
+    /// Open the working tree at the sync base, cloning or initializing it if
+    /// needed
+    pub fn open(config: &Config) -> Result<Self> {
+        let git = &config.git;
+        let work_tree = config.target.base.clone();
+        let prefix = config
+            .target
+            .dir
+            .strip_prefix(&work_tree)
+            .map(|p| p.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let repo = Self {
+            work_tree,
+            prefix,
+            remote: git.remote.clone(),
+            branch: git.branch.clone(),
+            author: Config::machine_id(),
+        };
+        repo.init(git)?;
+
+        Ok(repo)
+    }
+
+    /// Make sure the work tree is a repository with the configured remote
+    fn init(&self, git: &GitConfig) -> Result<()> {
+        if !self.work_tree.join(".git").exists() {
+            match &git.url {
+                Some(url) if !self.work_tree.exists() => {
+                    log::info!("Cloning {} into {:?}", url, self.work_tree);
+                    let parent = self.work_tree.parent().unwrap_or(Path::new("."));
+                    fs::create_dir_all(parent)?;
+                    let output = Command::new("git")
+                        .args(["clone", "--origin", &self.remote, url])
+                        .arg(&self.work_tree)
+                        .current_dir(parent)
+                        .output()
+                        .context("Failed to run git")?;
+                    check(&output, "git clone")?;
+                }
+                _ if self.work_tree.is_dir() => {
+                    log::info!("Initializing git repository in {:?}", self.work_tree);
+                    self.git(&["init", "--quiet"])?;
+                }
+                _ => bail!(
+                    "Git working tree does not exist: {:?} (set git.url to clone it)",
+                    self.work_tree
+                ),
+            }
+        }
+
+        // A fresh clone of an empty remote, or a new repository, has no
+        // commits yet; start the configured branch
+        if self.run(&["rev-parse", "--verify", "--quiet", "HEAD"])?.status.success() {
+            return self.ensure_remote(git);
+        }
+        self.git(&["symbolic-ref", "HEAD", &format!("refs/heads/{}", self.branch)])?;
+
+        self.ensure_remote(git)
+    }
+
+    /// Add the remote if a URL is configured and the repository lacks it
+    fn ensure_remote(&self, git: &GitConfig) -> Result<()> {
+        if let Some(url) = &git.url {
+            if !self.has_remote()? {
+                self.git(&["remote", "add", &self.remote, url])?;
+            }
+        }
+        Ok(())
+    }
+
+    fn has_remote(&self) -> Result<bool> {
+        Ok(self.git(&["remote"])?.lines().any(|r| r == self.remote))
+    }
+
+    /// Name of the remote-tracking ref for the branch
+    fn remote_ref(&self) -> String {
+        format!("refs/remotes/{}/{}", self.remote, self.branch)
+    }
+
+    /// Fetch the remote branch; false if there is no remote or no such branch
+    pub fn fetch(&self) -> Result<bool> {
+        if !self.has_remote()? {
+            return Ok(false);
+        }
+
+        let refspec = format!("+refs/heads/{}:{}", self.branch, self.remote_ref());
+        let output = self.run(&["fetch", "--quiet", &self.remote, &refspec])?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            if stderr.contains("couldn't find remote ref") {
+                // Nobody has pushed this branch yet
+                return Ok(false);
+            }
+            bail!("git fetch failed: {}", stderr.trim());
+        }
+
+        Ok(true)
+    }
+
+    /// Check if the fetched remote branch has commits we haven't merged
+    pub fn is_behind(&self) -> Result<bool> {
+        let Some(remote_head) = self.rev_parse(&self.remote_ref())? else {
+            return Ok(false);
+        };
+        match self.rev_parse("HEAD")? {
+            Some(head) => Ok(!self
+                .run(&["merge-base", "--is-ancestor", &remote_head, &head])?
+                .status
+                .success()),
+            None => Ok(true),
+        }
+    }
+
+    /// Commit leftovers, fetch and merge the remote branch
+    pub fn pull(&self) -> Result<PullResult> {
+        self.commit("Uncommitted changes")?;
+
+        if !self.fetch()? || !self.is_behind()? {
+            return Ok(PullResult::default());
+        }
+
+        let remote_ref = self.remote_ref();
+        if self.rev_parse("HEAD")?.is_none() {
+            // Nothing local yet: just take the remote branch
+            self.git(&["reset", "--quiet", "--hard", &remote_ref])?;
+            return Ok(PullResult {
+                updated: true,
+                conflicts: Vec::new(),
+            });
+        }
+
+        let output = self.run_as_author(&[
+            "merge",
+            "--quiet",
+            "--no-edit",
+            "--allow-unrelated-histories",
+            &remote_ref,
+        ])?;
+        if output.status.success() {
+            return Ok(PullResult {
+                updated: true,
+                conflicts: Vec::new(),
+            });
+        }
+
+        let conflicted: Vec<String> = self
+            .git(&["diff", "--name-only", "--diff-filter=U", "-z"])?
+            .split('\0')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string())
+            .collect();
+        if conflicted.is_empty() {
+            self.run(&["merge", "--abort"])?;
+            bail!("git merge failed: {}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+
+        // Take the remote side in the working tree and hand the conflict to
+        // the engine, which still has this machine's version in ~/.claude
+        let mut conflicts = Vec::new();
+        for repo_path in conflicted {
+            let base = self.show_stage(1, &repo_path)?;
+            match self.show_stage(3, &repo_path)? {
+                Some(theirs) => {
+                    fs::write(self.work_tree.join(&repo_path), theirs)?;
+                    self.git(&["add", "--", &repo_path])?;
+                }
+                None => {
+                    self.git(&["rm", "--quiet", "--", &repo_path])?;
+                }
+            }
+
+            if let Some(rel_path) = self.rel_path(&repo_path) {
+                conflicts.push(MergeConflict { rel_path, base });
+            }
+        }
+        self.run_as_author(&["commit", "--quiet", "--no-edit"])
+            .and_then(|output| check(&output, "git commit"))?;
+
+        Ok(PullResult {
+            updated: true,
+            conflicts,
+        })
+    }
+
+    /// Commit all changes in the work tree, returning the changed paths
+    /// (None if there was nothing to commit)
+    pub fn commit(&self, summary: &str) -> Result<Option<Vec<String>>> {
+        self.git(&["add", "--all"])?;
+
+        let changed: Vec<String> = self
+            .git(&["diff", "--cached", "--name-only", "-z"])?
+            .split('\0')
+            .filter(|p| !p.is_empty())
+            .map(|p| p.to_string())
+            .collect();
+        if changed.is_empty() {
+            return Ok(None);
+        }
+
+        let message = format!("{} ({})\n\n{}\n", summary, self.author, changed.join("\n"));
+        self.run_as_author(&["commit", "--quiet", "--message", &message])
+            .and_then(|output| check(&output, "git commit"))?;
+
+        Ok(Some(changed))
+    }
+
+    /// Push the branch if it has commits the remote doesn't
+    pub fn push(&self) -> Result<()> {
+        if !self.has_remote()? || self.rev_parse("HEAD")?.is_none() {
+            return Ok(());
+        }
+
+        if let Some(remote_head) = self.rev_parse(&self.remote_ref())? {
+            let ahead = self.git(&["rev-list", "--count", &format!("{}..HEAD", remote_head)])?;
+            if ahead.trim() == "0" {
+                return Ok(());
+            }
+        }
+
+        let refspec = format!("HEAD:refs/heads/{}", self.branch);
+        let output = self.run(&["push", "--quiet", &self.remote, &refspec])?;
+        check(&output, "git push")?;
+
+        // Keep the remote-tracking ref current so the next push check is right
+        self.git(&["update-ref", &self.remote_ref(), "HEAD"])?;
+        Ok(())
+    }
+
+    /// Commit time (Unix seconds) of the last commit touching a file in the
+    /// sync directory
+    pub fn commit_time(&self, rel_path: &str) -> Option<i64> {
+        let repo_path = if self.prefix.is_empty() {
+            rel_path.to_string()
+        } else {
+            format!("{}/{}", self.prefix, rel_path)
+        };
+        self.git(&["log", "-1", "--format=%ct", "--", &repo_path])
+            .ok()
+            .and_then(|out| out.trim().parse().ok())
+    }
+
+    /// Map a repository path to a path relative to the sync directory
+    fn rel_path(&self, repo_path: &str) -> Option<String> {
+        if self.prefix.is_empty() {
+            return Some(repo_path.to_string());
+        }
+        repo_path
+            .strip_prefix(self.prefix.as_str())
+            .and_then(|rest| rest.strip_prefix('/'))
+            .map(|rest| rest.to_string())
+    }
+
+    /// Content of a path at a merge stage (1 = base, 2 = ours, 3 = theirs)
+    fn show_stage(&self, stage: u8, repo_path: &str) -> Result<Option<Vec<u8>>> {
+        let output = self.run(&["show", &format!(":{}:{}", stage, repo_path)])?;
+        Ok(output.status.success().then_some(output.stdout))
+    }
+
+    /// Resolve a revision to a commit id
+    fn rev_parse(&self, rev: &str) -> Result<Option<String>> {
+        let output = self.run(&["rev-parse", "--verify", "--quiet", rev])?;
+        Ok(output
+            .status
+            .success()
+            .then(|| String::from_utf8_lossy(&output.stdout).trim().to_string()))
+    }
+
+    /// Run git and return stdout, failing on a non-zero exit
+    fn git(&self, args: &[&str]) -> Result<String> {
+        let output = self.run(args)?;
+        check(&output, &format!("git {}", args.first().unwrap_or(&"")))?;
+        Ok(String::from_utf8_lossy(&output.stdout).to_string())
+    }
+
+    /// Run git in the work tree
+    fn run(&self, args: &[&str]) -> Result<Output> {
+        Command::new("git")
+            .args(args)
+            .current_dir(&self.work_tree)
+            .output()
+            .context("Failed to run git")
+    }
+
+    /// Run git with this machine as author and committer
+    fn run_as_author(&self, args: &[&str]) -> Result<Output> {
+        let email = format!("{}@claude-sync", self.author);
+        Command::new("git")
+            .args(args)
+            .current_dir(&self.work_tree)
+            .env("GIT_AUTHOR_NAME", &self.author)
+            .env("GIT_AUTHOR_EMAIL", &email)
+            .env("GIT_COMMITTER_NAME", &self.author)
+            .env("GIT_COMMITTER_EMAIL", &email)
+            .output()
+            .context("Failed to run git")
+    }
+}
+
+/// Turn a failed git invocation into an error with its stderr
+fn check(output: &Output, what: &str) -> Result<()> {
+    if !output.status.success() {
+        bail!("{} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sync::{SyncDirection, SyncEngine};
+    use crate::testutil::TempDir;
+
+    /// Engine for a machine whose home is `dir`/`machine`, syncing through
+    /// a clone of `dir`/remote.git
+    fn machine(dir: &TempDir, machine: &str) -> SyncEngine {
+        let home = dir.path().join(machine);
+        fs::create_dir_all(home.join(".claude")).unwrap();
+        let config = Config::for_test(
+            &home,
+            &format!(
+                "[paths]\nprovider = \"git\"\nbase = {:?}\nfolder = \"Sync\"\n\n\
+                 [conflicts]\nstrategy = \"local\"\n\n[git]\nurl = {:?}\n",
+                home.join("repo").to_string_lossy(),
+                dir.path().join("remote.git").to_string_lossy()
+            ),
+        );
+        SyncEngine::new(config).unwrap()
+    }
+
+    fn bare_remote(dir: &TempDir) {
+        let output = Command::new("git")
+            .args(["init", "--quiet", "--bare", "remote.git"])
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        check(&output, "git init").unwrap();
+    }
+
+    /// Author and message of each commit on the remote branch, newest first
+    fn remote_log(dir: &TempDir) -> Vec<(String, String)> {
+        let output = Command::new("git")
+            .args(["log", "--format=%an%x00%B%x00", "main"])
+            .current_dir(dir.path().join("remote.git"))
+            .output()
+            .unwrap();
+        check(&output, "git log").unwrap();
+        String::from_utf8_lossy(&output.stdout)
+            .split_terminator("\0\n")
+            .map(|entry| {
+                let (author, message) = entry.split_once('\0').unwrap();
+                (author.to_string(), message.trim().to_string())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn each_sync_is_one_commit() {
+        let dir = TempDir::new();
+        bare_remote(&dir);
+        let a = machine(&dir, "a");
+        let b = machine(&dir, "b");
+        dir.write("a/.claude/settings.json", "{}\n");
+        dir.write("a/.claude/CLAUDE.md", "Be brief\n");
+
+        a.sync(SyncDirection::Bidirectional, None).unwrap();
+        let log = remote_log(&dir);
+        assert_eq!(log.len(), 1);
+        assert_eq!(log[0].0, Config::machine_id());
+        assert_eq!(
+            log[0].1,
+            format!("Sync ({})\n\nSync/CLAUDE.md\nSync/settings.json", Config::machine_id())
+        );
+
+        // Pulling the commit doesn't make another one
+        b.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("b/.claude/CLAUDE.md")).unwrap(), "Be brief\n");
+        assert_eq!(remote_log(&dir).len(), 1);
+
+        dir.write("b/.claude/CLAUDE.md", "Be very brief\n");
+        b.sync(SyncDirection::Bidirectional, None).unwrap();
+        let log = remote_log(&dir);
+        assert_eq!(log.len(), 2);
+        assert!(log[0].1.ends_with("\n\nSync/CLAUDE.md"));
+    }
+
+    #[test]
+    fn diverging_edits_conflict() {
+        let dir = TempDir::new();
+        bare_remote(&dir);
+        let a = machine(&dir, "a");
+        let b = machine(&dir, "b");
+        dir.write("a/.claude/CLAUDE.md", "Be brief\n");
+        a.sync(SyncDirection::Bidirectional, None).unwrap();
+        b.sync(SyncDirection::Bidirectional, None).unwrap();
+
+        // Both machines edit the file; b's sync commits its edit while the
+        // remote is unreachable, so the next pull has to merge
+        dir.write("a/.claude/CLAUDE.md", "Edited on a\n");
+        a.sync(SyncDirection::Bidirectional, None).unwrap();
+        let remote = dir.path().join("remote.git");
+        let offline = dir.path().join("offline.git");
+        fs::rename(&remote, &offline).unwrap();
+        dir.write("b/.claude/CLAUDE.md", "Edited on b\n");
+        let result = b.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert!(result.conflicts.is_empty());
+        assert_eq!(result.warnings.len(), 2);
+        fs::rename(&offline, &remote).unwrap();
+
+        let result = b.sync(SyncDirection::Bidirectional, None).unwrap();
+        let paths: Vec<&str> = result.conflicts.iter().map(|c| c.rel_path.as_str()).collect();
+        assert_eq!(paths, ["CLAUDE.md"]);
+        assert!(result.warnings.is_empty());
+
+        // b kept its own version and shared it
+        a.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(fs::read_to_string(dir.path().join("a/.claude/CLAUDE.md")).unwrap(), "Edited on b\n");
+    }
+}
@@ -0,0 +1,294 @@
+//! Persistent cache of file checksums
+//!
+//! A sync hashes the same files several times (change detection, the backup
+//! snapshot before and after, the remote folder listing), and every sync
+//! hashes every synced file again. The cache keeps each file's SHA-256 with
+//! its size, mtime (nanoseconds) and inode and reuses it while those are
+//! unchanged. It is shared by the whole process and stored in ~/.claude.
+//!
+//! Files modified in the last `MIN_AGE` aren't cached: another write within
+//! the filesystem's timestamp granularity would go unnoticed.
+
+use crate::remote::write_atomic;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How old a modification must be before its checksum is cached
+const MIN_AGE: Duration = Duration::from_secs(2);
+
+/// The process-wide cache (None until `init`: hash every time)
+static CACHE: Mutex<Option<HashCache>> = Mutex::new(None);
+
+/// What a cached checksum is valid for
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct FileKey {
+    size: u64,
+    mtime_ns: i64,
+    inode: u64,
+}
+
+impl FileKey {
+    fn of(metadata: &fs::Metadata) -> Self {
+        let mtime_ns = metadata
+            .modified()
+            .ok()
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+        Self {
+            size: metadata.len(),
+            mtime_ns,
+            inode: inode(metadata),
+        }
+    }
+
+    /// Whether the file was last modified long enough ago to cache it
+    fn is_settled(&self) -> bool {
+        let cutoff = SystemTime::now()
+            .checked_sub(MIN_AGE)
+            .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i64)
+            .unwrap_or(0);
+        self.mtime_ns < cutoff
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CachedHash {
+    #[serde(flatten)]
+    key: FileKey,
+    sha256: String,
+}
+
+struct HashCache {
+    path: PathBuf,
+    /// Rehash every file, only refreshing the cache (`--verify`)
+    verify: bool,
+    /// Absolute path -> checksum
+    entries: HashMap<PathBuf, CachedHash>,
+    changed: bool,
+}
+
+/// Load the cache from `path` and use it for all checksums from now on
+///
+/// With `verify`, cached checksums are ignored (and replaced by freshly
+/// computed ones).
+pub fn init(path: PathBuf, verify: bool) {
+    let entries = fs::read_to_string(&path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    *CACHE.lock().unwrap() = Some(HashCache {
+        path,
+        verify,
+        entries,
+        changed: false,
+    });
+}
+
+/// SHA-256 of a file, from the cache if it hasn't changed since it was hashed
+pub fn sha256_file(path: &Path) -> Result<String> {
+    cached_sha256(&CACHE, path)
+}
+
+fn cached_sha256(cache: &Mutex<Option<HashCache>>, path: &Path) -> Result<String> {
+    let metadata = fs::metadata(path).with_context(|| format!("Failed to get metadata for: {:?}", path))?;
+    let key = FileKey::of(&metadata);
+
+    let cached = cache.lock().unwrap().as_ref().and_then(|cache| {
+        cache
+            .entries
+            .get(path)
+            .filter(|entry| entry.key == key)
+            .map(|entry| (entry.sha256.clone(), cache.verify))
+    });
+    if let Some((sha256, false)) = &cached {
+        return Ok(sha256.clone());
+    }
+
+    let sha256 = hash_file(path)?;
+    if let Some((stale, true)) = &cached {
+        if *stale != sha256 {
+            log::warn!("Cached checksum of {:?} was out of date", path);
+        }
+    }
+
+    // Only cache what was hashed from an unchanged, settled file
+    let unchanged = fs::metadata(path).is_ok_and(|metadata| FileKey::of(&metadata) == key);
+    if unchanged && key.is_settled() {
+        if let Some(cache) = cache.lock().unwrap().as_mut() {
+            cache.entries.insert(
+                path.to_path_buf(),
+                CachedHash {
+                    key,
+                    sha256: sha256.clone(),
+                },
+            );
+            cache.changed = true;
+        }
+    }
+
+    Ok(sha256)
+}
+
+/// Write the cache back to disk if it changed, dropping files that are gone
+pub fn save() {
+    save_cache(&CACHE);
+}
+
+fn save_cache(cache: &Mutex<Option<HashCache>>) {
+    let mut guard = cache.lock().unwrap();
+    let Some(cache) = guard.as_mut().filter(|cache| cache.changed) else {
+        return;
+    };
+
+    cache.entries.retain(|path, _| path.is_file());
+    let result = serde_json::to_string(&cache.entries)
+        .map_err(anyhow::Error::from)
+        .and_then(|content| write_atomic(&cache.path, content.as_bytes(), None));
+    match result {
+        Ok(()) => cache.changed = false,
+        Err(e) => log::warn!("Failed to save hash cache {:?}: {}", cache.path, e),
+    }
+}
+
+/// Compute SHA-256 of a file
+fn hash_file(path: &Path) -> Result<String> {
+    let file = File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    let mut hasher = Sha256::new();
+    let mut buffer = [0u8; 8192];
+
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+#[cfg(unix)]
+fn inode(metadata: &fs::Metadata) -> u64 {
+    use std::os::unix::fs::MetadataExt;
+    metadata.ino()
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &fs::Metadata) -> u64 {
+    0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    /// Cache as loaded by `init`, without the process-wide instance
+    fn cache(dir: &TempDir, verify: bool) -> Mutex<Option<HashCache>> {
+        Mutex::new(Some(HashCache {
+            path: dir.path().join("cache.json"),
+            verify,
+            entries: HashMap::new(),
+            changed: false,
+        }))
+    }
+
+    /// Write `content` to `name`, last modified a minute ago
+    fn settled_file(dir: &TempDir, name: &str, content: &str) -> PathBuf {
+        let path = dir.write(name, content);
+        let minute_ago = SystemTime::now() - Duration::from_secs(60);
+        File::options().write(true).open(&path).unwrap().set_modified(minute_ago).unwrap();
+        path
+    }
+
+    fn sha256(content: &str) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    #[test]
+    fn recent_modifications_are_not_settled() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap();
+        let key = |age: Duration| FileKey {
+            size: 1,
+            mtime_ns: (now - age).as_nanos() as i64,
+            inode: 1,
+        };
+        assert!(!key(Duration::ZERO).is_settled());
+        assert!(!key(Duration::from_millis(500)).is_settled());
+        assert!(key(Duration::from_secs(3)).is_settled());
+
+        // In the future (clock skew)
+        let future = FileKey {
+            mtime_ns: (now + Duration::from_secs(60)).as_nanos() as i64,
+            ..key(Duration::ZERO)
+        };
+        assert!(!future.is_settled());
+    }
+
+    #[test]
+    fn unchanged_files_are_not_rehashed() {
+        let dir = TempDir::new();
+        let cache = cache(&dir, false);
+        let path = settled_file(&dir, "settings.json", "{}");
+
+        assert_eq!(cached_sha256(&cache, &path).unwrap(), sha256("{}"));
+        cache.lock().unwrap().as_mut().unwrap().entries.get_mut(&path).unwrap().sha256 = "cached".to_string();
+        assert_eq!(cached_sha256(&cache, &path).unwrap(), "cached");
+
+        // Any change to the file's metadata invalidates the entry
+        let path = settled_file(&dir, "settings.json", "{ }");
+        assert_eq!(cached_sha256(&cache, &path).unwrap(), sha256("{ }"));
+    }
+
+    #[test]
+    fn recently_modified_files_are_not_cached() {
+        let dir = TempDir::new();
+        let cache = cache(&dir, false);
+        let path = dir.write("CLAUDE.md", "Be brief\n");
+
+        assert_eq!(cached_sha256(&cache, &path).unwrap(), sha256("Be brief\n"));
+        assert!(cache.lock().unwrap().as_ref().unwrap().entries.is_empty());
+    }
+
+    #[test]
+    fn save_keeps_entries_of_existing_files() {
+        let dir = TempDir::new();
+        let cache = cache(&dir, false);
+        let kept = settled_file(&dir, "settings.json", "{}");
+        let deleted = settled_file(&dir, "CLAUDE.md", "Be brief\n");
+        cached_sha256(&cache, &kept).unwrap();
+        cached_sha256(&cache, &deleted).unwrap();
+        fs::remove_file(&deleted).unwrap();
+
+        save_cache(&cache);
+        let saved: HashMap<PathBuf, CachedHash> =
+            serde_json::from_str(&fs::read_to_string(dir.path().join("cache.json")).unwrap()).unwrap();
+        assert_eq!(saved.keys().collect::<Vec<_>>(), [&kept]);
+        assert_eq!(saved[&kept].sha256, sha256("{}"));
+    }
+
+    #[test]
+    fn verify_rehashes_and_refreshes_entries() {
+        let dir = TempDir::new();
+        let cache = cache(&dir, true);
+        let path = settled_file(&dir, "CLAUDE.md", "Be brief\n");
+
+        cached_sha256(&cache, &path).unwrap();
+        cache.lock().unwrap().as_mut().unwrap().entries.get_mut(&path).unwrap().sha256 = "stale".to_string();
+        assert_eq!(cached_sha256(&cache, &path).unwrap(), sha256("Be brief\n"));
+        assert_eq!(
+            cache.lock().unwrap().as_ref().unwrap().entries[&path].sha256,
+            sha256("Be brief\n")
+        );
+    }
+}
@@ -10,8 +10,8 @@
 //! - backup-first workflow
 
 use anyhow::{bail, Context, Result};
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
 
 /// Local process lock (prevents multiple daemons on same machine)
 pub struct ProcessLock {
@@ -27,7 +27,6 @@ impl ProcessLock {
     /// Attempt to acquire the process lock
     ///
     /// Returns Ok(()) if lock acquired, Err if another process holds it
-    #[allow(dead_code)]
     pub fn acquire(&self) -> Result<()> {
         // Ensure parent directory exists
         if let Some(parent) = self.lock_path.parent() {
@@ -100,6 +99,44 @@ impl Drop for ProcessLock {
     }
 }
 
+/// Lock held for the length of each sync, and while backups are restored or
+/// conflicts resolved, so those never overlap a sync
+///
+/// A lock on an open file rather than a PID file: the operating system
+/// releases it when the holder exits, however it exits.
+pub struct SyncLock {
+    _file: File,
+}
+
+impl SyncLock {
+    /// Take the lock, waiting for the current holder to release it (after
+    /// calling `waiting`)
+    pub fn acquire(path: &Path, waiting: impl FnOnce()) -> Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)
+            .with_context(|| format!("Failed to open sync lock: {:?}", path))?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                waiting();
+                file.lock().with_context(|| format!("Failed to take sync lock: {:?}", path))?;
+            }
+            Err(TryLockError::Error(e)) => {
+                return Err(e).with_context(|| format!("Failed to take sync lock: {:?}", path));
+            }
+        }
+
+        Ok(Self { _file: file })
+    }
+}
+
 /// Check if a process with the given PID exists
 #[cfg(unix)]
 fn process_exists(pid: u32) -> bool {
@@ -112,3 +149,35 @@ fn process_exists(_pid: u32) -> bool {
     // Conservative fallback: assume process exists
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn sync_lock_waits_for_the_holder() {
+        let dir = TempDir::new();
+        let path = dir.path().join(".sync.lock");
+        let held = SyncLock::acquire(&path, || panic!("nobody holds the lock")).unwrap();
+
+        let (tx, rx) = mpsc::channel();
+        let waiter = {
+            let path = path.clone();
+            thread::spawn(move || {
+                let waited = tx.clone();
+                let _lock = SyncLock::acquire(&path, move || waited.send("waiting").unwrap()).unwrap();
+                tx.send("acquired").unwrap();
+            })
+        };
+
+        assert_eq!(rx.recv().unwrap(), "waiting");
+        assert!(rx.recv_timeout(Duration::from_millis(200)).is_err());
+        drop(held);
+        assert_eq!(rx.recv().unwrap(), "acquired");
+        waiter.join().unwrap();
+    }
+}
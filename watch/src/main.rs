@@ -4,25 +4,53 @@
 //!   claude-sync-watch              # Run daemon in foreground
 //!   claude-sync-watch --daemon     # Daemonize (for launchd)
 //!   claude-sync-watch --once       # Single sync pass (no watch)
+//!   claude-sync-watch --once --verify  # Single sync pass, rehashing every file
 //!   claude-sync-watch --status     # Show sync status
-
+//!   claude-sync-watch resolve      # Interactively resolve conflicts
+//!   claude-sync-watch init-encryption  # Set up the encryption key
+//!   claude-sync-watch backups list     # List backup snapshots
+//!   claude-sync-watch backups restore <id>  # Restore a backup snapshot
+//!   claude-sync-watch backups undo     # Undo the last sync or restore
+//!   claude-sync-watch backups prune --dry-run  # List backups the retention policy drops
+
+mod backup;
+mod baseline;
 mod config;
+mod echo;
+mod encryption;
+mod filter;
+mod git;
+mod hashcache;
 mod lock;
+mod merge;
+mod overrides;
+mod remote;
+mod resolve;
+mod restore;
+mod s3;
+mod secrets;
+mod sftp;
 mod state;
 mod sync;
+mod target;
+#[cfg(test)]
+mod testutil;
+mod transform;
 mod watcher;
+mod webdav;
 
 use anyhow::Result;
-use clap::Parser;
+use clap::{Parser, Subcommand};
 use config::Config;
 use lock::ProcessLock;
+use target::ProviderKind;
 use watcher::SyncWatcher;
 
 /// Two-way file watching sync daemon for Claude Code configuration
 #[derive(Parser, Debug)]
 #[command(name = "claude-sync-watch")]
 #[command(version = "0.1.0")]
-#[command(about = "Bidirectional sync daemon for Claude Code config via Dropbox")]
+#[command(about = "Bidirectional sync daemon for Claude Code config via Dropbox, iCloud Drive, OneDrive, Google Drive or Syncthing")]
 struct Args {
     /// Run as a daemon (for launchd integration)
     #[arg(long)]
@@ -40,43 +68,135 @@ struct Args {
     #[arg(long)]
     validate: bool,
 
-    /// Set log level (debug, info, warn, error)
-    #[arg(long, default_value = "info")]
-    log_level: String,
+    /// Apply deletions even if the remote is empty or most tracked files
+    /// would be deleted
+    #[arg(long)]
+    allow_mass_delete: bool,
+
+    /// Rehash every file instead of trusting cached checksums
+    #[arg(long, global = true)]
+    verify: bool,
+
+    /// Set log level (debug, info, warn, error), overriding the config file
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Review and resolve conflicting files interactively
+    Resolve,
+    /// Derive the encryption key from a passphrase and store it on this machine
+    InitEncryption,
+    /// Manage backup snapshots
+    Backups {
+        #[command(subcommand)]
+        command: BackupsCommand,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum BackupsCommand {
+    /// List backup snapshots and how each differs from the current files
+    List,
+    /// Show the files in a snapshot and how each differs from the current one
+    Show {
+        /// Snapshot id (or a unique prefix)
+        id: String,
+    },
+    /// Show what changed in ~/.claude since a snapshot
+    Diff {
+        /// Snapshot id (or a unique prefix)
+        id: String,
+        /// Only these files or directories (relative to ~/.claude)
+        paths: Vec<String>,
+    },
+    /// Put files back as they were in a snapshot
+    Restore {
+        /// Snapshot id (or a unique prefix)
+        id: String,
+        /// Only these files or directories (relative to ~/.claude); everything if none
+        paths: Vec<String>,
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Restore the snapshot taken before the last sync that changed local files (or the last restore)
+    Undo {
+        /// Don't ask for confirmation
+        #[arg(short, long)]
+        yes: bool,
+    },
+    /// Remove the backups the retention policy ([backups] in config.toml) doesn't keep
+    Prune {
+        /// List what would be removed without removing anything
+        #[arg(long)]
+        dry_run: bool,
+    },
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    // Initialize logging
-    let log_level = match args.log_level.to_lowercase().as_str() {
+    // Initialize logging. The level is set from the command line, or from
+    // the config file once it's loaded.
+    let cli_log_level = args.log_level.as_deref().map(|level| match level.to_lowercase().as_str() {
         "debug" => log::LevelFilter::Debug,
         "warn" => log::LevelFilter::Warn,
         "error" => log::LevelFilter::Error,
         _ => log::LevelFilter::Info,
-    };
+    });
 
     env_logger::Builder::new()
-        .filter_level(log_level)
+        .filter_level(log::LevelFilter::Trace)
         .format_timestamp_secs()
         .init();
+    log::set_max_level(cli_log_level.unwrap_or(log::LevelFilter::Info));
 
     // Load configuration
-    let config = match Config::load() {
+    let mut config = match Config::load() {
         Ok(c) => c,
         Err(e) => {
-            log::error!("Failed to load configuration: {}", e);
-            log::error!("Run claude-sync-setup.sh --config to configure Dropbox location");
+            log::error!("Failed to load configuration: {:#}", e);
+            log::error!("Run claude-sync-setup.sh --config to configure the sync folder location");
             std::process::exit(1);
         }
     };
 
+    if cli_log_level.is_none() {
+        log::set_max_level(config.log_level.to_level_filter());
+    }
+
+    hashcache::init(config.local_hash_cache_path(), args.verify);
+    config.allow_mass_delete = args.allow_mass_delete;
+
     log::info!("Claude Sync Watch v0.1.0");
     log::info!("Machine ID: {}", Config::machine_id());
     log::debug!("Local:   {:?}", config.claude_dir);
-    log::debug!("Dropbox: {:?}", config.dropbox_claude_dir);
+    if let Some(dir) = config.target.local_dir() {
+        log::debug!("{}: {:?}", config.target.name(), dir);
+    }
 
     // Handle commands
+    match args.command {
+        Some(Command::Resolve) => return resolve::run(&config),
+        Some(Command::InitEncryption) => return encryption::init(&config),
+        Some(Command::Backups { command }) => {
+            return match command {
+                BackupsCommand::List => restore::list(&config),
+                BackupsCommand::Show { id } => restore::show(&config, &id),
+                BackupsCommand::Diff { id, paths } => restore::diff(&config, &id, &paths),
+                BackupsCommand::Restore { id, paths, yes } => restore::restore(&config, &id, &paths, yes),
+                BackupsCommand::Undo { yes } => restore::undo(&config, yes),
+                BackupsCommand::Prune { dry_run } => restore::prune(&config, dry_run),
+            }
+        }
+        None => {}
+    }
+
     if args.validate {
         return validate_config(&config);
     }
@@ -95,6 +215,10 @@ fn main() -> Result<()> {
         log::info!("Running in daemon mode");
     }
 
+    // Held while watching, so other commands can find the daemon
+    let process_lock = ProcessLock::new(config.local_lock_path());
+    process_lock.acquire()?;
+
     let watcher = SyncWatcher::new(config)?;
     watcher.run()
 }
@@ -102,28 +226,66 @@ fn main() -> Result<()> {
 /// Validate configuration
 fn validate_config(config: &Config) -> Result<()> {
     println!("Configuration:");
-    println!("  Dropbox base: {:?}", config.dropbox_base);
-    println!("  Dropbox sync: {:?}", config.dropbox_claude_dir);
+    println!("  Provider:     {}", config.target.name());
+    if config.target.local_dir().is_some() {
+        println!("  Sync base:    {:?}", config.target.base);
+        println!("  Sync dir:     {:?}", config.target.dir);
+    } else if config.target.kind == ProviderKind::S3 {
+        println!("  Endpoint:     {}", config.s3.endpoint.as_deref().unwrap_or("AWS"));
+        println!("  Bucket:       s3://{}/{}/", config.s3.bucket, config.folder);
+    } else if config.target.kind == ProviderKind::Webdav {
+        println!("  URL:          {}/{}/", config.webdav.url.trim_end_matches('/'), config.folder);
+    } else {
+        println!("  Server:       {} ({})", config.sftp.host, config.sftp.path);
+    }
     println!("  Local config: {:?}", config.claude_dir);
     println!("  Debounce:     {:.1}s", config.debounce_secs);
     println!("  Max batch:    {:.1}s", config.max_batch_secs);
     println!("  Conflict:     {:?}", config.conflict_strategy);
+    if config.encryption.enabled {
+        println!(
+            "  Encryption:   on ({:?} key{})",
+            config.encryption.key,
+            if config.encryption.encrypt_names { ", encrypted names" } else { "" }
+        );
+    }
     println!();
 
     // Check directories
     let mut ok = true;
 
-    if config.dropbox_base.exists() {
-        println!("  [OK] Dropbox base exists");
+    if config.target.local_dir().is_none() {
+        match remote::open(config).and_then(|remote| remote.list(&filter::SyncSet::new(config))) {
+            Ok(files) => println!("  [OK] {} is reachable ({} file(s))", config.target.name(), files.len()),
+            Err(e) => {
+                println!("  [ERROR] {} is not reachable: {:#}", config.target.name(), e);
+                ok = false;
+            }
+        }
+    } else if config.target.base.exists() {
+        println!("  [OK] {} base exists", config.target.name());
     } else {
-        println!("  [ERROR] Dropbox base does not exist");
+        println!("  [ERROR] {} base does not exist", config.target.name());
         ok = false;
     }
 
-    if config.dropbox_claude_dir.exists() {
-        println!("  [OK] Dropbox sync directory exists");
-    } else {
-        println!("  [WARN] Dropbox sync directory does not exist (will be created on first push)");
+    if config.target.dir.exists() {
+        println!("  [OK] {} sync directory exists", config.target.name());
+    } else if config.target.local_dir().is_some() {
+        println!(
+            "  [WARN] {} sync directory does not exist (will be created on first push)",
+            config.target.name()
+        );
+    }
+
+    if config.encryption.enabled {
+        match encryption::Cipher::load(config) {
+            Ok(_) => println!("  [OK] Encryption key is available"),
+            Err(e) => {
+                println!("  [ERROR] {:#}", e);
+                ok = false;
+            }
+        }
     }
 
     if config.claude_dir.exists() {
@@ -157,16 +319,28 @@ fn show_status(config: &Config) -> Result<()> {
     println!();
     println!("Machine: {}", Config::machine_id());
     println!("Local:   {:?}", config.claude_dir);
-    println!("Dropbox: {:?}", config.dropbox_claude_dir);
+    let remote = remote::open(config)?;
+    println!("Remote:  {} ({})", remote.location(), remote.name());
     println!();
 
-    // Load state (now stored locally, not in Dropbox)
+    // Load state (now stored locally, not in the sync folder)
     let state_path = config.local_state_path();
+    let mut sync_state = state::SyncState::default();
     if state_path.exists() {
         match state::SyncState::load(&state_path) {
-            Ok(state) => {
+            Ok(mut state) => {
                 println!("Last sync: {} by {}", state.last_sync, state.machine_id);
+                if state.bind_target(&remote.location()) {
+                    println!("Last synced with another target; the next sync starts without a baseline");
+                }
                 println!("Tracked files: {}", state.files.len());
+                if !state.conflicts.is_empty() {
+                    println!(
+                        "Pending conflicts: {} (run claude-sync-watch resolve)",
+                        state.conflicts.len()
+                    );
+                }
+                sync_state = state;
             }
             Err(e) => {
                 println!("Could not load sync state: {}", e);
@@ -193,18 +367,19 @@ fn show_status(config: &Config) -> Result<()> {
     // Check for differences
     let changes = state::detect_changes(
         &config.claude_dir,
-        &config.dropbox_claude_dir,
-        &config.sync_files,
-        &config.sync_dirs,
-        &state::SyncState::default(),
-    );
+        remote.as_ref(),
+        &filter::SyncSet::new(config),
+        &mut sync_state,
+        &transform::Transforms::load(config)?,
+        None,
+    )?;
 
     if changes.is_empty() {
         println!("No changes detected - files are in sync");
     } else {
         println!("Detected {} change(s):", changes.len());
         for change in &changes {
-            let arrow = if change.src.starts_with(&config.claude_dir) {
+            let arrow = if change.source == state::ConflictSide::Local {
                 "->"
             } else {
                 "<-"
@@ -216,12 +391,12 @@ fn show_status(config: &Config) -> Result<()> {
         }
     }
 
-    // Check for Dropbox conflicts
-    if config.dropbox_claude_dir.exists() {
-        let conflicts = find_conflicts(&config.dropbox_claude_dir);
+    // Check for provider conflict copies
+    if config.target.local_dir().is_some_and(|dir| dir.exists()) {
+        let conflicts = config.target.find_conflicts();
         if !conflicts.is_empty() {
             println!();
-            println!("Dropbox Conflicts Detected:");
+            println!("{} Conflicts Detected:", config.target.name());
             for conflict in &conflicts {
                 println!("  ! {:?}", conflict);
             }
@@ -230,25 +405,3 @@ fn show_status(config: &Config) -> Result<()> {
 
     Ok(())
 }
-
-/// Find Dropbox conflict files
-fn find_conflicts(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
-    let mut conflicts = Vec::new();
-
-    if let Ok(entries) = std::fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            let name = entry.file_name().to_string_lossy().to_string();
-
-            if name.contains("conflicted copy") {
-                conflicts.push(path.clone());
-            }
-
-            if path.is_dir() {
-                conflicts.extend(find_conflicts(&path));
-            }
-        }
-    }
-
-    conflicts
-}
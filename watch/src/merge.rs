@@ -0,0 +1,234 @@
+//! Three-way JSON merge for settings.json / mcp.json
+//!
+//! When both machines edited the same JSON file, most of the time they touched
+//! different keys (one added a permission, the other an MCP server). Merging
+//! key by key against the baseline keeps both edits; only keys changed on both
+//! sides to different values are real conflicts.
+
+use crate::state::ConflictSide;
+use serde_json::{Map, Value};
+
+/// Result of a three-way JSON merge
+#[derive(Debug)]
+pub struct JsonMerge {
+    /// Merged document
+    pub merged: Value,
+    /// JSON pointers of keys changed differently on both sides
+    pub conflicts: Vec<String>,
+}
+
+/// Merge `local` and `remote` against `base` (None if there is no baseline)
+///
+/// Keys changed on only one side take that side's value, keys removed on one
+/// side (and untouched on the other) are removed, and arrays of scalars are
+/// merged as sets. Keys and arrays holding objects or arrays (e.g. `hooks`)
+/// changed on both sides take the `prefer` side's value and are reported in
+/// `conflicts`.
+pub fn merge_json(base: Option<&Value>, local: &Value, remote: &Value, prefer: ConflictSide) -> JsonMerge {
+    let mut conflicts = Vec::new();
+    let merged = merge_value(base, Some(local), Some(remote), "", prefer, &mut conflicts)
+        .unwrap_or(Value::Null);
+
+    JsonMerge { merged, conflicts }
+}
+
+/// Merge a single value; None means "absent"
+fn merge_value(
+    base: Option<&Value>,
+    local: Option<&Value>,
+    remote: Option<&Value>,
+    pointer: &str,
+    prefer: ConflictSide,
+    conflicts: &mut Vec<String>,
+) -> Option<Value> {
+    if local == remote {
+        return local.cloned();
+    }
+    if local == base {
+        return remote.cloned();
+    }
+    if remote == base {
+        return local.cloned();
+    }
+
+    // Both changed. Objects can be merged key by key.
+    if let (Some(Value::Object(l)), Some(Value::Object(r))) = (local, remote) {
+        let empty = Map::new();
+        let b = match base {
+            Some(Value::Object(b)) => b,
+            _ => &empty,
+        };
+        return Some(Value::Object(merge_objects(b, l, r, pointer, prefer, conflicts)));
+    }
+
+    // Arrays of scalars (e.g. permissions.allow) are merged as sets: keep
+    // additions from both sides and drop entries removed on either side. An
+    // edited object in an array would look like a removal plus an addition,
+    // so arrays holding objects or arrays are not merged.
+    if let (Some(Value::Array(l)), Some(Value::Array(r))) = (local, remote) {
+        let empty = Vec::new();
+        let b = match base {
+            Some(Value::Array(b)) => b,
+            _ => &empty,
+        };
+        if [b, l, r].iter().all(|values| values.iter().all(is_scalar)) {
+            return Some(Value::Array(merge_arrays(b, l, r)));
+        }
+    }
+
+    conflicts.push(if pointer.is_empty() { "/".to_string() } else { pointer.to_string() });
+    match prefer {
+        ConflictSide::Local => local.cloned(),
+        ConflictSide::Remote => remote.cloned(),
+    }
+}
+
+fn is_scalar(value: &Value) -> bool {
+    !value.is_object() && !value.is_array()
+}
+
+/// Merge two arrays as sets, keeping local order first
+fn merge_arrays(base: &[Value], local: &[Value], remote: &[Value]) -> Vec<Value> {
+    let kept_local = local
+        .iter()
+        .filter(|v| remote.contains(v) || !base.contains(v));
+    let added_remote = remote
+        .iter()
+        .filter(|v| !local.contains(v) && !base.contains(v));
+
+    kept_local.chain(added_remote).cloned().collect()
+}
+
+/// Merge two objects key by key, keeping local key order first
+fn merge_objects(
+    base: &Map<String, Value>,
+    local: &Map<String, Value>,
+    remote: &Map<String, Value>,
+    pointer: &str,
+    prefer: ConflictSide,
+    conflicts: &mut Vec<String>,
+) -> Map<String, Value> {
+    let mut merged = Map::new();
+
+    let keys = local
+        .keys()
+        .chain(remote.keys().filter(|k| !local.contains_key(*k)))
+        .chain(base.keys().filter(|k| !local.contains_key(*k) && !remote.contains_key(*k)));
+
+    for key in keys {
+        let child_pointer = format!("{}/{}", pointer, key.replace('~', "~0").replace('/', "~1"));
+        if let Some(value) = merge_value(
+            base.get(key),
+            local.get(key),
+            remote.get(key),
+            &child_pointer,
+            prefer,
+            conflicts,
+        ) {
+            merged.insert(key.clone(), value);
+        }
+    }
+
+    merged
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn disjoint_keys_are_combined() {
+        let base = json!({"model": "opus", "permissions": {"allow": []}});
+        let local = json!({"model": "opus", "permissions": {"allow": []}, "theme": "dark"});
+        let remote = json!({"model": "sonnet", "permissions": {"allow": [], "deny": ["Bash(rm:*)"]}});
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Local);
+        assert_eq!(
+            merge.merged,
+            json!({"model": "sonnet", "permissions": {"allow": [], "deny": ["Bash(rm:*)"]}, "theme": "dark"})
+        );
+        assert!(merge.conflicts.is_empty());
+    }
+
+    #[test]
+    fn same_key_changed_on_both_sides_conflicts() {
+        let base = json!({"model": "opus", "env": {"a/b": "1", "KEEP": "x"}});
+        let local = json!({"model": "haiku", "env": {"a/b": "2", "KEEP": "x"}});
+        let remote = json!({"model": "sonnet", "env": {"a/b": "3", "KEEP": "x"}});
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Local);
+        assert_eq!(merge.merged, local);
+        assert_eq!(merge.conflicts, ["/model", "/env/a~1b"]);
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Remote);
+        assert_eq!(merge.merged, remote);
+        assert_eq!(merge.conflicts, ["/model", "/env/a~1b"]);
+
+        // Without a baseline, keys only one side has are additions
+        let merge = merge_json(None, &json!({"a": 1, "b": 2}), &json!({"a": 3, "c": 4}), ConflictSide::Remote);
+        assert_eq!(merge.merged, json!({"a": 3, "b": 2, "c": 4}));
+        assert_eq!(merge.conflicts, ["/a"]);
+    }
+
+    #[test]
+    fn deletions_on_one_side_are_kept() {
+        let base = json!({"model": "opus", "theme": "dark", "hooks": {"Stop": []}});
+        let local = json!({"model": "opus", "hooks": {"Stop": []}});
+        let remote = json!({"model": "opus", "theme": "dark", "hooks": {"Stop": []}, "verbose": true});
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Remote);
+        assert_eq!(merge.merged, json!({"model": "opus", "hooks": {"Stop": []}, "verbose": true}));
+        assert!(merge.conflicts.is_empty());
+
+        // Deleted on one side, changed on the other
+        let local = json!({"model": "opus"});
+        let remote = json!({"model": "opus", "theme": "light"});
+        let merge = merge_json(Some(&json!({"model": "opus", "theme": "dark"})), &local, &remote, ConflictSide::Local);
+        assert_eq!(merge.merged, json!({"model": "opus"}));
+        assert_eq!(merge.conflicts, ["/theme"]);
+    }
+
+    #[test]
+    fn removed_array_elements_stay_removed() {
+        let base = json!({"allow": ["Read", "Bash(git:*)", "Edit"]});
+        let local = json!({"allow": ["Read", "Edit"]});
+        let remote = json!({"allow": ["Read", "Bash(git:*)", "Edit", "WebFetch"]});
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Local);
+        assert_eq!(merge.merged, json!({"allow": ["Read", "Edit", "WebFetch"]}));
+        assert!(merge.conflicts.is_empty());
+
+        // Removed on both sides, added on both sides
+        let local = json!({"allow": ["Edit", "Grep"]});
+        let remote = json!({"allow": ["Edit", "Grep", "Glob"]});
+        let merge = merge_json(Some(&json!({"allow": ["Read", "Edit"]})), &local, &remote, ConflictSide::Local);
+        assert_eq!(merge.merged, json!({"allow": ["Edit", "Grep", "Glob"]}));
+    }
+
+    #[test]
+    fn arrays_of_objects_changed_on_both_sides_conflict() {
+        let hook = |command: &str| json!({"matcher": "Edit", "hooks": [{"type": "command", "command": command}]});
+        let base = json!({"hooks": {"PostToolUse": [hook("fmt")], "Stop": [hook("notify")]}});
+        let local = json!({"hooks": {"PostToolUse": [hook("fmt --check")], "Stop": [hook("notify")]}});
+        let remote = json!({"hooks": {"PostToolUse": [hook("lint")], "Stop": [hook("say done")]}});
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Local);
+        assert_eq!(
+            merge.merged,
+            json!({"hooks": {"PostToolUse": [hook("fmt --check")], "Stop": [hook("say done")]}})
+        );
+        assert_eq!(merge.conflicts, ["/hooks/PostToolUse"]);
+
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Remote);
+        assert_eq!(merge.merged, remote);
+        assert_eq!(merge.conflicts, ["/hooks/PostToolUse"]);
+
+        // Hooks added on both sides are not set-merged either
+        let local = json!({"hooks": {"PostToolUse": [hook("fmt"), hook("test")], "Stop": [hook("notify")]}});
+        let remote = json!({"hooks": {"PostToolUse": [hook("fmt"), hook("lint")], "Stop": [hook("notify")]}});
+        let merge = merge_json(Some(&base), &local, &remote, ConflictSide::Remote);
+        assert_eq!(merge.merged, remote);
+        assert_eq!(merge.conflicts, ["/hooks/PostToolUse"]);
+    }
+}
@@ -0,0 +1,182 @@
+//! Machine-specific overrides for synced JSON files
+//!
+//! Some values legitimately differ per Mac (absolute paths, `env` vars, a
+//! lighter model on a slow laptop). Each machine can keep an override file at
+//! ~/.claude/.sync_overrides/<machine_id>.json mapping synced relative paths
+//! to JSON fragments:
+//!
+//! ```json
+//! {
+//!   "settings.json": { "model": "sonnet" },
+//!   "mcp.json": { "mcpServers": { "db": { "env": { "DB_PATH": "/Users/me/db" } } } }
+//! }
+//! ```
+//!
+//! Fragments are deep-merged over the shared content when pulling, and the
+//! overridden keys are put back to their shared values when pushing, so
+//! host-specific values never reach the sync directory.
+
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+/// Overrides for this machine (relative path -> JSON fragment)
+#[derive(Debug, Default)]
+pub struct Overrides {
+    files: HashMap<String, Value>,
+}
+
+impl Overrides {
+    /// Load overrides from a file; a missing file means no overrides
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read overrides file: {:?}", path))?;
+        let files: HashMap<String, Value> = serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse overrides file: {:?}", path))?;
+
+        Ok(Self { files })
+    }
+
+    /// Override fragment for a relative path, if any
+    pub fn for_file(&self, rel_path: &str) -> Option<&Value> {
+        self.files.get(rel_path)
+    }
+
+    /// Number of files with overrides
+    pub fn len(&self) -> usize {
+        self.files.len()
+    }
+}
+
+/// Deep-merge `overlay` over `shared` (used when pulling)
+pub fn apply(shared: &Value, overlay: &Value) -> Value {
+    match (shared, overlay) {
+        (Value::Object(base), Value::Object(over)) => {
+            let mut merged = base.clone();
+            for (key, over_value) in over {
+                let value = match base.get(key) {
+                    Some(base_value) => apply(base_value, over_value),
+                    None => over_value.clone(),
+                };
+                merged.insert(key.clone(), value);
+            }
+            Value::Object(merged)
+        }
+        _ => overlay.clone(),
+    }
+}
+
+/// Replace every overridden key in `local` with its value in `shared`, or drop
+/// it if `shared` doesn't have it (used when pushing)
+pub fn strip(local: &Value, overlay: &Value, shared: Option<&Value>) -> Value {
+    let (Value::Object(local_map), Value::Object(over)) = (local, overlay) else {
+        return shared.cloned().unwrap_or_else(|| local.clone());
+    };
+
+    let shared_map = match shared {
+        Some(Value::Object(m)) => Some(m),
+        _ => None,
+    };
+
+    let mut stripped = Map::new();
+    for (key, local_value) in local_map {
+        let shared_value = shared_map.and_then(|m| m.get(key));
+        match over.get(key) {
+            None => {
+                stripped.insert(key.clone(), local_value.clone());
+            }
+            Some(over_value @ Value::Object(_)) if local_value.is_object() => {
+                let value = strip(local_value, over_value, shared_value);
+                // Don't leave behind an empty object the overlay introduced
+                let introduced = shared_value.is_none() && value.as_object().is_some_and(|m| m.is_empty());
+                if !introduced {
+                    stripped.insert(key.clone(), value);
+                }
+            }
+            Some(_) => {
+                if let Some(shared_value) = shared_value {
+                    stripped.insert(key.clone(), shared_value.clone());
+                }
+            }
+        }
+    }
+
+    Value::Object(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn strip_undoes_apply() {
+        let shared = json!({
+            "model": "opus",
+            "env": { "PATH_A": "/shared/a", "KEEP": "1" },
+            "mcpServers": { "db": { "command": "db", "env": { "DB_PATH": "/shared/db" } } },
+            "hooks": ["a", "b"]
+        });
+        for overlay in [
+            json!({ "model": "sonnet" }),
+            json!({ "env": { "PATH_A": "/Users/me/a" } }),
+            json!({ "mcpServers": { "db": { "env": { "DB_PATH": "/Users/me/db" } } } }),
+            json!({ "hooks": [] }),
+            json!({ "model": "sonnet", "env": { "PATH_A": "/Users/me/a", "KEEP": "0" } }),
+        ] {
+            let local = apply(&shared, &overlay);
+            assert_ne!(local, shared, "{}", overlay);
+            assert_eq!(strip(&local, &overlay, Some(&shared)), shared, "{}", overlay);
+        }
+    }
+
+    #[test]
+    fn nested_overrides_keep_sibling_keys() {
+        let shared = json!({ "mcpServers": { "db": { "command": "db", "env": { "DB_PATH": "/shared" } } } });
+        let overlay = json!({ "mcpServers": { "db": { "env": { "DB_PATH": "/Users/me/db", "EXTRA": "1" } } } });
+
+        let local = apply(&shared, &overlay);
+        assert_eq!(
+            local,
+            json!({ "mcpServers": { "db": { "command": "db", "env": { "DB_PATH": "/Users/me/db", "EXTRA": "1" } } } })
+        );
+
+        // Edits outside the overridden keys are pushed
+        let mut edited = local.clone();
+        edited["mcpServers"]["db"]["command"] = json!("db2");
+        assert_eq!(
+            strip(&edited, &overlay, Some(&shared)),
+            json!({ "mcpServers": { "db": { "command": "db2", "env": { "DB_PATH": "/shared" } } } })
+        );
+    }
+
+    #[test]
+    fn override_keys_missing_from_shared_are_dropped() {
+        let shared = json!({ "model": "opus" });
+        let overlay = json!({ "theme": "dark", "env": { "LOCAL_ONLY": "1" }, "mcpServers": { "db": { "env": {} } } });
+
+        let local = apply(&shared, &overlay);
+        assert_eq!(local["theme"], "dark");
+        assert_eq!(local["env"]["LOCAL_ONLY"], "1");
+        assert_eq!(strip(&local, &overlay, Some(&shared)), shared);
+
+        // Nothing shared yet: only the keys that aren't overridden are pushed
+        let local = json!({ "model": "opus", "theme": "dark", "env": { "LOCAL_ONLY": "1", "OTHER": "2" } });
+        assert_eq!(strip(&local, &overlay, None), json!({ "model": "opus", "env": { "OTHER": "2" } }));
+    }
+
+    #[test]
+    fn non_object_overlays_replace_the_whole_value() {
+        let shared = json!({ "model": "opus" });
+        let overlay = json!("replaced");
+        let local = apply(&shared, &overlay);
+        assert_eq!(local, overlay);
+        assert_eq!(strip(&local, &overlay, Some(&shared)), shared);
+    }
+}
@@ -0,0 +1,520 @@
+//! Storage for the shared copy
+//!
+//! The engine reads and writes the shared copy through `Remote`, so it can
+//! live in a folder kept in sync by a cloud client or git (`FolderRemote`),
+//! in an object store (`s3::S3Remote`), on a WebDAV server
+//! (`webdav::WebdavRemote`) or on an SSH server (`sftp::SftpRemote`), and be
+//! encrypted on the way (`encryption::EncryptedRemote`). Writes and deletes
+//! name the version of the file the caller last saw; a backend that can
+//! check this atomically refuses to overwrite a change another machine made
+//! in the meantime.
+
+use crate::config::Config;
+use crate::encryption::{self, EncryptedRemote};
+use crate::filter::SyncSet;
+use crate::s3::S3Remote;
+use crate::sftp::SftpRemote;
+use crate::state::{FileState, SyncState};
+use crate::target::{ProviderKind, SyncTarget};
+use crate::webdav::WebdavRemote;
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// A file in the shared copy
+#[derive(Debug, Clone)]
+pub struct RemoteFile {
+    /// Checksum, size and modification time
+    pub state: FileState,
+    /// Backend version token (ETag, or file metadata on SSH servers)
+    pub version: Option<String>,
+    /// Unix permission bits, if the backend keeps them
+    pub mode: Option<u32>,
+}
+
+/// Backend holding the shared copy
+pub trait Remote {
+    /// Display name of the backend
+    fn name(&self) -> &str;
+
+    /// Where the shared copy lives, for logs and status output
+    fn location(&self) -> String;
+
+    /// Local folder holding the shared copy, if there is one to watch
+    fn local_dir(&self) -> Option<&Path> {
+        None
+    }
+
+    /// Where a file is stored in `local_dir`, if there is one
+    fn local_path(&self, rel_path: &str) -> Option<PathBuf> {
+        self.local_dir().map(|dir| dir.join(rel_path))
+    }
+
+    /// All synced files (relative path -> file)
+    fn list(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, RemoteFile>>;
+
+    /// A single file, None if it doesn't exist
+    fn stat(&self, rel_path: &str) -> Result<Option<RemoteFile>>;
+
+    /// Content of a file
+    fn read(&self, rel_path: &str) -> Result<Vec<u8>>;
+
+    /// Write a file, provided it is still as `expected` (None: it must not
+    /// exist yet)
+    fn write(
+        &self,
+        rel_path: &str,
+        content: &[u8],
+        mode: Option<u32>,
+        expected: Option<&RemoteFile>,
+    ) -> Result<RemoteFile>;
+
+    /// Delete a file, provided it is still as `expected`
+    fn delete(&self, rel_path: &str, expected: &RemoteFile) -> Result<()>;
+
+    /// Check if a file hasn't finished syncing to this machine
+    fn is_pending(&self, _rel_path: &str) -> bool {
+        false
+    }
+
+    /// Check if a file is a conflict copy saved by the sync provider
+    fn is_conflict_copy(&self, _rel_path: &str) -> bool {
+        false
+    }
+
+    /// Check for changes since the last list, write or delete (for backends
+    /// that can't be watched)
+    fn has_changes(&self, _sync_set: &SyncSet) -> Result<bool> {
+        Ok(false)
+    }
+}
+
+/// Open the remote configured for this machine
+pub fn open(config: &Config) -> Result<Box<dyn Remote>> {
+    let remote = open_backend(config)?;
+    if config.encryption.enabled {
+        return Ok(Box::new(EncryptedRemote::new(config, remote)?));
+    }
+    encryption::check_unencrypted(remote.as_ref())?;
+    Ok(remote)
+}
+
+/// Open the configured backend as is, without encryption
+pub fn open_backend(config: &Config) -> Result<Box<dyn Remote>> {
+    match config.target.kind {
+        ProviderKind::S3 => Ok(Box::new(S3Remote::new(config)?)),
+        ProviderKind::Webdav => Ok(Box::new(WebdavRemote::new(config)?)),
+        ProviderKind::Sftp => Ok(Box::new(SftpRemote::new(config)?)),
+        _ => Ok(Box::new(FolderRemote::new(config.target.clone()))),
+    }
+}
+
+/// Shared copy in a local folder synced by a provider client (or git)
+pub struct FolderRemote {
+    target: SyncTarget,
+}
+
+impl FolderRemote {
+    pub fn new(target: SyncTarget) -> Self {
+        Self { target }
+    }
+
+    fn path(&self, rel_path: &str) -> PathBuf {
+        self.target.dir.join(rel_path)
+    }
+
+    /// Fail if a file no longer matches what the caller last saw
+    fn check_unchanged(&self, rel_path: &str, expected: Option<&RemoteFile>) -> Result<()> {
+        let current = SyncState::current_file_state(&self.path(rel_path));
+        let unchanged = match (&current, expected) {
+            (Some(current), Some(expected)) => current.sha256 == expected.state.sha256,
+            (None, None) => true,
+            _ => false,
+        };
+        if !unchanged {
+            bail!("{} changed in {} since it was read", rel_path, self.target.name());
+        }
+        Ok(())
+    }
+
+    fn remote_file(&self, path: &Path) -> Option<RemoteFile> {
+        let state = SyncState::current_file_state(path)?;
+        Some(RemoteFile {
+            state,
+            version: None,
+            mode: fs::metadata(path).ok().and_then(|m| file_mode(&m)),
+        })
+    }
+}
+
+impl Remote for FolderRemote {
+    fn name(&self) -> &str {
+        self.target.name()
+    }
+
+    fn location(&self) -> String {
+        format!("{:?}", self.target.dir)
+    }
+
+    fn local_dir(&self) -> Option<&Path> {
+        Some(&self.target.dir)
+    }
+
+    fn list(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, RemoteFile>> {
+        Ok(sync_set
+            .collect_files(&self.target.dir)
+            .into_iter()
+            .filter_map(|rel_path| {
+                let file = self.remote_file(&self.path(&rel_path))?;
+                Some((rel_path, file))
+            })
+            .collect())
+    }
+
+    fn stat(&self, rel_path: &str) -> Result<Option<RemoteFile>> {
+        Ok(self.remote_file(&self.path(rel_path)))
+    }
+
+    fn read(&self, rel_path: &str) -> Result<Vec<u8>> {
+        let path = self.path(rel_path);
+        fs::read(&path).with_context(|| format!("Failed to read {:?}", path))
+    }
+
+    fn write(
+        &self,
+        rel_path: &str,
+        content: &[u8],
+        mode: Option<u32>,
+        expected: Option<&RemoteFile>,
+    ) -> Result<RemoteFile> {
+        self.check_unchanged(rel_path, expected)?;
+
+        let path = self.path(rel_path);
+        write_atomic(&path, content, mode).with_context(|| format!("Failed to write {:?}", path))?;
+
+        self.remote_file(&path)
+            .with_context(|| format!("Failed to read back {:?}", path))
+    }
+
+    fn delete(&self, rel_path: &str, expected: &RemoteFile) -> Result<()> {
+        self.check_unchanged(rel_path, Some(expected))?;
+
+        let path = self.path(rel_path);
+        fs::remove_file(&path).with_context(|| format!("Failed to delete {:?}", path))?;
+        remove_empty_parents(&path, &self.target.dir);
+        Ok(())
+    }
+
+    fn is_pending(&self, rel_path: &str) -> bool {
+        self.target.is_pending(&self.path(rel_path))
+    }
+
+    fn is_conflict_copy(&self, rel_path: &str) -> bool {
+        self.target.conflict_original(&self.path(rel_path)).is_some()
+    }
+}
+
+/// Checksum and permission bits of a file version
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct CachedVersion {
+    pub etag: String,
+    pub sha256: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mode: Option<u32>,
+}
+
+/// Last seen version of each file on a network backend, kept in a local file
+///
+/// Listings only return ETags; the index maps them to checksums so that a
+/// file is only downloaded (or its metadata fetched) when it has changed, and
+/// tells the daemon's poll whether anything changed since the last sync.
+pub struct VersionIndex {
+    path: PathBuf,
+    /// Relative path -> version
+    versions: RefCell<HashMap<String, CachedVersion>>,
+}
+
+impl VersionIndex {
+    /// Load the index, starting empty if it is missing or unreadable
+    pub fn load(path: PathBuf) -> Self {
+        let versions = fs::read_to_string(&path)
+            .ok()
+            .and_then(|content| serde_json::from_str(&content).ok())
+            .unwrap_or_default();
+        Self {
+            path,
+            versions: RefCell::new(versions),
+        }
+    }
+
+    /// The cached version of a file, if it still has this ETag
+    pub fn get(&self, rel_path: &str, etag: &str) -> Option<CachedVersion> {
+        self.versions
+            .borrow()
+            .get(rel_path)
+            .filter(|cached| cached.etag == etag)
+            .cloned()
+    }
+
+    /// Remember the version of a file we wrote or stat'ed
+    pub fn remember(&self, rel_path: &str, file: &RemoteFile) {
+        let Some(version) = cached_version(file) else {
+            return;
+        };
+        self.versions.borrow_mut().insert(rel_path.to_string(), version);
+        self.save();
+    }
+
+    pub fn forget(&self, rel_path: &str) {
+        if self.versions.borrow_mut().remove(rel_path).is_some() {
+            self.save();
+        }
+    }
+
+    /// Replace the index with the files of a full listing
+    pub fn replace(&self, files: &BTreeMap<String, RemoteFile>) {
+        let versions: HashMap<String, CachedVersion> = files
+            .iter()
+            .filter_map(|(rel_path, file)| Some((rel_path.clone(), cached_version(file)?)))
+            .collect();
+        if *self.versions.borrow() != versions {
+            *self.versions.borrow_mut() = versions;
+            self.save();
+        }
+    }
+
+    /// Check if a listing (relative path -> ETag) differs from the index
+    pub fn differs(&self, listed: &HashMap<String, String>) -> bool {
+        let versions = self.versions.borrow();
+        listed.len() != versions.len()
+            || listed
+                .iter()
+                .any(|(rel_path, etag)| versions.get(rel_path).is_none_or(|c| &c.etag != etag))
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(&*self.versions.borrow())
+            .map_err(anyhow::Error::from)
+            .and_then(|content| Ok(fs::write(&self.path, content)?));
+        if let Err(e) = result {
+            log::warn!("Failed to save index {:?}: {}", self.path, e);
+        }
+    }
+}
+
+fn cached_version(file: &RemoteFile) -> Option<CachedVersion> {
+    Some(CachedVersion {
+        etag: file.version.clone()?,
+        sha256: file.state.sha256.clone(),
+        mode: file.mode,
+    })
+}
+
+/// Remove directories left empty by a deletion, up to (not including) `root`
+pub fn remove_empty_parents(path: &Path, root: &Path) {
+    let mut dir = path.parent();
+    while let Some(d) = dir {
+        if d == root || !d.starts_with(root) || fs::remove_dir(d).is_err() {
+            break;
+        }
+        dir = d.parent();
+    }
+}
+
+/// Permission bits of a file
+#[cfg(unix)]
+pub fn file_mode(metadata: &fs::Metadata) -> Option<u32> {
+    use std::os::unix::fs::PermissionsExt;
+    Some(metadata.permissions().mode() & 0o7777)
+}
+
+#[cfg(not(unix))]
+pub fn file_mode(_metadata: &fs::Metadata) -> Option<u32> {
+    None
+}
+
+/// Set the permission bits of a file (skill scripts need their executable bit)
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions of {:?}", path))
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> Result<()> {
+    Ok(())
+}
+
+/// Write a file atomically
+///
+/// The content is staged in a temporary file next to `path`, synced to disk
+/// and read back to check its checksum, then renamed over `path`. Until the
+/// rename `path` keeps its previous content, so a failed or interrupted write
+/// never leaves a partial file for Claude Code or the sync provider to see.
+/// Without `mode`, an existing file keeps its permissions. A symlink is
+/// written through, as `fs::write` would.
+pub fn write_atomic(path: &Path, content: &[u8], mode: Option<u32>) -> Result<()> {
+    let path = match fs::symlink_metadata(path) {
+        Ok(metadata) if metadata.file_type().is_symlink() => {
+            fs::canonicalize(path).with_context(|| format!("Failed to resolve symlink {:?}", path))?
+        }
+        _ => path.to_path_buf(),
+    };
+    let (Some(parent), Some(file_name)) = (path.parent(), path.file_name()) else {
+        bail!("Not a file path: {:?}", path);
+    };
+    fs::create_dir_all(parent)?;
+
+    let mode = mode.or_else(|| fs::metadata(&path).ok().and_then(|m| file_mode(&m)));
+
+    // Hidden and *.tmp, so the sync set and the watcher ignore it
+    let mut attempt = 0;
+    let (tmp, file) = loop {
+        let tmp = parent.join(format!(
+            ".{}.{}.{}.tmp",
+            file_name.to_string_lossy(),
+            std::process::id(),
+            attempt
+        ));
+        match create_new(&tmp, mode) {
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => attempt += 1,
+            result => break (tmp, result),
+        }
+    };
+    let file = file.with_context(|| format!("Failed to create {:?}", tmp))?;
+
+    let result = stage(file, &tmp, content, mode)
+        .and_then(|()| fs::rename(&tmp, &path).with_context(|| format!("Failed to replace {:?}", path)));
+    if result.is_err() {
+        fs::remove_file(&tmp).ok();
+    }
+    result?;
+
+    // Make the rename itself durable (not supported everywhere)
+    fs::File::open(parent).and_then(|dir| dir.sync_all()).ok();
+
+    Ok(())
+}
+
+/// Write and verify the temporary file for `write_atomic`
+fn stage(mut file: fs::File, tmp: &Path, content: &[u8], mode: Option<u32>) -> Result<()> {
+    file.write_all(content)
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {:?}", tmp))?;
+
+    // The umask may have cleared bits of the mode the file was created with
+    let metadata = file.metadata().with_context(|| format!("Failed to read back {:?}", tmp))?;
+    drop(file);
+    if let Some(mode) = mode.filter(|&mode| file_mode(&metadata) != Some(mode)) {
+        set_mode(tmp, mode)?;
+    }
+
+    let written = fs::read(tmp).with_context(|| format!("Failed to read back {:?}", tmp))?;
+    if Sha256::digest(&written) != Sha256::digest(content) {
+        bail!("Checksum mismatch after writing {:?}", tmp);
+    }
+
+    Ok(())
+}
+
+/// Write a file only the user can read
+#[cfg(unix)]
+pub fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(0o600)
+        .open(path)?;
+    file.write_all(content)?;
+    set_mode(path, 0o600).map_err(std::io::Error::other)
+}
+
+#[cfg(not(unix))]
+pub fn write_private(path: &Path, content: &[u8]) -> std::io::Result<()> {
+    fs::write(path, content)
+}
+
+/// Create a new file only the user can read, failing if `path` exists
+pub fn create_private(path: &Path) -> std::io::Result<fs::File> {
+    create_new(path, Some(0o600))
+}
+
+/// Create a new file with permissions `mode` (less the umask), failing if
+/// `path` exists
+#[cfg(unix)]
+fn create_new(path: &Path, mode: Option<u32>) -> std::io::Result<fs::File> {
+    use std::os::unix::fs::OpenOptionsExt;
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    if let Some(mode) = mode {
+        options.mode(mode);
+    }
+    options.open(path)
+}
+
+#[cfg(not(unix))]
+fn create_new(path: &Path, _mode: Option<u32>) -> std::io::Result<fs::File> {
+    fs::OpenOptions::new().write(true).create_new(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[cfg(unix)]
+    fn mode_of(path: &Path) -> Option<u32> {
+        file_mode(&fs::metadata(path).unwrap())
+    }
+
+    #[test]
+    fn write_atomic_replaces_content() {
+        let dir = TempDir::new();
+        let path = dir.path().join("skills/review/SKILL.md");
+
+        write_atomic(&path, b"first", None).unwrap();
+        write_atomic(&path, b"second", None).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"second");
+        // Only the file itself is left
+        assert_eq!(fs::read_dir(path.parent().unwrap()).unwrap().count(), 1);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_sets_permissions() {
+        let dir = TempDir::new();
+        let private = dir.path().join("secrets.json");
+        let script = dir.path().join("hook.sh");
+
+        write_atomic(&private, b"{}", Some(0o600)).unwrap();
+        write_atomic(&script, b"#!/bin/sh", Some(0o775)).unwrap();
+        assert_eq!(mode_of(&private), Some(0o600));
+        assert_eq!(mode_of(&script), Some(0o775));
+
+        // Without a mode, the file keeps its permissions
+        write_atomic(&private, b"{\"a\": 1}", None).unwrap();
+        assert_eq!(mode_of(&private), Some(0o600));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_atomic_never_writes_through_existing_temp_files() {
+        let dir = TempDir::new();
+        let path = dir.path().join("settings.json");
+        let victim = dir.write("victim", "untouched");
+        let tmp = dir.path().join(format!(".settings.json.{}.0.tmp", std::process::id()));
+        std::os::unix::fs::symlink(&victim, &tmp).unwrap();
+
+        write_atomic(&path, b"{}", Some(0o600)).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"{}");
+        assert_eq!(fs::read_to_string(&victim).unwrap(), "untouched");
+    }
+}
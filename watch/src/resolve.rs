@@ -0,0 +1,666 @@
+//! Interactive resolution of pending conflicts (`claude-sync-watch resolve`)
+//!
+//! Two kinds of conflicts are listed:
+//! - conflicts the engine resolved automatically (recorded in `SyncState`)
+//! - conflict copies saved by the sync provider (e.g. Dropbox "conflicted
+//!   copy" files) in the sync directory
+//!
+//! For each one we show a diff of local vs. remote (against the baseline when
+//! we have it), then write the chosen content to both sides and record it as
+//! the new baseline so the daemon treats the file as in sync. The sync lock is
+//! held throughout, so the daemon doesn't sync in between.
+
+use crate::baseline::BaselineStore;
+use crate::config::Config;
+use crate::encryption::Cipher;
+use crate::lock::SyncLock;
+use crate::remote::{self, file_mode, write_atomic, Remote, RemoteFile};
+use crate::state::{ConflictSide, PendingConflict, SyncState};
+use crate::transform::Transforms;
+use anyhow::{bail, Context, Result};
+use similar::{DiffTag, TextDiff};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// A conflict awaiting a decision
+enum ConflictItem {
+    /// Both sides changed and the engine picked a winner
+    Engine {
+        rel_path: String,
+        conflict: PendingConflict,
+    },
+    /// The sync provider saved a conflict copy next to the file
+    ProviderCopy { rel_path: String, copy_path: PathBuf },
+}
+
+impl ConflictItem {
+    fn rel_path(&self) -> &str {
+        match self {
+            ConflictItem::Engine { rel_path, .. } | ConflictItem::ProviderCopy { rel_path, .. } => {
+                rel_path
+            }
+        }
+    }
+}
+
+/// Contents of a conflict to compare
+struct ConflictContents {
+    rel_path: String,
+    local: Option<Vec<u8>>,
+    remote: Option<Vec<u8>>,
+    base: Option<Vec<u8>>,
+    local_label: String,
+    remote_label: String,
+    /// The local file as shown (None if missing), to notice later edits
+    shown_local: Option<Vec<u8>>,
+    /// The remote file as shown; the resolution is only written over it
+    shown_remote: Option<RemoteFile>,
+    /// Conflict copy to remove once the conflict is resolved
+    leftover: Option<PathBuf>,
+}
+
+/// User's decision for one conflict
+enum Choice {
+    Local,
+    Remote,
+    Edit,
+    Skip,
+    Quit,
+}
+
+/// Run the interactive resolve loop
+pub fn run(config: &Config) -> Result<()> {
+    let _lock = SyncLock::acquire(&config.local_sync_lock_path(), || {
+        println!("Waiting for the sync daemon to finish the current sync...")
+    })?;
+
+    let state_path = config.local_state_path();
+    let mut state = SyncState::load(&state_path)?;
+    let baseline = BaselineStore::new(config.local_baseline_dir());
+    let transforms = Transforms::load(config)?;
+    let remote = remote::open(config)?;
+    let cipher = match config.encryption.enabled {
+        true => Some(Cipher::load(config)?),
+        false => None,
+    };
+
+    let items = collect_conflicts(config, &state, cipher.as_ref());
+    if items.is_empty() {
+        println!("No conflicts to resolve.");
+        return Ok(());
+    }
+
+    println!("{} conflict(s) to resolve:", items.len());
+    for item in &items {
+        println!("  ! {}", describe(config, item));
+    }
+
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+
+    for item in &items {
+        println!();
+        println!("=== {} ===", describe(config, item));
+
+        let contents = load_contents(
+            config,
+            remote.as_ref(),
+            &state,
+            &baseline,
+            &transforms,
+            cipher.as_ref(),
+            item,
+        )?;
+        show_diff(&contents);
+
+        loop {
+            let chosen = match prompt(&mut input)? {
+                Choice::Local => contents.local.clone(),
+                Choice::Remote => contents.remote.clone(),
+                Choice::Edit => match edit_merge(item.rel_path(), &contents) {
+                    Ok(merged) => Some(merged),
+                    Err(e) => {
+                        println!("Edit failed: {}", e);
+                        continue;
+                    }
+                },
+                Choice::Skip => break,
+                Choice::Quit => {
+                    state.save(&state_path)?;
+                    return Ok(());
+                }
+            };
+
+            let Some(chosen) = chosen else {
+                println!("That side's content is not available, pick another option.");
+                continue;
+            };
+
+            match apply_resolution(config, remote.as_ref(), &mut state, &baseline, &transforms, &contents, &chosen) {
+                Ok(()) => {
+                    println!("Resolved {}", item.rel_path());
+                    state.save(&state_path)?;
+                    break;
+                }
+                Err(e) => println!("Could not apply resolution: {:#}", e),
+            }
+        }
+    }
+
+    state.save(&state_path)?;
+    baseline.refresh(&state, |rel_path| remote.read(rel_path).ok())?;
+
+    Ok(())
+}
+
+/// Gather engine-recorded conflicts and provider conflict copies
+fn collect_conflicts(config: &Config, state: &SyncState, cipher: Option<&Cipher>) -> Vec<ConflictItem> {
+    let mut items: Vec<ConflictItem> = state
+        .conflicts
+        .iter()
+        .map(|(rel_path, conflict)| ConflictItem::Engine {
+            rel_path: rel_path.clone(),
+            conflict: conflict.clone(),
+        })
+        .collect();
+
+    for copy_path in config.target.find_conflicts() {
+        // Encrypted copies name the file they belong to inside
+        if let Some(cipher) = cipher {
+            match read_copy(&copy_path, Some(cipher)) {
+                Ok((Some(rel_path), _)) => items.push(ConflictItem::ProviderCopy { rel_path, copy_path }),
+                Ok(_) => {}
+                Err(e) => log::warn!("Skipping conflict copy {:?}: {:#}", copy_path, e),
+            }
+            continue;
+        }
+
+        let rel_path = config
+            .target
+            .conflict_original(&copy_path)
+            .and_then(|original| {
+                original
+                    .strip_prefix(&config.target.dir)
+                    .ok()
+                    .map(|rel| rel.to_string_lossy().to_string())
+            });
+        if let Some(rel_path) = rel_path {
+            items.push(ConflictItem::ProviderCopy { rel_path, copy_path });
+        }
+    }
+
+    items.sort_by(|a, b| a.rel_path().cmp(b.rel_path()));
+    items
+}
+
+/// Read a provider conflict copy, decrypting it if the shared copy is
+/// encrypted (then also returning the path stored in it)
+fn read_copy(copy_path: &Path, cipher: Option<&Cipher>) -> Result<(Option<String>, Vec<u8>)> {
+    let content =
+        fs::read(copy_path).with_context(|| format!("Failed to read conflict copy: {:?}", copy_path))?;
+    match cipher {
+        Some(cipher) => {
+            let (rel_path, content) = cipher
+                .decrypt(&content)
+                .with_context(|| format!("Failed to decrypt conflict copy: {:?}", copy_path))?;
+            Ok((Some(rel_path), content))
+        }
+        None => Ok((None, content)),
+    }
+}
+
+/// One-line description of a conflict
+fn describe(config: &Config, item: &ConflictItem) -> String {
+    match item {
+        ConflictItem::Engine { rel_path, conflict } => format!(
+            "{} (changed on both sides, kept {:?} on {})",
+            rel_path,
+            conflict.kept,
+            conflict.detected_at.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M")
+        ),
+        ConflictItem::ProviderCopy { rel_path, copy_path } => format!(
+            "{} ({} conflict copy: {})",
+            rel_path,
+            config.target.name(),
+            copy_path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default()
+        ),
+    }
+}
+
+/// Load local, remote and baseline contents for a conflict
+///
+/// The side the engine kept is the file as it is now (it may have been
+/// edited since); the discarded side only survives in the baseline store.
+fn load_contents(
+    config: &Config,
+    remote: &dyn Remote,
+    state: &SyncState,
+    baseline: &BaselineStore,
+    transforms: &Transforms,
+    cipher: Option<&Cipher>,
+    item: &ConflictItem,
+) -> Result<ConflictContents> {
+    let rel_path = item.rel_path();
+    let shown_local = fs::read(config.claude_dir.join(rel_path)).ok();
+    let shown_remote = remote.stat(rel_path)?;
+    let current = shown_local.as_ref().map(|local| {
+        let shared = shown_remote.as_ref().and_then(|_| remote.read(rel_path).ok());
+        transforms.to_shared(rel_path, local, shared.as_deref())
+    });
+
+    let contents = match item {
+        ConflictItem::Engine { conflict, .. } => {
+            let (local, remote) = match conflict.kept {
+                ConflictSide::Local => (current, baseline.get(&conflict.remote_sha256)),
+                ConflictSide::Remote => (baseline.get(&conflict.local_sha256), current),
+            };
+            ConflictContents {
+                rel_path: rel_path.to_string(),
+                local,
+                remote,
+                base: conflict.base_sha256.as_deref().and_then(|s| baseline.get(s)),
+                local_label: label("local", conflict.kept == ConflictSide::Local),
+                remote_label: label("remote", conflict.kept == ConflictSide::Remote),
+                shown_local,
+                shown_remote,
+                leftover: conflict.conflict_copy.clone(),
+            }
+        }
+        ConflictItem::ProviderCopy { copy_path, .. } => ConflictContents {
+            rel_path: rel_path.to_string(),
+            local: current,
+            remote: Some(read_copy(copy_path, cipher)?.1),
+            base: state.files.get(rel_path).and_then(|f| baseline.get(&f.sha256)),
+            local_label: "local".to_string(),
+            remote_label: "remote (conflict copy)".to_string(),
+            shown_local,
+            shown_remote,
+            leftover: Some(copy_path.clone()),
+        },
+    };
+    Ok(contents)
+}
+
+fn label(side: &str, kept: bool) -> String {
+    if kept {
+        format!("{} (kept, as it is now)", side)
+    } else {
+        format!("{} (discarded)", side)
+    }
+}
+
+/// Print unified diffs: baseline -> each side, or local -> remote without a baseline
+fn show_diff(contents: &ConflictContents) {
+    let text = |bytes: &Option<Vec<u8>>| -> Option<String> {
+        bytes
+            .as_ref()
+            .and_then(|b| String::from_utf8(b.clone()).ok())
+    };
+
+    let local = text(&contents.local);
+    let remote = text(&contents.remote);
+
+    if contents.local.is_none() || contents.remote.is_none() {
+        println!("(content of one side is not available)");
+    }
+
+    let (Some(local), Some(remote)) = (local, remote) else {
+        println!("(binary or unavailable content, no diff shown)");
+        return;
+    };
+
+    match text(&contents.base) {
+        Some(base) => {
+            print_diff(&base, &local, "baseline", &contents.local_label);
+            print_diff(&base, &remote, "baseline", &contents.remote_label);
+        }
+        None => {
+            println!("(no baseline available)");
+            print_diff(&local, &remote, &contents.local_label, &contents.remote_label);
+        }
+    }
+}
+
+/// Print a unified diff of two texts
+pub fn print_diff(old: &str, new: &str, old_label: &str, new_label: &str) {
+    let diff = TextDiff::from_lines(old, new);
+    if diff.ratio() == 1.0 {
+        println!("--- {} / +++ {}: identical", old_label, new_label);
+        return;
+    }
+    print!(
+        "{}",
+        diff.unified_diff()
+            .context_radius(3)
+            .header(old_label, new_label)
+    );
+}
+
+/// Ask the user what to do with a conflict
+fn prompt(input: &mut impl BufRead) -> Result<Choice> {
+    loop {
+        print!("Keep [l]ocal, [r]emote, [e]dit merge, [s]kip, [q]uit? ");
+        io::stdout().flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(Choice::Quit);
+        }
+
+        match line.trim().to_lowercase().as_str() {
+            "l" | "local" => return Ok(Choice::Local),
+            "r" | "remote" => return Ok(Choice::Remote),
+            "e" | "edit" => return Ok(Choice::Edit),
+            "s" | "skip" => return Ok(Choice::Skip),
+            "q" | "quit" => return Ok(Choice::Quit),
+            _ => println!("Please answer l, r, e, s or q."),
+        }
+    }
+}
+
+/// Write `content` to a new file in the temp directory that only the user
+/// can read (the name ends with `file_name`, for the editor's syntax
+/// highlighting)
+fn create_merge_file(file_name: &str, content: &[u8]) -> Result<PathBuf> {
+    let mut attempt = 0;
+    loop {
+        let path = std::env::temp_dir().join(format!(
+            "claude-sync-merge-{}-{}-{}",
+            std::process::id(),
+            attempt,
+            file_name
+        ));
+        let mut file = match remote::create_private(&path) {
+            Ok(file) => file,
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                attempt += 1;
+                continue;
+            }
+            Err(e) => return Err(e).with_context(|| format!("Failed to create {:?}", path)),
+        };
+        if let Err(e) = file.write_all(content) {
+            fs::remove_file(&path).ok();
+            return Err(e).with_context(|| format!("Failed to write {:?}", path));
+        }
+        return Ok(path);
+    }
+}
+
+/// Open both sides, with conflict markers around differing hunks, in $EDITOR
+fn edit_merge(rel_path: &str, contents: &ConflictContents) -> Result<Vec<u8>> {
+    let local = String::from_utf8(contents.local.clone().unwrap_or_default())
+        .context("Local content is not text")?;
+    let remote = String::from_utf8(contents.remote.clone().unwrap_or_default())
+        .context("Remote content is not text")?;
+
+    let file_name = Path::new(rel_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "merge".to_string());
+    let merge_path = create_merge_file(&file_name, merge_template(&local, &remote).as_bytes())?;
+
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+    let status = Command::new("sh")
+        .arg("-c")
+        .arg(format!("{} \"$1\"", editor))
+        .arg("sh")
+        .arg(&merge_path)
+        .status()
+        .with_context(|| format!("Failed to run editor: {}", editor))?;
+
+    let merged = fs::read(&merge_path)?;
+    fs::remove_file(&merge_path).ok();
+
+    if !status.success() {
+        bail!("Editor exited with {}", status);
+    }
+    if String::from_utf8_lossy(&merged)
+        .lines()
+        .any(|l| l.starts_with("<<<<<<<") || l.starts_with(">>>>>>>"))
+    {
+        bail!("Conflict markers are still present");
+    }
+
+    Ok(merged)
+}
+
+/// Build a merge file: shared lines as-is, differing hunks wrapped in
+/// git-style conflict markers
+fn merge_template(local: &str, remote: &str) -> String {
+    let diff = TextDiff::from_lines(local, remote);
+    let mut out = String::new();
+
+    let push_lines = |out: &mut String, lines: &[&str]| {
+        for line in lines {
+            out.push_str(line);
+            if !line.ends_with('\n') {
+                out.push('\n');
+            }
+        }
+    };
+
+    for op in diff.ops() {
+        let old = &diff.old_slices()[op.old_range()];
+        let new = &diff.new_slices()[op.new_range()];
+
+        if op.tag() == DiffTag::Equal {
+            push_lines(&mut out, old);
+        } else {
+            out.push_str("<<<<<<< local\n");
+            push_lines(&mut out, old);
+            out.push_str("=======\n");
+            push_lines(&mut out, new);
+            out.push_str(">>>>>>> remote\n");
+        }
+    }
+
+    out
+}
+
+/// Write the chosen (shared form) content to both sides and record it as the
+/// new baseline
+///
+/// Refuses if either side changed since `contents` were loaded, rather than
+/// overwriting an edit nobody has seen.
+fn apply_resolution(
+    config: &Config,
+    remote: &dyn Remote,
+    state: &mut SyncState,
+    baseline: &BaselineStore,
+    transforms: &Transforms,
+    contents: &ConflictContents,
+    content: &[u8],
+) -> Result<()> {
+    let rel_path = contents.rel_path.as_str();
+
+    if rel_path.ends_with(".json") {
+        serde_json::from_slice::<serde_json::Value>(content)
+            .with_context(|| format!("Resolved content of {} is not valid JSON", rel_path))?;
+    }
+
+    transforms.check_secrets(rel_path, content)?;
+
+    let local_path = config.claude_dir.join(rel_path);
+    if fs::read(&local_path).ok() != contents.shown_local {
+        bail!("{:?} changed since it was shown; run resolve again", local_path);
+    }
+
+    let local_content = transforms.to_local(rel_path, content, contents.shown_local.as_deref());
+
+    let mode = contents
+        .shown_remote
+        .as_ref()
+        .and_then(|f| f.mode)
+        .or_else(|| fs::metadata(&local_path).ok().and_then(|m| file_mode(&m)));
+    remote.write(rel_path, content, mode, contents.shown_remote.as_ref())?;
+
+    write_atomic(&local_path, &local_content, None)
+        .with_context(|| format!("Failed to write {:?}", local_path))?;
+
+    baseline.put(content)?;
+    let file_state = transforms
+        .local_state(rel_path, &local_path, || Some(content.to_vec()))
+        .with_context(|| format!("Failed to read {:?}", local_path))?;
+    state.update_file(rel_path, file_state);
+    state.conflicts.remove(rel_path);
+
+    // The leftover copy has been dealt with
+    if let Some(path) = &contents.leftover {
+        fs::remove_file(path).ok();
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use sha2::{Digest, Sha256};
+
+    /// Config syncing `home`/.claude with the folder `home`/share/Sync
+    fn folder_config(home: &TempDir) -> Config {
+        let share = home.path().join("share");
+        fs::create_dir_all(share.join("Sync")).unwrap();
+        Config::for_test(
+            home.path(),
+            &format!(
+                "[paths]\nprovider = \"directory\"\nbase = {:?}\nfolder = \"Sync\"\n",
+                share.to_string_lossy()
+            ),
+        )
+    }
+
+    fn sha256(content: &str) -> String {
+        format!("{:x}", Sha256::digest(content))
+    }
+
+    /// A conflict the engine resolved in favour of the local side, with
+    /// both sides since edited to `now`
+    fn engine_conflict(home: &TempDir, config: &Config, now: &str) -> (SyncState, BaselineStore) {
+        let baseline = BaselineStore::new(config.local_baseline_dir());
+        baseline.put(b"remote edit\n").unwrap();
+        home.write(".claude/CLAUDE.md", now);
+        home.write("share/Sync/CLAUDE.md", now);
+        let copy = home.write(".claude/CLAUDE.conflict-other-20250101_000000.md", "remote edit\n");
+
+        let mut state = SyncState::default();
+        state.conflicts.insert(
+            "CLAUDE.md".to_string(),
+            PendingConflict {
+                base_sha256: None,
+                local_sha256: sha256("local edit\n"),
+                remote_sha256: sha256("remote edit\n"),
+                kept: ConflictSide::Local,
+                conflict_copy: Some(copy),
+                detected_at: chrono::Utc::now(),
+            },
+        );
+        (state, baseline)
+    }
+
+    fn load(config: &Config, state: &SyncState, baseline: &BaselineStore) -> ConflictContents {
+        let remote = remote::open(config).unwrap();
+        let items = collect_conflicts(config, state, None);
+        assert_eq!(items.len(), 1);
+        load_contents(config, remote.as_ref(), state, baseline, &Transforms::load(config).unwrap(), None, &items[0])
+            .unwrap()
+    }
+
+    #[test]
+    fn merge_template_marks_differing_hunks() {
+        for (local, remote, expected) in [
+            ("a\nb\n", "a\nb\n", "a\nb\n"),
+            (
+                "a\nb\nc\n",
+                "a\nB\nc\n",
+                "a\n<<<<<<< local\nb\n=======\nB\n>>>>>>> remote\nc\n",
+            ),
+            ("a\n", "a\nb\n", "a\n<<<<<<< local\n=======\nb\n>>>>>>> remote\n"),
+            // Without a final newline
+            ("a\nb", "a\nc", "a\n<<<<<<< local\nb\n=======\nc\n>>>>>>> remote\n"),
+        ] {
+            assert_eq!(merge_template(local, remote), expected, "{:?} / {:?}", local, remote);
+        }
+    }
+
+    #[test]
+    fn kept_side_is_shown_as_it_is_now() {
+        let home = TempDir::new();
+        let config = folder_config(&home);
+        let (state, baseline) = engine_conflict(&home, &config, "local edit, then more\n");
+
+        let contents = load(&config, &state, &baseline);
+        assert_eq!(contents.local.as_deref(), Some(&b"local edit, then more\n"[..]));
+        assert_eq!(contents.remote.as_deref(), Some(&b"remote edit\n"[..]));
+    }
+
+    #[test]
+    fn resolution_is_written_to_both_sides() {
+        let home = TempDir::new();
+        let config = folder_config(&home);
+        let (mut state, baseline) = engine_conflict(&home, &config, "local edit\n");
+        let remote = remote::open(&config).unwrap();
+        let transforms = Transforms::load(&config).unwrap();
+
+        let contents = load(&config, &state, &baseline);
+        let chosen = contents.remote.clone().unwrap();
+        apply_resolution(&config, remote.as_ref(), &mut state, &baseline, &transforms, &contents, &chosen).unwrap();
+
+        assert_eq!(fs::read_to_string(config.claude_dir.join("CLAUDE.md")).unwrap(), "remote edit\n");
+        assert_eq!(fs::read_to_string(config.target.dir.join("CLAUDE.md")).unwrap(), "remote edit\n");
+        assert_eq!(state.files["CLAUDE.md"].sha256, sha256("remote edit\n"));
+        assert!(state.conflicts.is_empty());
+        assert!(!contents.leftover.unwrap().exists());
+    }
+
+    #[test]
+    fn resolution_is_refused_after_an_edit() {
+        let home = TempDir::new();
+        let config = folder_config(&home);
+        let (mut state, baseline) = engine_conflict(&home, &config, "local edit\n");
+        let remote = remote::open(&config).unwrap();
+        let transforms = Transforms::load(&config).unwrap();
+
+        for (edited, other) in [
+            (".claude/CLAUDE.md", "share/Sync/CLAUDE.md"),
+            ("share/Sync/CLAUDE.md", ".claude/CLAUDE.md"),
+        ] {
+            home.write(".claude/CLAUDE.md", "local edit\n");
+            home.write("share/Sync/CLAUDE.md", "local edit\n");
+            let contents = load(&config, &state, &baseline);
+
+            home.write(edited, "edited while resolving\n");
+            let chosen = contents.remote.clone().unwrap();
+            let applied =
+                apply_resolution(&config, remote.as_ref(), &mut state, &baseline, &transforms, &contents, &chosen);
+            assert!(applied.is_err(), "{} was edited", edited);
+            assert_eq!(fs::read_to_string(home.path().join(edited)).unwrap(), "edited while resolving\n");
+            assert_eq!(fs::read_to_string(home.path().join(other)).unwrap(), "local edit\n");
+            assert!(state.conflicts.contains_key("CLAUDE.md"));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn merge_files_are_private_and_unique() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let first = create_merge_file("settings.json", b"one").unwrap();
+        let second = create_merge_file("settings.json", b"two").unwrap();
+        assert_ne!(first, second);
+        assert!(first.to_string_lossy().ends_with("-settings.json"));
+        assert_eq!(fs::read(&second).unwrap(), b"two");
+        for path in [&first, &second] {
+            assert_eq!(fs::metadata(path).unwrap().permissions().mode() & 0o777, 0o600);
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
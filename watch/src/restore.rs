@@ -0,0 +1,436 @@
+//! Inspecting and restoring backup snapshots (`claude-sync-watch backups`)
+//!
+//! Snapshots are compared with the synced files in ~/.claude as they are now.
+//! A restore first snapshots the current files, so it can be undone in turn,
+//! and holds the sync lock so a running daemon doesn't sync half-restored
+//! files. The restored files are then ordinary local changes, shared by the
+//! next sync.
+
+use crate::backup::{BackupStore, Snapshot, SnapshotFile};
+use crate::config::Config;
+use crate::filter::SyncSet;
+use crate::lock::SyncLock;
+use crate::remote::{remove_empty_parents, write_atomic};
+use crate::resolve::print_diff;
+use crate::state::SyncState;
+use anyhow::{bail, Context, Result};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::io::{self, Write};
+
+/// How a file in ~/.claude differs from a snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Unchanged,
+    /// Content or permissions changed since
+    Modified,
+    /// In the snapshot, missing now
+    Deleted,
+    /// Not in the snapshot
+    Added,
+}
+
+impl Status {
+    fn label(self) -> &'static str {
+        match self {
+            Status::Unchanged => "",
+            Status::Modified => "modified",
+            Status::Deleted => "deleted",
+            Status::Added => "new",
+        }
+    }
+}
+
+/// Compare a snapshot's files with the current ones, by path
+fn compare<'a>(
+    snapshot: &'a BTreeMap<String, SnapshotFile>,
+    current: &'a BTreeMap<String, SnapshotFile>,
+) -> Vec<(&'a str, Status)> {
+    let paths: BTreeSet<&str> = snapshot.keys().chain(current.keys()).map(String::as_str).collect();
+    paths
+        .into_iter()
+        .map(|rel_path| {
+            let status = match (snapshot.get(rel_path), current.get(rel_path)) {
+                (Some(then), Some(now)) if then == now => Status::Unchanged,
+                (Some(_), Some(_)) => Status::Modified,
+                (Some(_), None) => Status::Deleted,
+                (None, _) => Status::Added,
+            };
+            (rel_path, status)
+        })
+        .collect()
+}
+
+/// One-line summary of a comparison
+fn summarize(statuses: &[(&str, Status)]) -> String {
+    let count = |status| statuses.iter().filter(|(_, s)| *s == status).count();
+    let parts: Vec<String> = [Status::Modified, Status::Deleted, Status::Added]
+        .into_iter()
+        .filter(|&status| count(status) > 0)
+        .map(|status| format!("{} {}", count(status), status.label()))
+        .collect();
+
+    if parts.is_empty() {
+        "same as now".to_string()
+    } else {
+        format!("{} since", parts.join(", "))
+    }
+}
+
+/// Find a snapshot by id or unique id prefix
+fn find(store: &BackupStore, id: &str) -> Result<Snapshot> {
+    if let Ok(snapshot) = store.load(id) {
+        return Ok(snapshot);
+    }
+
+    let mut matching: Vec<Snapshot> = store
+        .list()?
+        .into_iter()
+        .filter(|s| s.id.starts_with(id))
+        .collect();
+    match matching.len() {
+        0 => bail!("No backup {} (run `claude-sync-watch backups list` to see backups)", id),
+        1 => Ok(matching.remove(0)),
+        n => bail!("{} backups start with {}; give more of the id", n, id),
+    }
+}
+
+/// Check if a path was selected on the command line (all paths if none were)
+fn is_selected(rel_path: &str, paths: &[String]) -> bool {
+    paths.is_empty()
+        || paths.iter().any(|p| {
+            let p = p.trim_matches('/');
+            rel_path == p || rel_path.strip_prefix(p).is_some_and(|rest| rest.starts_with('/'))
+        })
+}
+
+/// Make sure each path given on the command line names something
+fn check_paths(statuses: &[(&str, Status)], paths: &[String]) -> Result<()> {
+    for p in paths {
+        if !statuses.iter().any(|(rel_path, _)| is_selected(rel_path, std::slice::from_ref(p))) {
+            bail!("{} is neither in the backup nor in ~/.claude", p);
+        }
+    }
+    Ok(())
+}
+
+/// `claude-sync-watch backups list`
+pub fn list(config: &Config) -> Result<()> {
+    let store = BackupStore::new(config);
+    let current = store.current(&config.claude_dir, &SyncSet::new(config))?;
+    let snapshots = store.list()?;
+    if snapshots.is_empty() {
+        println!("No backups yet.");
+        return Ok(());
+    }
+
+    let last_id = store.last_id();
+    println!("{} backup(s), newest first:", snapshots.len());
+    for snapshot in snapshots.iter().rev() {
+        println!(
+            "  {}  {}  {:>3} file(s)  {}{}",
+            snapshot.id,
+            snapshot.created.format("%Y-%m-%d %H:%M:%S"),
+            snapshot.files.len(),
+            summarize(&compare(&snapshot.files, &current)),
+            if last_id.as_deref() == Some(snapshot.id.as_str()) { "  (undo)" } else { "" }
+        );
+    }
+
+    Ok(())
+}
+
+/// `claude-sync-watch backups show <id>`
+pub fn show(config: &Config, id: &str) -> Result<()> {
+    let store = BackupStore::new(config);
+    let snapshot = find(&store, id)?;
+    let current = store.current(&config.claude_dir, &SyncSet::new(config))?;
+    let statuses = compare(&snapshot.files, &current);
+
+    println!(
+        "Backup {} ({}), {} file(s), {}:",
+        snapshot.id,
+        snapshot.created.format("%Y-%m-%d %H:%M:%S"),
+        snapshot.files.len(),
+        summarize(&statuses)
+    );
+    for (rel_path, status) in &statuses {
+        let size = snapshot.files.get(*rel_path).map(|f| format!("  ({} bytes)", f.size));
+        println!("  {:<8}  {}{}", status.label(), rel_path, size.unwrap_or_default());
+    }
+
+    Ok(())
+}
+
+/// `claude-sync-watch backups diff <id> [paths]`
+pub fn diff(config: &Config, id: &str, paths: &[String]) -> Result<()> {
+    let store = BackupStore::new(config);
+    let snapshot = find(&store, id)?;
+    let current = store.current(&config.claude_dir, &SyncSet::new(config))?;
+    let statuses = compare(&snapshot.files, &current);
+    check_paths(&statuses, paths)?;
+
+    let mut shown = 0;
+    for (rel_path, status) in statuses {
+        if status == Status::Unchanged || !is_selected(rel_path, paths) {
+            continue;
+        }
+        shown += 1;
+
+        let then = match snapshot.files.get(rel_path) {
+            Some(file) => store.read(file)?,
+            None => Vec::new(),
+        };
+        let now = match status {
+            Status::Deleted => Vec::new(),
+            _ => fs::read(config.claude_dir.join(rel_path))?,
+        };
+
+        let old_label = match status {
+            Status::Added => "/dev/null".to_string(),
+            _ => format!("backup {}/{}", snapshot.id, rel_path),
+        };
+        let new_label = match status {
+            Status::Deleted => "/dev/null".to_string(),
+            _ => format!("~/.claude/{}", rel_path),
+        };
+        match (String::from_utf8(then), String::from_utf8(now)) {
+            (Ok(then), Ok(now)) => print_diff(&then, &now, &old_label, &new_label),
+            _ => println!("Binary files {} and {} differ", old_label, new_label),
+        }
+    }
+
+    if shown == 0 {
+        println!("No changes since backup {}.", snapshot.id);
+    }
+
+    Ok(())
+}
+
+/// `claude-sync-watch backups restore <id> [paths]`
+///
+/// Files are written back as they were in the snapshot; synced files the
+/// snapshot doesn't have are removed.
+pub fn restore(config: &Config, id: &str, paths: &[String], yes: bool) -> Result<()> {
+    // Keep the daemon from syncing while files are compared and replaced
+    let _lock = SyncLock::acquire(&config.local_sync_lock_path(), || {
+        println!("Waiting for the sync daemon to finish the current sync...")
+    })?;
+
+    let store = BackupStore::new(config);
+    let sync_set = SyncSet::new(config);
+    let snapshot = find(&store, id)?;
+    let current = store.current(&config.claude_dir, &sync_set)?;
+    let statuses = compare(&snapshot.files, &current);
+    check_paths(&statuses, paths)?;
+
+    let plan: Vec<(&str, Status)> = statuses
+        .into_iter()
+        .filter(|&(rel_path, status)| status != Status::Unchanged && is_selected(rel_path, paths))
+        .collect();
+    if plan.is_empty() {
+        println!("Nothing to restore: the files already match backup {}.", snapshot.id);
+        return Ok(());
+    }
+
+    println!(
+        "Restoring from backup {} ({}):",
+        snapshot.id,
+        snapshot.created.format("%Y-%m-%d %H:%M:%S")
+    );
+    for (rel_path, status) in &plan {
+        let action = if *status == Status::Added { "remove" } else { "restore" };
+        println!("  {:<7}  {}", action, rel_path);
+    }
+    if !yes && !confirm("Proceed?")? {
+        println!("Aborted.");
+        return Ok(());
+    }
+
+    let (safety, _) = store.snapshot(&config.claude_dir, &sync_set)?;
+    println!("Saved the current files as backup {}.", safety.id);
+
+    let mut written = Vec::new();
+    for (rel_path, status) in &plan {
+        let path = config.claude_dir.join(rel_path);
+        match snapshot.files.get(*rel_path) {
+            Some(file) => {
+                let content = store.read(file)?;
+                write_atomic(&path, &content, file.mode).with_context(|| format!("Failed to restore {:?}", path))?;
+                written.push(*rel_path);
+            }
+            None => {
+                fs::remove_file(&path).with_context(|| format!("Failed to remove {:?}", path))?;
+                remove_empty_parents(&path, &config.claude_dir);
+            }
+        }
+        log::debug!("Restored {} ({:?})", rel_path, status);
+    }
+
+    // A restored file may have been deleted by a sync. Without its tombstone
+    // it is a local change to share, rather than a stale copy to delete again.
+    let state_path = config.local_state_path();
+    if state_path.exists() {
+        let mut state = SyncState::load(&state_path)?;
+        let before = state.tombstones.len();
+        state.tombstones.retain(|rel_path, _| !written.contains(&rel_path.as_str()));
+        if state.tombstones.len() != before {
+            state.save(&state_path)?;
+        }
+    }
+
+    println!(
+        "Restored {} file(s). Undo with `claude-sync-watch backups undo`; the next sync shares them.",
+        plan.len()
+    );
+
+    Ok(())
+}
+
+/// `claude-sync-watch backups undo`: restore the snapshot taken before the
+/// last sync that changed local files, or before the last restore
+pub fn undo(config: &Config, yes: bool) -> Result<()> {
+    let store = BackupStore::new(config);
+    let Some(id) = store.last_id() else {
+        bail!("Nothing to undo (run `claude-sync-watch backups list` to see backups)");
+    };
+
+    println!("Undoing the last sync that changed local files (or the last restore).");
+
+    restore(config, &id, &[], yes)
+}
+
+/// `claude-sync-watch backups prune`: apply the retention policy now, or
+/// list what it would remove
+pub fn prune(config: &Config, dry_run: bool) -> Result<()> {
+    let store = BackupStore::new(config);
+    let expired = match dry_run {
+        true => store.expired(&config.retention)?,
+        false => store.prune(&config.retention)?,
+    };
+
+    if expired.is_empty() {
+        println!("No backups to prune.");
+        return Ok(());
+    }
+
+    println!(
+        "{} {} backup(s):",
+        if dry_run { "Would remove" } else { "Removed" },
+        expired.len()
+    );
+    for snapshot in &expired {
+        println!(
+            "  {}  {}  {} file(s)",
+            snapshot.id,
+            snapshot.created.format("%Y-%m-%d %H:%M:%S"),
+            snapshot.files.len()
+        );
+    }
+
+    Ok(())
+}
+
+/// Ask a yes/no question, defaulting to no
+fn confirm(question: &str) -> Result<bool> {
+    print!("{} [y/N] ", question);
+    io::stdout().flush()?;
+
+    let mut line = String::new();
+    io::stdin().read_line(&mut line)?;
+    Ok(matches!(line.trim().to_lowercase().as_str(), "y" | "yes"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::state::Tombstone;
+    use crate::testutil::TempDir;
+
+    fn test_config(home: &TempDir) -> Config {
+        Config::for_test(home.path(), "[paths]\nprovider = \"s3\"\n\n[s3]\nbucket = \"b\"\n")
+    }
+
+    fn read(config: &Config, rel_path: &str) -> Option<String> {
+        fs::read_to_string(config.claude_dir.join(rel_path)).ok()
+    }
+
+    #[test]
+    fn restore_then_undo() {
+        let home = TempDir::new();
+        let config = test_config(&home);
+        let store = BackupStore::new(&config);
+        home.write(".claude/settings.json", "{}");
+        home.write(".claude/CLAUDE.md", "Be brief\n");
+        let (before, _) = store.snapshot(&config.claude_dir, &SyncSet::new(&config)).unwrap();
+
+        home.write(".claude/settings.json", "{\"model\": \"opus\"}");
+        fs::remove_file(config.claude_dir.join("CLAUDE.md")).unwrap();
+        home.write(".claude/skills/review/SKILL.md", "Review the diff\n");
+
+        restore(&config, &before.id, &[], true).unwrap();
+        assert_eq!(read(&config, "settings.json").as_deref(), Some("{}"));
+        assert_eq!(read(&config, "CLAUDE.md").as_deref(), Some("Be brief\n"));
+        assert!(!config.claude_dir.join("skills").exists());
+
+        // The files replaced by the restore were saved first
+        let safety = store.last_id().unwrap();
+        assert_ne!(safety, before.id);
+        assert_eq!(store.load(&safety).unwrap().files.len(), 2);
+
+        undo(&config, true).unwrap();
+        assert_eq!(read(&config, "settings.json").as_deref(), Some("{\"model\": \"opus\"}"));
+        assert_eq!(read(&config, "CLAUDE.md"), None);
+        assert_eq!(read(&config, "skills/review/SKILL.md").as_deref(), Some("Review the diff\n"));
+    }
+
+    #[test]
+    fn restore_selected_paths_only() {
+        let home = TempDir::new();
+        let config = test_config(&home);
+        let store = BackupStore::new(&config);
+        home.write(".claude/settings.json", "{}");
+        home.write(".claude/skills/review/SKILL.md", "Review the diff\n");
+        let (before, _) = store.snapshot(&config.claude_dir, &SyncSet::new(&config)).unwrap();
+
+        home.write(".claude/settings.json", "{\"model\": \"opus\"}");
+        home.write(".claude/skills/review/SKILL.md", "Review carefully\n");
+
+        restore(&config, &before.id, &["skills/".to_string()], true).unwrap();
+        assert_eq!(read(&config, "settings.json").as_deref(), Some("{\"model\": \"opus\"}"));
+        assert_eq!(read(&config, "skills/review/SKILL.md").as_deref(), Some("Review the diff\n"));
+
+        let missing = restore(&config, &before.id, &["agents".to_string()], true);
+        assert!(missing.is_err());
+    }
+
+    #[test]
+    fn restored_files_lose_their_tombstones() {
+        let home = TempDir::new();
+        let config = test_config(&home);
+        let store = BackupStore::new(&config);
+        home.write(".claude/CLAUDE.md", "Be brief\n");
+        let (before, _) = store.snapshot(&config.claude_dir, &SyncSet::new(&config)).unwrap();
+
+        // A sync deleted it (and mcp.json, which isn't restored)
+        fs::remove_file(config.claude_dir.join("CLAUDE.md")).unwrap();
+        let mut state = SyncState::default();
+        for rel_path in ["CLAUDE.md", "mcp.json"] {
+            state.tombstones.insert(
+                rel_path.to_string(),
+                Tombstone {
+                    sha256: "sha".to_string(),
+                    deleted_at: chrono::Utc::now(),
+                    machine_id: "other".to_string(),
+                },
+            );
+        }
+        state.save(&config.local_state_path()).unwrap();
+
+        restore(&config, &before.id, &[], true).unwrap();
+        assert_eq!(read(&config, "CLAUDE.md").as_deref(), Some("Be brief\n"));
+        let state = SyncState::load(&config.local_state_path()).unwrap();
+        let tombstones: Vec<&str> = state.tombstones.keys().map(String::as_str).collect();
+        assert_eq!(tombstones, ["mcp.json"]);
+    }
+}
@@ -0,0 +1,586 @@
+//! Secrets in outgoing content
+//!
+//! Before a file is pushed, its shared form is scanned for obvious secrets:
+//! - API keys and tokens with well-known prefixes (`sk-`, `ghp_`, `xoxb-`,
+//!   `AIza`, ...) and AWS access key IDs
+//! - `Bearer` tokens and PEM private keys
+//! - `NAME=value` / `NAME: value` assignments whose name looks like a
+//!   secret (`API_KEY`, `TOKEN`, `PASSWORD`, ...) and whose value looks random
+//! - in JSON, every value in an `env` object (`mcpServers.<name>.env`, the
+//!   `env` of settings.json) under such a name
+//!
+//! Depending on `secrets.mode` the push goes ahead with a warning, is blocked,
+//! or the values are replaced with `${secret:<name>}` references. Redacted
+//! values are kept in a local store (~/.config/claude-sync/secrets.json) and
+//! put back when the file is pulled; a machine whose store lacks a value keeps
+//! the reference until the value is added to the store or the local file.
+
+use crate::config::{Config, SecretsMode};
+use crate::remote;
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::Range;
+use std::path::PathBuf;
+
+/// Start of a reference to a redacted value
+const REFERENCE_PREFIX: &str = "${secret:";
+
+/// Token prefixes: (prefix, kind, minimum length after the prefix)
+const TOKEN_PREFIXES: &[(&str, &str, usize)] = &[
+    ("sk-ant-", "anthropic-key", 20),
+    ("sk-", "api-key", 20),
+    ("github_pat_", "github-token", 30),
+    ("ghp_", "github-token", 30),
+    ("gho_", "github-token", 30),
+    ("ghu_", "github-token", 30),
+    ("ghs_", "github-token", 30),
+    ("ghr_", "github-token", 30),
+    ("glpat-", "gitlab-token", 20),
+    ("xoxb-", "slack-token", 20),
+    ("xoxp-", "slack-token", 20),
+    ("xapp-", "slack-token", 20),
+    ("AIza", "google-api-key", 35),
+];
+
+/// Words (`_`-separated for several) that mark a value as secret when they
+/// appear as whole parts of its name
+const SECRET_NAME_PARTS: &[&str] = &[
+    "APIKEY", "API_KEY", "ACCESS_KEY", "PRIVATE_KEY", "SECRET", "TOKEN", "PASSWORD", "PASSWD",
+    "CREDENTIAL", "CREDENTIALS", "AUTHORIZATION",
+];
+
+/// Words that only mark a value as secret at the end of its name
+/// (`BASIC_AUTH`, but not `AUTH_URL`)
+const SECRET_NAME_ENDINGS: &[&str] = &["AUTH"];
+
+/// A secret found in a file
+#[derive(Debug, Clone)]
+struct Finding {
+    /// Where it is, for messages (JSON path or line number)
+    location: String,
+    /// Name of its reference
+    name: String,
+    /// Byte range in the string it was found in
+    range: Range<usize>,
+}
+
+/// Secret scanning and redaction for this machine
+pub struct Secrets {
+    mode: SecretsMode,
+    store_path: PathBuf,
+    /// Redacted values (reference name -> value)
+    store: RefCell<BTreeMap<String, String>>,
+}
+
+impl Secrets {
+    /// Load the settings and, when redacting, the local store
+    pub fn load(config: &Config) -> Result<Self> {
+        let store_path = PathBuf::from(&config.secrets.store);
+        let store = if config.secrets.mode == SecretsMode::Redact && store_path.exists() {
+            let content = fs::read_to_string(&store_path)
+                .with_context(|| format!("Failed to read secrets store: {:?}", store_path))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse secrets store: {:?}", store_path))?
+        } else {
+            BTreeMap::new()
+        };
+
+        Ok(Self {
+            mode: config.secrets.mode,
+            store_path,
+            store: RefCell::new(store),
+        })
+    }
+
+    /// Check if secrets are replaced with references in the shared copy
+    pub fn redacts(&self) -> bool {
+        self.mode == SecretsMode::Redact
+    }
+
+    /// Check content about to be pushed: fail in block mode, warn in warn
+    /// mode
+    pub fn check(&self, rel_path: &str, shared: &[u8]) -> Result<()> {
+        let locations = match self.mode {
+            SecretsMode::Off => return Ok(()),
+            _ => self.scan(rel_path, shared),
+        };
+        if locations.is_empty() {
+            return Ok(());
+        }
+
+        let message = format!("{} contains what look like secrets ({})", rel_path, locations.join(", "));
+        match self.mode {
+            SecretsMode::Block => bail!(
+                "{}; not pushed. Remove them, or set secrets.mode = \"redact\" to push references instead",
+                message
+            ),
+            _ => {
+                log::warn!("{}", message);
+                Ok(())
+            }
+        }
+    }
+
+    /// Locations of secrets in content (empty when scanning is off)
+    pub fn scan(&self, rel_path: &str, content: &[u8]) -> Vec<String> {
+        if self.mode == SecretsMode::Off {
+            return Vec::new();
+        }
+        let mut locations = Vec::new();
+        let _ = map_strings(rel_path, content, |path, text| {
+            locations.extend(find_secrets(rel_path, path, text).into_iter().map(|f| f.location));
+            None
+        });
+        locations
+    }
+
+    /// Replace secrets with references, remembering their values (push)
+    pub fn redact(&self, rel_path: &str, content: Vec<u8>) -> Vec<u8> {
+        if !self.redacts() {
+            return content;
+        }
+
+        let mut added = false;
+        let redacted = map_strings(rel_path, &content, |path, text| {
+            let findings = find_secrets(rel_path, path, text);
+            if findings.is_empty() {
+                return None;
+            }
+
+            let mut out = text.to_string();
+            for finding in findings.iter().rev() {
+                let value = &text[finding.range.clone()];
+                let mut store = self.store.borrow_mut();
+                if store.get(&finding.name).map(String::as_str) != Some(value) {
+                    store.insert(finding.name.clone(), value.to_string());
+                    added = true;
+                }
+                out.replace_range(finding.range.clone(), &reference(&finding.name));
+            }
+            Some(out)
+        });
+
+        if added {
+            self.save();
+        }
+        redacted.unwrap_or(content)
+    }
+
+    /// Put redacted values back in place of their references (pull)
+    pub fn inject(&self, rel_path: &str, content: Vec<u8>) -> Vec<u8> {
+        if !self.redacts() {
+            return content;
+        }
+
+        let store = self.store.borrow();
+        let injected = map_strings(rel_path, &content, |_, text| {
+            if !text.contains(REFERENCE_PREFIX) {
+                return None;
+            }
+
+            let mut out = String::with_capacity(text.len());
+            let mut rest = text;
+            while let Some(start) = rest.find(REFERENCE_PREFIX) {
+                let after = &rest[start + REFERENCE_PREFIX.len()..];
+                let Some(end) = after.find('}') else {
+                    break;
+                };
+                let name = &after[..end];
+                out.push_str(&rest[..start]);
+                match store.get(name) {
+                    Some(value) => out.push_str(value),
+                    None => {
+                        log::warn!(
+                            "{} refers to secret {} which is not in {:?}; add it there or to the file",
+                            rel_path,
+                            name,
+                            self.store_path
+                        );
+                        out.push_str(&reference(name));
+                    }
+                }
+                rest = &after[end + 1..];
+            }
+            out.push_str(rest);
+            (out != text).then_some(out)
+        });
+
+        injected.unwrap_or(content)
+    }
+
+    fn save(&self) {
+        let result = serde_json::to_string_pretty(&*self.store.borrow())
+            .map_err(anyhow::Error::from)
+            .and_then(|content| {
+                if let Some(parent) = self.store_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                Ok(remote::write_private(&self.store_path, content.as_bytes())?)
+            });
+        if let Err(e) = result {
+            log::warn!("Failed to save secrets store {:?}: {}", self.store_path, e);
+        }
+    }
+}
+
+fn reference(name: &str) -> String {
+    format!("{}{}}}", REFERENCE_PREFIX, name)
+}
+
+/// Apply `f` to every string of a file: the string values of a JSON file
+/// (with their key path), or the whole of any other text file. Returns the
+/// new content if `f` changed anything.
+fn map_strings(
+    rel_path: &str,
+    content: &[u8],
+    mut f: impl FnMut(Option<&[String]>, &str) -> Option<String>,
+) -> Option<Vec<u8>> {
+    if rel_path.ends_with(".json") {
+        if let Ok(mut json) = serde_json::from_slice::<Value>(content) {
+            let mut changed = false;
+            map_json_strings(&mut json, &mut Vec::new(), &mut |path, text| {
+                let new = f(Some(path), text);
+                changed |= new.is_some();
+                new
+            });
+            if !changed {
+                return None;
+            }
+            let mut bytes = serde_json::to_vec_pretty(&json).unwrap_or_default();
+            if content.ends_with(b"\n") {
+                bytes.push(b'\n');
+            }
+            return Some(bytes);
+        }
+    }
+
+    let text = std::str::from_utf8(content).ok()?;
+    f(None, text).map(String::into_bytes)
+}
+
+fn map_json_strings(
+    value: &mut Value,
+    path: &mut Vec<String>,
+    f: &mut impl FnMut(&[String], &str) -> Option<String>,
+) {
+    match value {
+        Value::String(text) => {
+            if let Some(new) = f(path, text) {
+                *text = new;
+            }
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                path.push(i.to_string());
+                map_json_strings(item, path, f);
+                path.pop();
+            }
+        }
+        Value::Object(map) => {
+            for (key, item) in map.iter_mut() {
+                path.push(key.clone());
+                map_json_strings(item, path, f);
+                path.pop();
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Secrets in one string; `json_path` is its key path in a JSON file
+fn find_secrets(rel_path: &str, json_path: Option<&[String]>, text: &str) -> Vec<Finding> {
+    // A whole `env` value under a secret-looking name
+    if let Some(path) = json_path {
+        let in_env = path.len() >= 2 && path[path.len() - 2] == "env";
+        if in_env && is_secret_name(&path[path.len() - 1]) && text.len() >= 8 && !text.starts_with('$') {
+            let dotted = path.join(".");
+            return vec![Finding {
+                location: dotted.clone(),
+                name: format!("{}:{}", rel_path, dotted),
+                range: 0..text.len(),
+            }];
+        }
+    }
+
+    let mut findings: Vec<Finding> = find_tokens(text)
+        .into_iter()
+        .chain(find_assignments(text))
+        .map(|(range, kind)| {
+            let location = match json_path {
+                Some(path) => path.join("."),
+                None => format!("line {}", text[..range.start].matches('\n').count() + 1),
+            };
+            let hash = format!("{:x}", Sha256::digest(&text.as_bytes()[range.clone()]));
+            Finding {
+                location: format!("{} {}", kind, location),
+                name: format!("{}-{}", kind, &hash[..12]),
+                range,
+            }
+        })
+        .collect();
+
+    // Leave references alone, and drop overlaps (an assignment of a prefixed
+    // token), keeping the first
+    let references = reference_ranges(text);
+    findings.retain(|f| !references.iter().any(|r| f.range.start < r.end && r.start < f.range.end));
+    findings.sort_by_key(|f| (f.range.start, std::cmp::Reverse(f.range.end)));
+    let mut end = 0;
+    findings.retain(|f| {
+        let keep = f.range.start >= end;
+        if keep {
+            end = f.range.end;
+        }
+        keep
+    });
+    findings
+}
+
+/// Prefixed tokens, AWS key IDs, Bearer tokens and private keys
+fn find_tokens(text: &str) -> Vec<(Range<usize>, &'static str)> {
+    let bytes = text.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if !text.is_char_boundary(i) || (i > 0 && is_token_byte(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let rest = &text[i..];
+
+        if rest.starts_with("-----BEGIN ") {
+            let header_end = rest.find('\n').unwrap_or(rest.len());
+            if rest[..header_end].contains("PRIVATE KEY-----") {
+                if let Some(footer) = rest.find("-----END ") {
+                    let end = rest[footer + 9..]
+                        .find("-----")
+                        .map(|e| footer + 9 + e + 5)
+                        .unwrap_or(rest.len());
+                    found.push((i..i + end, "private-key"));
+                    i += end;
+                    continue;
+                }
+            }
+        }
+
+        if let Some(token) = rest.strip_prefix("Bearer ") {
+            let len = token.bytes().take_while(|&b| is_token_byte(b) || b"./+=~".contains(&b)).count();
+            if len >= 20 {
+                let start = i + "Bearer ".len();
+                found.push((start..start + len, "bearer-token"));
+                i = start + len;
+                continue;
+            }
+        }
+
+        if rest.starts_with("AKIA") || rest.starts_with("ASIA") {
+            let len = rest.bytes().take_while(|b| b.is_ascii_uppercase() || b.is_ascii_digit()).count();
+            if len == 20 {
+                found.push((i..i + len, "aws-access-key"));
+                i += len;
+                continue;
+            }
+        }
+
+        let prefixed = TOKEN_PREFIXES.iter().find_map(|&(prefix, kind, min_len)| {
+            let body = rest.strip_prefix(prefix)?;
+            let len = body.bytes().take_while(|&b| is_token_byte(b)).count();
+            (len >= min_len && looks_random(&body[..len])).then_some((prefix.len() + len, kind))
+        });
+        if let Some((len, kind)) = prefixed {
+            found.push((i..i + len, kind));
+            i += len;
+            continue;
+        }
+
+        i += 1;
+    }
+
+    found
+}
+
+/// Values assigned to secret-looking names (`API_KEY=...`, `token: ...`)
+fn find_assignments(text: &str) -> Vec<(Range<usize>, &'static str)> {
+    let bytes = text.as_bytes();
+    let mut found = Vec::new();
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let is_name_byte = |b: u8| b.is_ascii_alphanumeric() || b == b'_' || b == b'-';
+        if !is_name_byte(bytes[i]) || (i > 0 && is_name_byte(bytes[i - 1])) {
+            i += 1;
+            continue;
+        }
+        let name_end = i + bytes[i..].iter().take_while(|&&b| is_name_byte(b)).count();
+        let name = &text[i..name_end];
+        i = name_end;
+        if !is_secret_name(name) {
+            continue;
+        }
+
+        // `=` or `:`, optionally surrounded by spaces and followed by a quote
+        let mut j = name_end;
+        while j < bytes.len() && bytes[j] == b' ' {
+            j += 1;
+        }
+        if j >= bytes.len() || !matches!(bytes[j], b'=' | b':') {
+            continue;
+        }
+        j += 1;
+        while j < bytes.len() && bytes[j] == b' ' {
+            j += 1;
+        }
+        if j < bytes.len() && matches!(bytes[j], b'"' | b'\'') {
+            j += 1;
+        }
+
+        let len = bytes[j..]
+            .iter()
+            .take_while(|&&b| !b.is_ascii_whitespace() && !b"\"'`,;".contains(&b))
+            .count();
+        let value = &text[j..j + len];
+        if len >= 12 && !value.starts_with('$') && looks_random(value) {
+            found.push((j..j + len, "secret"));
+            i = j + len;
+        }
+    }
+
+    found
+}
+
+/// Byte ranges of `${...}` references (to redacted values or environment
+/// variables)
+fn reference_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("${") {
+        let start = offset + start;
+        let Some(len) = text[start..].find('}') else {
+            break;
+        };
+        ranges.push(start..start + len + 1);
+        offset = start + len + 1;
+    }
+    ranges
+}
+
+fn is_token_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_' || b == b'-'
+}
+
+/// Check if a variable or key name marks its value as secret
+///
+/// Names are compared word by word (`API_KEY`, `apiKey` and `x-api-key` are
+/// all `API`, `KEY`), so `AUTHOR`, `OAUTH_PORT` or `maxTokens` don't match.
+fn is_secret_name(name: &str) -> bool {
+    let words = name_words(name);
+    let matches_at = |start: usize, part: &str| {
+        let part_words: Vec<&str> = part.split('_').collect();
+        start + part_words.len() <= words.len()
+            && part_words.iter().enumerate().all(|(k, p)| words[start + k] == *p)
+    };
+
+    (0..words.len()).any(|start| SECRET_NAME_PARTS.iter().any(|part| matches_at(start, part)))
+        || words
+            .last()
+            .is_some_and(|last| SECRET_NAME_ENDINGS.contains(&last.as_str()))
+}
+
+/// Upper-cased words of a name: split at anything but letters and digits,
+/// and at camelCase boundaries (`APIKey` is `API`, `KEY`)
+fn name_words(name: &str) -> Vec<String> {
+    let chars: Vec<char> = name.chars().collect();
+    let mut words = Vec::new();
+    let mut word = String::new();
+
+    for (i, &c) in chars.iter().enumerate() {
+        if !c.is_alphanumeric() {
+            if !word.is_empty() {
+                words.push(std::mem::take(&mut word));
+            }
+            continue;
+        }
+
+        let prev = i.checked_sub(1).map(|p| chars[p]);
+        let next = chars.get(i + 1);
+        let boundary = c.is_uppercase()
+            && prev.is_some_and(|p| {
+                p.is_lowercase() || p.is_ascii_digit() || (p.is_uppercase() && next.is_some_and(|n| n.is_lowercase()))
+            });
+        if boundary && !word.is_empty() {
+            words.push(std::mem::take(&mut word));
+        }
+        word.extend(c.to_uppercase());
+    }
+    if !word.is_empty() {
+        words.push(word);
+    }
+
+    words
+}
+
+/// Letters and digits mixed, unlike words and placeholders
+fn looks_random(value: &str) -> bool {
+    value.bytes().any(|b| b.is_ascii_digit()) && value.bytes().any(|b| b.is_ascii_alphabetic())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_names() {
+        for name in [
+            "API_KEY",
+            "OPENAI_API_KEY",
+            "apiKey",
+            "x-api-key",
+            "APIKey",
+            "AWS_SECRET_ACCESS_KEY",
+            "clientSecret",
+            "GITHUB_TOKEN",
+            "_authToken",
+            "accessToken",
+            "DB_PASSWORD",
+            "passwd",
+            "GOOGLE_APPLICATION_CREDENTIALS",
+            "Authorization",
+            "auth",
+            "BASIC_AUTH",
+            "proxyAuth",
+        ] {
+            assert!(is_secret_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn names_that_only_contain_secret_words() {
+        for name in [
+            "AUTHOR",
+            "author",
+            "AUTH_URL",
+            "authDomain",
+            "OAUTH_CALLBACK_PORT",
+            "oauth",
+            "TOKENIZER",
+            "maxTokens",
+            "SECRETARY",
+            "KEYBOARD",
+            "",
+        ] {
+            assert!(!is_secret_name(name), "{}", name);
+        }
+    }
+
+    #[test]
+    fn assignments_to_non_secret_names_are_ignored() {
+        let text = "AUTHOR=Joe1234567890abc\nAUTH_URL=https://auth.example.com/v1\n\
+                    OAUTH_CALLBACK_PORT=8080123456789\nAPI_KEY=abc123def456ghi789\n";
+        let found: Vec<String> = find_secrets("env.sh", None, text).into_iter().map(|f| f.location).collect();
+        assert_eq!(found, ["secret line 4"]);
+    }
+}
@@ -0,0 +1,495 @@
+//! SSH backend: a directory on a server reached with the user's ssh
+//!
+//! With `provider = "sftp"`, the shared copy is `<sftp.path>/<paths.folder>`
+//! on `sftp.host`. ssh runs as a subprocess, so ~/.ssh/config, the agent and
+//! known hosts apply; one connection is shared by all commands of a sync
+//! (ControlMaster). The server needs a POSIX shell with `sha256sum` or
+//! `shasum`.
+//!
+//! Listing runs `stat` on the server; checksums of files whose metadata
+//! changed since the last sync are computed there too, so only the manifest
+//! crosses the wire (cached in ~/.claude/.sync_sftp_index.json, see
+//! `remote::VersionIndex`). Uploads go to a temporary file next to the
+//! target, are checked against the checksum of what was sent and renamed
+//! into place, unless the file changed on the server since it was read.
+
+use crate::config::Config;
+use crate::filter::SyncSet;
+use crate::remote::{Remote, RemoteFile, VersionIndex};
+use crate::state::FileState;
+use anyhow::{bail, Context, Result};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Exit status of a write or delete refused because the file changed
+const CHANGED_STATUS: i32 = 3;
+
+/// Picks the checksum and stat commands available on the server (GNU or
+/// BSD); stat prints `mtime:size:inode:mode:path`
+const PRELUDE: &str = "if command -v sha256sum >/dev/null 2>&1; then SUM=sha256sum; else SUM='shasum -a 256'; fi; \
+if stat -c %Y / >/dev/null 2>&1; then STAT='stat -c %Y:%s:%i:%a:%n'; else STAT='stat -f %m:%z:%i:%Lp:%N'; fi; ";
+
+/// Metadata of every file in the sync directory ($1)
+const LIST_SCRIPT: &str = "cd \"$1\" 2>/dev/null || exit 0; \
+find . -type f -not -name '*.claude-sync.tmp' -exec $STAT {} +";
+
+/// Checksums of the files named on stdin, relative to $1
+const HASH_SCRIPT: &str = "cd \"$1\" || exit 1; tr '\\n' '\\0' | xargs -0 $SUM";
+
+/// Metadata and checksum of file $2 in $1
+const STAT_SCRIPT: &str = "cd \"$1\" 2>/dev/null || exit 0; [ -f \"$2\" ] || exit 0; $STAT \"./$2\"; $SUM \"./$2\"";
+
+/// Replace file $2 in $1 with stdin (checksum $4, mode $5) if its checksum
+/// is still $3 (empty: it must not exist)
+const WRITE_SCRIPT: &str = "f=\"$1/$2\"; d=$(dirname \"$f\"); t=\"$d/.$(basename \"$f\").claude-sync.tmp\"; \
+mkdir -p \"$d\" && cat > \"$t\" || exit 1; \
+[ \"$($SUM \"$t\" | cut -d ' ' -f 1)\" = \"$4\" ] || { rm -f \"$t\"; echo 'upload incomplete' >&2; exit 1; }; \
+[ -z \"$5\" ] || chmod \"$5\" \"$t\" || exit 1; \
+[ \"$($SUM \"$f\" 2>/dev/null | cut -d ' ' -f 1)\" = \"$3\" ] || { rm -f \"$t\"; exit 3; }; \
+mv -f \"$t\" \"$f\" && $STAT \"$f\"";
+
+/// Delete file $2 in $1 if its checksum is still $3, then the directories
+/// it leaves empty
+const DELETE_SCRIPT: &str = "f=\"$1/$2\"; [ -e \"$f\" ] || exit 0; \
+[ \"$($SUM \"$f\" | cut -d ' ' -f 1)\" = \"$3\" ] || exit 3; rm -f \"$f\" || exit 1; \
+d=$(dirname \"./$2\"); until [ \"$d\" = . ]; do rmdir \"$1/$d\" 2>/dev/null || break; d=$(dirname \"$d\"); done";
+
+const READ_SCRIPT: &str = "cat \"$1/$2\"";
+
+/// A line of `stat` output
+struct Stat {
+    /// mtime, size, inode and mode: changes whenever the file does
+    version: String,
+    mtime: i64,
+    size: u64,
+    mode: Option<u32>,
+    /// Path relative to the directory listed
+    rel_path: String,
+}
+
+/// Shared copy in a directory on an SSH server
+pub struct SftpRemote {
+    /// Host as given to ssh (a Host from ~/.ssh/config or [user@]hostname)
+    host: String,
+    port: Option<u16>,
+    /// Sync directory on the server (relative to the login directory unless
+    /// absolute)
+    dir: String,
+    /// Socket of the shared ssh connection
+    control_path: PathBuf,
+    /// Local config directory, whose ignore files decide what is synced
+    claude_dir: PathBuf,
+    /// Last seen version of each synced file
+    index: VersionIndex,
+}
+
+impl SftpRemote {
+    pub fn new(config: &Config) -> Result<Self> {
+        let sftp = &config.sftp;
+        // ssh starts in the home directory, so `~/x` is just `x`
+        let path = match sftp.path.strip_prefix('~') {
+            Some(rest) => rest.trim_start_matches('/'),
+            None => sftp.path.as_str(),
+        };
+        let dir = if path.is_empty() {
+            config.folder.clone()
+        } else {
+            format!("{}/{}", path.trim_end_matches('/'), config.folder)
+        };
+        // Keep commands on the server from taking it for an option
+        let dir = match dir.starts_with('-') {
+            true => format!("./{}", dir),
+            false => dir,
+        };
+
+        Ok(Self {
+            host: sftp.host.clone(),
+            port: sftp.port,
+            dir,
+            control_path: config.claude_dir.join(".sync_ssh_%C"),
+            claude_dir: config.claude_dir.clone(),
+            index: VersionIndex::load(config.local_sftp_index_path()),
+        })
+    }
+
+    /// Metadata of every synced file
+    fn list_stats(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, Stat>> {
+        let output = self.script(LIST_SCRIPT, &[&self.dir], None)?;
+        check(&output, &format!("Listing {}", self.location()))?;
+
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(parse_stat)
+            .filter(|stat| sync_set.is_synced(&self.claude_dir, &stat.rel_path, false))
+            .map(|stat| (stat.rel_path.clone(), stat))
+            .collect())
+    }
+
+    /// Checksums of files, computed on the server (relative path ->
+    /// checksum; files deleted in the meantime are left out)
+    fn hash(&self, rel_paths: &[&str]) -> Result<HashMap<String, String>> {
+        if rel_paths.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let names: String = rel_paths.iter().map(|p| format!("./{}\n", p)).collect();
+        let output = self.script(HASH_SCRIPT, &[&self.dir], Some(names.as_bytes()))?;
+        if output.status.code() == Some(255) {
+            check(&output, &format!("Hashing files in {}", self.location()))?;
+        }
+        Ok(parse_sums(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Run a script from this module on the server with `sh -c`
+    fn script(&self, script: &str, args: &[&str], input: Option<&[u8]>) -> Result<Output> {
+        self.ssh(&script_command(script, args), input)
+    }
+
+    /// Run a command on the server
+    fn ssh(&self, command: &str, input: Option<&[u8]>) -> Result<Output> {
+        let mut ssh = Command::new("ssh");
+        ssh.args(["-o", "BatchMode=yes", "-o", "ConnectTimeout=10"])
+            .args(["-o", "ControlMaster=auto", "-o", "ControlPersist=60"])
+            .arg("-o")
+            .arg(format!("ControlPath={}", self.control_path.display()));
+        if let Some(port) = self.port {
+            ssh.arg("-p").arg(port.to_string());
+        }
+        ssh.arg(&self.host)
+            .arg(command)
+            .stdin(if input.is_some() { Stdio::piped() } else { Stdio::null() })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = ssh.spawn().context("Failed to run ssh")?;
+        let sent = match (input, child.stdin.take()) {
+            (Some(input), Some(mut stdin)) => stdin.write_all(input),
+            _ => Ok(()),
+        };
+        let output = child.wait_with_output().context("Failed to run ssh")?;
+        // If the command failed early, its error explains the broken pipe
+        if let Err(e) = sent {
+            if output.status.success() {
+                return Err(e).with_context(|| format!("Failed to send data to {}", self.host));
+            }
+        }
+        Ok(output)
+    }
+}
+
+impl Remote for SftpRemote {
+    fn name(&self) -> &str {
+        "SFTP"
+    }
+
+    fn location(&self) -> String {
+        format!("{}:{}", self.host, self.dir)
+    }
+
+    fn list(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, RemoteFile>> {
+        let stats = self.list_stats(sync_set)?;
+
+        let unknown: Vec<&str> = stats
+            .values()
+            .filter(|stat| self.index.get(&stat.rel_path, &stat.version).is_none())
+            .map(|stat| stat.rel_path.as_str())
+            .collect();
+        let sums = self.hash(&unknown)?;
+
+        let mut files = BTreeMap::new();
+        for (rel_path, stat) in stats {
+            let sha256 = match self.index.get(&rel_path, &stat.version) {
+                Some(cached) => cached.sha256,
+                None => match sums.get(&rel_path) {
+                    Some(sha256) => sha256.clone(),
+                    // Deleted since the listing
+                    None => continue,
+                },
+            };
+            files.insert(
+                rel_path,
+                RemoteFile {
+                    state: FileState {
+                        sha256,
+                        mtime: stat.mtime,
+                        size: stat.size,
+                    },
+                    version: Some(stat.version),
+                    mode: stat.mode,
+                },
+            );
+        }
+
+        self.index.replace(&files);
+        Ok(files)
+    }
+
+    fn stat(&self, rel_path: &str) -> Result<Option<RemoteFile>> {
+        let output = self.script(STAT_SCRIPT, &[&self.dir, rel_path], None)?;
+        check(&output, &format!("Reading metadata of {} on {}", rel_path, self.host))?;
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let mut lines = stdout.lines();
+        let (Some(stat), Some(sha256)) = (
+            lines.next().and_then(parse_stat),
+            lines.next().and_then(|line| line.split_whitespace().next()),
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(Some(RemoteFile {
+            state: FileState {
+                sha256: sha256.to_string(),
+                mtime: stat.mtime,
+                size: stat.size,
+            },
+            version: Some(stat.version),
+            mode: stat.mode,
+        }))
+    }
+
+    fn read(&self, rel_path: &str) -> Result<Vec<u8>> {
+        let output = self.script(READ_SCRIPT, &[&self.dir, rel_path], None)?;
+        check(&output, &format!("Reading {} on {}", rel_path, self.host))?;
+        Ok(output.stdout)
+    }
+
+    fn write(
+        &self,
+        rel_path: &str,
+        content: &[u8],
+        mode: Option<u32>,
+        expected: Option<&RemoteFile>,
+    ) -> Result<RemoteFile> {
+        let sha256 = format!("{:x}", Sha256::digest(content));
+        let mode_arg = mode.map(|m| format!("{:o}", m)).unwrap_or_default();
+        let expected_sha256 = expected.map_or("", |e| e.state.sha256.as_str());
+
+        let output = self.script(
+            WRITE_SCRIPT,
+            &[&self.dir, rel_path, expected_sha256, &sha256, &mode_arg],
+            Some(content),
+        )?;
+        if output.status.code() == Some(CHANGED_STATUS) {
+            bail!("{} was changed on {} by another machine since it was read", rel_path, self.host);
+        }
+        check(&output, &format!("Uploading {} to {}", rel_path, self.host))?;
+
+        let stat = String::from_utf8_lossy(&output.stdout).lines().find_map(parse_stat);
+        let file = RemoteFile {
+            state: FileState {
+                sha256,
+                mtime: stat.as_ref().map_or_else(|| Utc::now().timestamp(), |s| s.mtime),
+                size: content.len() as u64,
+            },
+            version: stat.map(|s| s.version),
+            mode,
+        };
+        self.index.remember(rel_path, &file);
+        Ok(file)
+    }
+
+    fn delete(&self, rel_path: &str, expected: &RemoteFile) -> Result<()> {
+        let output = self.script(DELETE_SCRIPT, &[&self.dir, rel_path, &expected.state.sha256], None)?;
+        if output.status.code() == Some(CHANGED_STATUS) {
+            bail!("{} was changed on {} by another machine since it was read", rel_path, self.host);
+        }
+        check(&output, &format!("Deleting {} on {}", rel_path, self.host))?;
+
+        self.index.forget(rel_path);
+        Ok(())
+    }
+
+    fn has_changes(&self, sync_set: &SyncSet) -> Result<bool> {
+        let listed: HashMap<String, String> = self
+            .list_stats(sync_set)?
+            .into_iter()
+            .map(|(rel_path, stat)| (rel_path, stat.version))
+            .collect();
+        Ok(self.index.differs(&listed))
+    }
+}
+
+/// Parse a `mtime:size:inode:mode:./path` line
+fn parse_stat(line: &str) -> Option<Stat> {
+    let mut fields = line.splitn(5, ':');
+    let (mtime, size, inode, mode, path) = (
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+        fields.next()?,
+    );
+    Some(Stat {
+        version: format!("{}:{}:{}:{}", mtime, size, inode, mode),
+        mtime: mtime.parse().ok()?,
+        size: size.parse().ok()?,
+        mode: u32::from_str_radix(mode, 8).ok(),
+        rel_path: path.strip_prefix("./").unwrap_or(path).to_string(),
+    })
+}
+
+/// Parse `sha256sum` output (`<checksum>  ./path` per line)
+fn parse_sums(output: &str) -> HashMap<String, String> {
+    output
+        .lines()
+        // Names with special characters are escaped and start with `\`
+        .filter(|line| !line.starts_with('\\'))
+        .filter_map(|line| {
+            let (sha256, path) = line.split_once("  ")?;
+            let path = path.strip_prefix("./")?;
+            Some((path.to_string(), sha256.to_string()))
+        })
+        .collect()
+}
+
+/// Command line running `script` with `sh -c`, `args` being $1, $2, ...
+fn script_command(script: &str, args: &[&str]) -> String {
+    let mut command = format!("sh -c {} sh", shell_quote(&format!("{}{}", PRELUDE, script)));
+    for arg in args {
+        command.push(' ');
+        command.push_str(&shell_quote(arg));
+    }
+    command
+}
+
+/// Quote a word for the remote shell
+fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+/// Turn a failed ssh command into an error with its stderr
+fn check(output: &Output, what: &str) -> Result<()> {
+    if !output.status.success() {
+        bail!("{} failed: {}", what, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+    use std::fs;
+    use std::path::Path;
+
+    #[test]
+    fn parse_sums_reads_manifest() {
+        let output = "abc123  ./settings.json\n\
+                      def456  ./skills/my  skill/SKILL.md\n\
+                      \\0ff  ./new\\nline.md\n\
+                      789  settings.json\n\
+                      \n";
+        let sums = parse_sums(output);
+        assert_eq!(sums.len(), 2);
+        assert_eq!(sums["settings.json"], "abc123");
+        assert_eq!(sums["skills/my  skill/SKILL.md"], "def456");
+    }
+
+    #[test]
+    fn parse_stat_reads_fields() {
+        let stat = parse_stat("1738059300:42:1234:644:./settings.json").unwrap();
+        assert_eq!(stat.rel_path, "settings.json");
+        assert_eq!((stat.mtime, stat.size, stat.mode), (1738059300, 42, Some(0o644)));
+        assert_eq!(stat.version, "1738059300:42:1234:644");
+
+        // Only the first four colons separate fields
+        let stat = parse_stat("1:2:3:755:./hooks/a:b.sh").unwrap();
+        assert_eq!(stat.rel_path, "hooks/a:b.sh");
+        assert_eq!(stat.mode, Some(0o755));
+
+        // Absolute paths (stat of a written file) are kept as they are
+        assert_eq!(parse_stat("1:2:3:600:/srv/sync/x").unwrap().rel_path, "/srv/sync/x");
+
+        assert!(parse_stat("1:2:3:./short").is_none());
+        assert!(parse_stat("yesterday:2:3:644:./x").is_none());
+        assert!(parse_stat("stat: cannot stat './x': No such file").is_none());
+    }
+
+    /// Run a script the way ssh does: the command line goes to a shell
+    fn run(cwd: &Path, script: &str, args: &[&str], input: &[u8]) -> Output {
+        let mut child = Command::new("sh")
+            .arg("-c")
+            .arg(script_command(script, args))
+            .current_dir(cwd)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(input).unwrap();
+        child.wait_with_output().unwrap()
+    }
+
+    #[test]
+    fn shell_quote_keeps_words_intact() {
+        let dir = TempDir::new();
+        for word in ["plain", "two words", "it's", "\"quoted\"", "-n", "$(touch x)", "`touch x`", "a\\b", "*", ""] {
+            let output = run(dir.path(), "printf %s \"$1\"", &[word], b"");
+            assert_eq!(String::from_utf8_lossy(&output.stdout), word);
+        }
+        assert!(!dir.path().join("x").exists());
+    }
+
+    #[test]
+    fn scripts_handle_awkward_paths() {
+        let home = TempDir::new();
+        let config = Config::for_test(
+            home.path(),
+            "[paths]\nprovider = \"sftp\"\nfolder = \"-sync 'dir'\"\n\n[sftp]\nhost = \"server\"\npath = \"~\"\n",
+        );
+        let remote = SftpRemote::new(&config).unwrap();
+        assert_eq!(remote.dir, "./-sync 'dir'");
+        let root = home.path().join("-sync 'dir'");
+        let files = ["-n.md", "it's \"quoted\".md", "-x/$(echo hi) b/file.md"];
+
+        for rel_path in files {
+            let content = format!("content of {}\n", rel_path);
+            let sha256 = format!("{:x}", Sha256::digest(&content));
+            let output = run(home.path(), WRITE_SCRIPT, &[&remote.dir, rel_path, "", &sha256, "600"], content.as_bytes());
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            assert_eq!(fs::read_to_string(root.join(rel_path)).unwrap(), content);
+
+            // Writing again needs the current checksum
+            let output = run(home.path(), WRITE_SCRIPT, &[&remote.dir, rel_path, "", &sha256, ""], content.as_bytes());
+            assert_eq!(output.status.code(), Some(CHANGED_STATUS));
+
+            let output = run(home.path(), STAT_SCRIPT, &[&remote.dir, rel_path], b"");
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            let mut lines = stdout.lines();
+            let stat = lines.next().and_then(parse_stat).unwrap();
+            assert_eq!(stat.rel_path, rel_path);
+            assert_eq!(stat.mode, Some(0o600));
+            assert!(lines.next().unwrap().starts_with(&sha256));
+
+            let output = run(home.path(), READ_SCRIPT, &[&remote.dir, rel_path], b"");
+            assert_eq!(output.stdout, content.as_bytes());
+        }
+
+        let output = run(home.path(), LIST_SCRIPT, &[&remote.dir], b"");
+        let mut listed: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(parse_stat)
+            .map(|stat| stat.rel_path)
+            .collect();
+        listed.sort();
+        assert_eq!(listed, ["-n.md", "-x/$(echo hi) b/file.md", "it's \"quoted\".md"]);
+
+        let names: String = files.iter().map(|p| format!("./{}\n", p)).collect();
+        let output = run(home.path(), HASH_SCRIPT, &[&remote.dir], names.as_bytes());
+        let sums = parse_sums(&String::from_utf8_lossy(&output.stdout));
+        assert_eq!(sums.len(), 3);
+
+        for rel_path in files {
+            let output = run(home.path(), DELETE_SCRIPT, &[&remote.dir, rel_path, "wrong"], b"");
+            assert_eq!(output.status.code(), Some(CHANGED_STATUS));
+            let output = run(home.path(), DELETE_SCRIPT, &[&remote.dir, rel_path, &sums[rel_path]], b"");
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        }
+        // Emptied directories go too
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+    }
+}
@@ -1,12 +1,14 @@
 //! Sync state tracking (checksums, mtimes)
 
+use crate::filter::SyncSet;
+use crate::hashcache;
+use crate::remote::{Remote, RemoteFile};
+use crate::transform::Transforms;
 use anyhow::{Context, Result};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
-use std::collections::HashMap;
-use std::fs::{self, File};
-use std::io::{BufReader, Read};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
 use std::path::{Path, PathBuf};
 use std::time::SystemTime;
 
@@ -21,6 +23,69 @@ pub struct FileState {
     pub size: u64,
 }
 
+/// Record of a file deleted by a sync, so a stale copy doesn't resurrect it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tombstone {
+    /// SHA-256 checksum of the content that was deleted
+    pub sha256: String,
+    /// When the deletion was synced
+    pub deleted_at: DateTime<Utc>,
+    /// Machine that synced the deletion
+    pub machine_id: String,
+}
+
+/// Side of a sync pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ConflictSide {
+    Local,
+    Remote,
+}
+
+impl ConflictSide {
+    /// The opposite side
+    pub fn other(self) -> Self {
+        match self {
+            ConflictSide::Local => ConflictSide::Remote,
+            ConflictSide::Remote => ConflictSide::Local,
+        }
+    }
+}
+
+/// A conflict the engine resolved automatically, kept for review with
+/// `claude-sync-watch resolve`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingConflict {
+    /// Checksum of the last-synced content, if there was one
+    pub base_sha256: Option<String>,
+    /// Checksum of the local content at the time of the conflict
+    pub local_sha256: String,
+    /// Checksum of the remote content at the time of the conflict
+    pub remote_sha256: String,
+    /// Side the engine kept
+    pub kept: ConflictSide,
+    /// Conflict copy holding the losing side (KeepBoth only)
+    #[serde(default)]
+    pub conflict_copy: Option<PathBuf>,
+    /// When the conflict was detected
+    pub detected_at: DateTime<Utc>,
+}
+
+impl PendingConflict {
+    /// All content checksums this conflict refers to
+    pub fn checksums(&self) -> impl Iterator<Item = &str> {
+        self.base_sha256
+            .as_deref()
+            .into_iter()
+            .chain([self.local_sha256.as_str(), self.remote_sha256.as_str()])
+    }
+}
+
+/// Marker in the file name of a saved losing conflict side
+pub const CONFLICT_COPY_MARKER: &str = ".conflict-";
+
+/// How long tombstones are kept before being pruned
+const TOMBSTONE_MAX_AGE_DAYS: i64 = 30;
+
 /// Sync state for tracking file changes
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SyncState {
@@ -28,10 +93,19 @@ pub struct SyncState {
     pub version: u32,
     /// Machine ID that last updated this state
     pub machine_id: String,
+    /// Location of the remote the baseline was recorded against
+    #[serde(default)]
+    pub target: Option<String>,
     /// Last sync timestamp
     pub last_sync: DateTime<Utc>,
     /// State of each synced file (relative path -> state)
     pub files: HashMap<String, FileState>,
+    /// Deleted files (relative path -> tombstone)
+    #[serde(default)]
+    pub tombstones: HashMap<String, Tombstone>,
+    /// Automatically resolved conflicts awaiting review (relative path -> conflict)
+    #[serde(default)]
+    pub conflicts: HashMap<String, PendingConflict>,
 }
 
 impl Default for SyncState {
@@ -39,8 +113,11 @@ impl Default for SyncState {
         Self {
             version: 1,
             machine_id: String::new(),
+            target: None,
             last_sync: Utc::now(),
             files: HashMap::new(),
+            tombstones: HashMap::new(),
+            conflicts: HashMap::new(),
         }
     }
 }
@@ -70,24 +147,6 @@ impl SyncState {
         fs::write(path, content).with_context(|| format!("Failed to write state file: {:?}", path))
     }
 
-    /// Compute SHA-256 checksum of a file
-    pub fn sha256_file(path: &Path) -> Result<String> {
-        let file = File::open(path).with_context(|| format!("Failed to open file: {:?}", path))?;
-        let mut reader = BufReader::new(file);
-        let mut hasher = Sha256::new();
-        let mut buffer = [0u8; 8192];
-
-        loop {
-            let bytes_read = reader.read(&mut buffer)?;
-            if bytes_read == 0 {
-                break;
-            }
-            hasher.update(&buffer[..bytes_read]);
-        }
-
-        Ok(format!("{:x}", hasher.finalize()))
-    }
-
     /// Get file state from the filesystem
     pub fn get_file_state(path: &Path) -> Result<FileState> {
         let metadata = fs::metadata(path)
@@ -100,7 +159,7 @@ impl SyncState {
             .map(|d| d.as_secs() as i64)
             .unwrap_or(0);
 
-        let sha256 = Self::sha256_file(path)?;
+        let sha256 = hashcache::sha256_file(path)?;
 
         Ok(FileState {
             sha256,
@@ -118,21 +177,63 @@ impl SyncState {
         }
     }
 
+    /// Tie the state to the remote at `location`, dropping the baseline and
+    /// tombstones if they were recorded against another one (a new remote
+    /// would otherwise look like every file was deleted from it)
+    ///
+    /// Returns true if the baseline was dropped. State from before targets
+    /// were recorded is adopted as is.
+    pub fn bind_target(&mut self, location: &str) -> bool {
+        let switched = self.target.as_deref().is_some_and(|target| target != location);
+        if switched {
+            self.files.clear();
+            self.tombstones.clear();
+        }
+        self.target = Some(location.to_string());
+        switched
+    }
+
     /// Update state for a file
     pub fn update_file(&mut self, rel_path: &str, state: FileState) {
+        self.tombstones.remove(rel_path);
         self.files.insert(rel_path.to_string(), state);
         self.last_sync = Utc::now();
     }
 
     /// Remove a file from state
-    #[allow(dead_code)]
     pub fn remove_file(&mut self, rel_path: &str) {
         self.files.remove(rel_path);
         self.last_sync = Utc::now();
     }
 
+    /// Remove a file from state and leave a tombstone for its last content
+    pub fn record_deletion(&mut self, rel_path: &str, sha256: &str) {
+        self.remove_file(rel_path);
+        self.tombstones.insert(
+            rel_path.to_string(),
+            Tombstone {
+                sha256: sha256.to_string(),
+                deleted_at: Utc::now(),
+                machine_id: self.machine_id.clone(),
+            },
+        );
+    }
+
+    /// Check if a file's content matches a tombstone (i.e. it is a stale copy
+    /// of something we already deleted)
+    pub fn is_tombstoned(&self, rel_path: &str, current: &FileState) -> bool {
+        self.tombstones
+            .get(rel_path)
+            .is_some_and(|t| t.sha256 == current.sha256)
+    }
+
+    /// Drop tombstones older than the retention window
+    pub fn prune_tombstones(&mut self) {
+        let cutoff = Utc::now() - chrono::Duration::days(TOMBSTONE_MAX_AGE_DAYS);
+        self.tombstones.retain(|_, t| t.deleted_at > cutoff);
+    }
+
     /// Check if a file has changed compared to recorded state
-    #[allow(dead_code)]
     pub fn file_changed(&self, rel_path: &str, current: &FileState) -> bool {
         match self.files.get(rel_path) {
             Some(recorded) => recorded.sha256 != current.sha256,
@@ -143,11 +244,12 @@ impl SyncState {
 
 /// Type of change detected
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[allow(dead_code)]
 pub enum ChangeType {
     Created,
     Modified,
     Deleted,
+    /// Both sides changed since the last sync
+    Conflict,
 }
 
 /// A detected change
@@ -157,173 +259,321 @@ pub struct Change {
     pub rel_path: String,
     /// Type of change
     pub change_type: ChangeType,
-    /// Source path (for sync operations)
-    pub src: PathBuf,
-    /// Destination path (for sync operations)
-    pub dst: PathBuf,
+    /// Side the change comes from: the file is copied from it to the other
+    /// side (for deletions, the side it was deleted from)
+    pub source: ConflictSide,
+    /// The remote file as seen when the change was detected (None if it
+    /// doesn't exist); remote writes are conditional on it being unchanged
+    pub remote: Option<RemoteFile>,
 }
 
-/// Detect changes between local and remote directories
+/// Detect changes between the local directory and the remote
+///
+/// Uses a three-way comparison: each side's current checksum is compared
+/// against the checksum recorded in `state` at the last successful sync, so
+/// we can tell "only local changed", "only remote changed" and "both changed"
+/// apart instead of trusting mtimes. Files that are already identical on both
+/// sides are recorded in `state` as the new baseline. Local files are
+/// compared in their shared form (see `Transforms`).
+///
+/// A file that is in the baseline but missing on one side (and unchanged on
+/// the other) is reported as `Deleted`. A file that reappears with the exact
+/// content of a tombstone is treated as a stale copy and deleted again.
+///
+/// With a `scope`, only those files are looked at (each stat'ed on the
+/// remote) instead of scanning both sides.
 pub fn detect_changes(
     local_dir: &Path,
-    remote_dir: &Path,
-    sync_files: &[String],
-    sync_dirs: &[String],
-    state: &SyncState,
-) -> Vec<Change> {
-    let mut changes = Vec::new();
-
-    // Check individual files
-    for file_name in sync_files {
-        let local_path = local_dir.join(file_name);
-        let remote_path = remote_dir.join(file_name);
+    remote: &dyn Remote,
+    sync_set: &SyncSet,
+    state: &mut SyncState,
+    transforms: &Transforms,
+    scope: Option<&BTreeSet<String>>,
+) -> Result<Vec<Change>> {
+    let (remote_files, rel_paths) = match scope {
+        Some(scope) => {
+            let mut remote_files = BTreeMap::new();
+            for rel_path in scope {
+                if let Some(file) = remote.stat(rel_path)? {
+                    remote_files.insert(rel_path.clone(), file);
+                }
+            }
+            (remote_files, scope.clone())
+        }
+        None => {
+            let remote_files = remote.list(sync_set)?;
+
+            // Collect every relative path that exists on either side
+            let mut rel_paths = sync_set.collect_files(local_dir);
+            rel_paths.extend(remote_files.keys().cloned());
+
+            // Include baseline entries so we notice files that vanished from a side
+            rel_paths.extend(
+                state
+                    .files
+                    .keys()
+                    .filter(|rel_path| sync_set.is_synced(local_dir, rel_path, false))
+                    .cloned(),
+            );
+            (remote_files, rel_paths)
+        }
+    };
 
-        let local_state = SyncState::current_file_state(&local_path);
-        let remote_state = SyncState::current_file_state(&remote_path);
+    let mut changes = Vec::new();
 
-        match (&local_state, &remote_state) {
+    for rel_path in rel_paths {
+        let local_path = local_dir.join(&rel_path);
+        let remote_file = remote_files.get(&rel_path);
+
+        let local_state = transforms.local_state(&rel_path, &local_path, || {
+            remote_file.and_then(|_| remote.read(&rel_path).ok())
+        });
+        let remote_state = remote_file.map(|f| &f.state);
+
+        let change = |change_type, source| Change {
+            rel_path: rel_path.clone(),
+            change_type,
+            source,
+            remote: remote_file.cloned(),
+        };
+        let push = |change_type| change(change_type, ConflictSide::Local);
+        let pull = |change_type| change(change_type, ConflictSide::Remote);
+
+        match (&local_state, remote_state) {
             (Some(local), Some(remote)) => {
-                // Both exist - check which is newer
-                if local.sha256 != remote.sha256 {
-                    if local.mtime > remote.mtime {
-                        // Local is newer -> push to remote
-                        changes.push(Change {
-                            rel_path: file_name.clone(),
-                            change_type: ChangeType::Modified,
-                            src: local_path,
-                            dst: remote_path,
-                        });
-                    } else if remote.mtime > local.mtime {
-                        // Remote is newer -> pull to local
-                        changes.push(Change {
-                            rel_path: file_name.clone(),
-                            change_type: ChangeType::Modified,
-                            src: remote_path,
-                            dst: local_path,
-                        });
+                if local.sha256 == remote.sha256 {
+                    // Already in sync - make sure the baseline reflects it
+                    if state.file_changed(&rel_path, local) {
+                        state.update_file(&rel_path, local.clone());
+                    }
+                    continue;
+                }
+
+                let local_changed = state.file_changed(&rel_path, local);
+                let remote_changed = state.file_changed(&rel_path, remote);
+
+                match (local_changed, remote_changed) {
+                    (true, false) => changes.push(push(ChangeType::Modified)),
+                    (false, true) => changes.push(pull(ChangeType::Modified)),
+                    _ => {
+                        // Both changed (or no baseline yet). Point the change
+                        // at the newer side; the engine decides what to do.
+                        if remote.mtime > local.mtime {
+                            changes.push(pull(ChangeType::Conflict));
+                        } else {
+                            changes.push(push(ChangeType::Conflict));
+                        }
                     }
-                    // If same mtime but different hash, that's a conflict - use config strategy
                 }
             }
-            (Some(_), None) => {
-                // Local exists, remote doesn't -> push
-                changes.push(Change {
-                    rel_path: file_name.clone(),
-                    change_type: ChangeType::Created,
-                    src: local_path,
-                    dst: remote_path,
-                });
+            (Some(local), None) => {
+                if is_deleted_elsewhere(state, &rel_path, local) {
+                    // Deleted remotely and untouched here -> delete local
+                    changes.push(pull(ChangeType::Deleted));
+                } else {
+                    // Local exists, remote doesn't -> push
+                    changes.push(push(ChangeType::Created));
+                }
             }
-            (None, Some(_)) => {
-                // Remote exists, local doesn't -> pull
-                changes.push(Change {
-                    rel_path: file_name.clone(),
-                    change_type: ChangeType::Created,
-                    src: remote_path,
-                    dst: local_path,
-                });
+            (None, Some(remote)) => {
+                if is_deleted_elsewhere(state, &rel_path, remote) {
+                    // Deleted locally and untouched remotely -> delete remote
+                    changes.push(push(ChangeType::Deleted));
+                } else {
+                    // Remote exists, local doesn't -> pull
+                    changes.push(pull(ChangeType::Created));
+                }
             }
             (None, None) => {
-                // Neither exists - nothing to do
+                // Gone from both sides - forget the baseline
+                if let Some(recorded) = state.files.get(&rel_path).cloned() {
+                    state.record_deletion(&rel_path, &recorded.sha256);
+                }
             }
         }
     }
 
-    // Check directories
-    for dir_name in sync_dirs {
-        let local_dir_path = local_dir.join(dir_name);
-        let remote_dir_path = remote_dir.join(dir_name);
-
-        if local_dir_path.exists() {
-            scan_directory_changes(
-                &local_dir_path,
-                &remote_dir_path,
-                dir_name,
-                state,
-                &mut changes,
-                true, // local is source
-            );
+    Ok(changes)
+}
+
+/// Check if a file that only exists on one side was deleted from the other
+///
+/// True when the surviving copy still matches the last-synced baseline (the
+/// other side deleted it and nobody edited it since), or when it matches a
+/// tombstone (a lagging copy of something already deleted).
+fn is_deleted_elsewhere(state: &SyncState, rel_path: &str, surviving: &FileState) -> bool {
+    match state.files.get(rel_path) {
+        Some(recorded) => recorded.sha256 == surviving.sha256,
+        None => state.is_tombstoned(rel_path, surviving),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+    use crate::remote::FolderRemote;
+    use crate::testutil::{write_at, TempDir};
+    use sha2::{Digest, Sha256};
+
+    const T: u64 = 1_700_000_000;
+
+    fn file_state(content: &str) -> FileState {
+        FileState {
+            sha256: format!("{:x}", Sha256::digest(content)),
+            mtime: T as i64,
+            size: content.len() as u64,
         }
+    }
 
-        if remote_dir_path.exists() {
-            scan_directory_changes(
-                &remote_dir_path,
-                &local_dir_path,
-                dir_name,
-                state,
-                &mut changes,
-                false, // remote is source
-            );
+    /// Detect changes to CLAUDE.md between `home`/.claude and the folder
+    /// `home`/share/Sync, each side written with the given mtime (None:
+    /// missing)
+    fn detect(
+        home: &TempDir,
+        state: &mut SyncState,
+        local: Option<(&str, u64)>,
+        remote: Option<(&str, u64)>,
+    ) -> Vec<(String, ChangeType, ConflictSide)> {
+        let share = home.path().join("share");
+        fs::create_dir_all(share.join("Sync")).unwrap();
+        let config = Config::for_test(
+            home.path(),
+            &format!(
+                "[paths]\nprovider = \"directory\"\nbase = {:?}\nfolder = \"Sync\"\n",
+                share.to_string_lossy()
+            ),
+        );
+        fs::create_dir_all(&config.claude_dir).unwrap();
+        if let Some((content, mtime)) = local {
+            write_at(&config.claude_dir.join("CLAUDE.md"), content, mtime);
+        }
+        if let Some((content, mtime)) = remote {
+            write_at(&config.target.dir.join("CLAUDE.md"), content, mtime);
         }
+
+        let changes = detect_changes(
+            &config.claude_dir,
+            &FolderRemote::new(config.target.clone()),
+            &SyncSet::new(&config),
+            state,
+            &Transforms::load(&config).unwrap(),
+            None,
+        )
+        .unwrap();
+        changes
+            .into_iter()
+            .map(|c| (c.rel_path, c.change_type, c.source))
+            .collect()
     }
 
-    changes
-}
+    #[test]
+    fn three_way_comparison() {
+        use ChangeType::*;
+        use ConflictSide::*;
+
+        for (baseline, local, remote, expected) in [
+            // Changed on one side
+            (Some("base\n"), ("local\n", T + 100), ("base\n", T), Some((Modified, Local))),
+            (Some("base\n"), ("base\n", T), ("remote\n", T + 100), Some((Modified, Remote))),
+            // Changed on both sides: pointed at the newer one
+            (Some("base\n"), ("local\n", T + 200), ("remote\n", T + 100), Some((Conflict, Local))),
+            (Some("base\n"), ("local\n", T + 100), ("remote\n", T + 200), Some((Conflict, Remote))),
+            // Same edit on both sides
+            (Some("base\n"), ("same\n", T + 100), ("same\n", T + 200), None),
+            // No baseline: different contents can't be told apart
+            (None, ("local\n", T + 100), ("remote\n", T + 200), Some((Conflict, Remote))),
+            (None, ("same\n", T + 100), ("same\n", T + 200), None),
+            // Unchanged
+            (Some("base\n"), ("base\n", T), ("base\n", T), None),
+        ] {
+            let home = TempDir::new();
+            let mut state = SyncState::default();
+            if let Some(content) = baseline {
+                state.update_file("CLAUDE.md", file_state(content));
+            }
 
-/// Scan a directory for changes
-fn scan_directory_changes(
-    src_dir: &Path,
-    dst_dir: &Path,
-    prefix: &str,
-    state: &SyncState,
-    changes: &mut Vec<Change>,
-    local_is_src: bool,
-) {
-    if let Ok(entries) = fs::read_dir(src_dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            let file_name = entry.file_name();
-            let file_name_str = file_name.to_string_lossy();
-
-            // Skip hidden files
-            if file_name_str.starts_with('.') {
-                continue;
+            let changes = detect(&home, &mut state, Some(local), Some(remote));
+            let expected: Vec<_> = expected
+                .map(|(change_type, source)| ("CLAUDE.md".to_string(), change_type, source))
+                .into_iter()
+                .collect();
+            let case = format!("baseline {:?}, local {:?}, remote {:?}", baseline, local, remote);
+            assert_eq!(changes, expected, "{}", case);
+
+            // Equal contents become the new baseline
+            if local.0 == remote.0 {
+                assert_eq!(state.files["CLAUDE.md"].sha256, file_state(local.0).sha256, "{}", case);
             }
+        }
+    }
 
-            let rel_path = format!("{}/{}", prefix, file_name_str);
-            let dst_path = dst_dir.join(&*file_name_str);
-
-            if path.is_file() {
-                let src_state = SyncState::current_file_state(&path);
-                let dst_state = SyncState::current_file_state(&dst_path);
-
-                let should_add = match (&src_state, &dst_state) {
-                    (Some(_src), None) => true,
-                    (Some(src), Some(dst)) => {
-                        if src.sha256 != dst.sha256 {
-                            // Different content - check which is newer
-                            if local_is_src {
-                                src.mtime > dst.mtime
-                            } else {
-                                src.mtime > dst.mtime
-                            }
-                        } else {
-                            false
-                        }
-                    }
-                    _ => false,
-                };
-
-                if should_add {
-                    // Check if we already have this change (avoid duplicates)
-                    let already_exists = changes.iter().any(|c| c.rel_path == rel_path);
-                    if !already_exists {
-                        changes.push(Change {
-                            rel_path,
-                            change_type: if dst_state.is_some() {
-                                ChangeType::Modified
-                            } else {
-                                ChangeType::Created
-                            },
-                            src: path,
-                            dst: dst_path,
-                        });
-                    }
-                }
-            } else if path.is_dir() {
-                // Recurse into subdirectory
-                scan_directory_changes(&path, &dst_path, &rel_path, state, changes, local_is_src);
+    #[test]
+    fn files_on_one_side_without_baseline_are_new() {
+        let home = TempDir::new();
+        let mut state = SyncState::default();
+        let changes = detect(&home, &mut state, Some(("local\n", T)), None);
+        assert_eq!(changes, [("CLAUDE.md".to_string(), ChangeType::Created, ConflictSide::Local)]);
+
+        let home = TempDir::new();
+        let changes = detect(&home, &mut state, None, Some(("remote\n", T)));
+        assert_eq!(changes, [("CLAUDE.md".to_string(), ChangeType::Created, ConflictSide::Remote)]);
+        assert!(state.files.is_empty());
+    }
+
+    #[test]
+    fn deletions_and_tombstones() {
+        use ChangeType::*;
+        use ConflictSide::*;
+
+        for (baseline, tombstone, local, remote, expected) in [
+            // Deleted on one side, untouched on the other
+            (Some("base\n"), None, None, Some("base\n"), Some((Deleted, Local))),
+            (Some("base\n"), None, Some("base\n"), None, Some((Deleted, Remote))),
+            // Deleted on one side, edited on the other: the edit wins
+            (Some("base\n"), None, None, Some("edited\n"), Some((Created, Remote))),
+            (Some("base\n"), None, Some("edited\n"), None, Some((Created, Local))),
+            // A stale copy of something deleted before is deleted again...
+            (None, Some("old\n"), None, Some("old\n"), Some((Deleted, Local))),
+            (None, Some("old\n"), Some("old\n"), None, Some((Deleted, Remote))),
+            // ...but a file created again with other content comes back
+            (None, Some("old\n"), None, Some("new\n"), Some((Created, Remote))),
+            (None, Some("old\n"), Some("new\n"), None, Some((Created, Local))),
+        ] {
+            let home = TempDir::new();
+            let mut state = SyncState::default();
+            if let Some(content) = baseline {
+                state.update_file("CLAUDE.md", file_state(content));
+            }
+            if let Some(content) = tombstone {
+                state.record_deletion("CLAUDE.md", &file_state(content).sha256);
             }
+
+            let changes = detect(&home, &mut state, local.map(|c| (c, T)), remote.map(|c| (c, T)));
+            let expected: Vec<_> = expected
+                .map(|(change_type, source)| ("CLAUDE.md".to_string(), change_type, source))
+                .into_iter()
+                .collect();
+            let case = format!(
+                "baseline {:?}, tombstone {:?}, local {:?}, remote {:?}",
+                baseline, tombstone, local, remote
+            );
+            assert_eq!(changes, expected, "{}", case);
         }
     }
+
+    #[test]
+    fn files_gone_from_both_sides_leave_a_tombstone() {
+        let home = TempDir::new();
+        let mut state = SyncState::default();
+        state.update_file("CLAUDE.md", file_state("base\n"));
+
+        assert!(detect(&home, &mut state, None, None).is_empty());
+        assert!(state.files.is_empty());
+        assert!(state.is_tombstoned("CLAUDE.md", &file_state("base\n")));
+
+        // Synced again, the tombstone goes
+        state.update_file("CLAUDE.md", file_state("new\n"));
+        assert!(state.tombstones.is_empty());
+    }
 }
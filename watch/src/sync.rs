@@ -2,18 +2,45 @@
 //!
 //! NOTE: Distributed locking was removed because it fundamentally cannot work
 //! with Dropbox's eventual consistency. Conflict resolution relies on:
-//! - mtime comparison (newer wins)
+//! - three-way checksum comparison against the last-synced state
+//! - mtime comparison (newer wins) when both sides changed
 //! - checksum verification
 //! - backup-first workflow
 
-use crate::config::Config;
-use crate::state::{detect_changes, SyncState};
-use anyhow::{bail, Context, Result};
+use crate::backup::{BackupStore, Snapshot};
+use crate::baseline::BaselineStore;
+use crate::config::{Config, ConflictStrategy, SecretsMode};
+use crate::echo::SelfWrites;
+use crate::filter::SyncSet;
+use crate::git::{GitRepo, MergeConflict};
+use crate::hashcache;
+use crate::lock::SyncLock;
+use crate::merge::merge_json;
+use crate::remote::{self, file_mode, remove_empty_parents, write_atomic, Remote, RemoteFile};
+use crate::target::ProviderKind;
+use crate::transform::Transforms;
+use crate::state::{
+    detect_changes, Change, ChangeType, ConflictSide, FileState, PendingConflict, SyncState,
+    CONFLICT_COPY_MARKER,
+};
+use anyhow::{anyhow, bail, Context, Result};
 use sha2::{Digest, Sha256};
+use std::collections::BTreeSet;
 use std::ffi::OsStr;
-use std::fs::{self, File};
-use std::io::{BufReader, Read};
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// How long a recently modified source must stay unchanged before it is copied
+const STABLE_INTERVAL: Duration = Duration::from_millis(100);
+/// Sources modified longer ago than this (in seconds) are not waited for
+const STABLE_AGE_SECS: i64 = 2;
+/// Reads of a changing source before it is deferred to the next sync
+const STABLE_ATTEMPTS: u32 = 4;
+/// Deleting fewer files than this is never held back for its share of the
+/// tracked files (in a small tree, any deletion is a large share)
+const MASS_DELETE_MIN_FILES: usize = 5;
 
 /// Direction of sync
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -31,77 +58,293 @@ pub enum SyncDirection {
 pub struct SyncResult {
     /// Number of files copied
     pub copied: usize,
+    /// Number of files deleted
+    pub deleted: usize,
     /// Number of files skipped
     pub skipped: usize,
-    /// Path to backup created
-    pub backup_path: Option<PathBuf>,
+    /// Id of the backup snapshot taken before syncing (None if nothing
+    /// changed, so it was removed again)
+    pub backup_id: Option<String>,
     /// Any warnings encountered
     pub warnings: Vec<String>,
+    /// Files left for the next sync because their source was still being
+    /// written, with the side that changed
+    pub deferred: Vec<(String, ConflictSide)>,
+    /// Conflicts that were resolved during this sync
+    pub conflicts: Vec<ResolvedConflict>,
+}
+
+/// Error for a source file that kept changing while it was being read
+#[derive(Debug)]
+pub struct Unstable(pub String);
+
+impl std::fmt::Display for Unstable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} is still being written", self.0)
+    }
+}
+
+impl std::error::Error for Unstable {}
+
+/// A conflict (both sides changed) and how it was resolved
+#[derive(Debug, Clone)]
+pub struct ResolvedConflict {
+    /// Relative path of the file
+    pub rel_path: String,
+    /// Strategy that was applied
+    pub strategy: ConflictStrategy,
+    /// Side whose content was kept
+    pub winner: ConflictSide,
+    /// Where the losing content was saved (KeepBoth only)
+    pub conflict_copy: Option<PathBuf>,
+    /// Both sides' edits were combined by a JSON merge
+    pub merged: bool,
+    /// JSON keys changed on both sides (JSON merge only)
+    pub keys: Vec<String>,
 }
 
 /// Sync engine
 pub struct SyncEngine {
     config: Config,
     state_path: PathBuf,
+    baseline: BaselineStore,
+    backups: BackupStore,
+    transforms: Transforms,
+    sync_set: SyncSet,
+    /// Where the shared copy is stored
+    remote: Box<dyn Remote>,
+    /// Repository holding the sync directory (git provider only)
+    git: Option<GitRepo>,
+    /// Files this engine wrote, so their watcher events can be ignored
+    self_writes: SelfWrites,
 }
 
 impl SyncEngine {
     /// Create a new sync engine
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config) -> Result<Self> {
         // State is stored locally (not in Dropbox) to prevent conflict file explosion
         let state_path = config.local_state_path();
+        let baseline = BaselineStore::new(config.local_baseline_dir());
+        let backups = BackupStore::new(&config);
+        let transforms = Transforms::load(&config)?;
+        let sync_set = SyncSet::new(&config);
+        let git = match config.target.kind {
+            ProviderKind::Git => Some(GitRepo::open(&config)?),
+            _ => None,
+        };
+        let remote = remote::open(&config)?;
 
-        Self {
+        Ok(Self {
             config,
             state_path,
+            baseline,
+            backups,
+            transforms,
+            sync_set,
+            remote,
+            git,
+            self_writes: SelfWrites::default(),
+        })
+    }
+
+    /// Where the shared copy is stored
+    pub fn remote(&self) -> &dyn Remote {
+        self.remote.as_ref()
+    }
+
+    /// Check if a watcher event for `path` was caused by a write of this
+    /// engine (the file still has the content it wrote)
+    pub fn is_self_write(&self, path: &Path) -> bool {
+        self.self_writes.is_echo(path)
+    }
+
+    /// Check a remote that can't be watched for changes: fetch the git remote
+    /// and check if it has commits to pull, or list the bucket
+    pub fn remote_has_updates(&self) -> Result<bool> {
+        match &self.git {
+            Some(git) => Ok(git.fetch()? && git.is_behind()?),
+            None => self.remote.has_changes(&self.sync_set),
         }
     }
 
     /// Perform a sync operation
-    pub fn sync(&self, direction: SyncDirection) -> Result<SyncResult> {
-        log::info!("Starting {:?} sync...", direction);
+    ///
+    /// `dirty` limits change detection to the given relative paths (files or
+    /// deleted directories), as reported by the watcher; None scans
+    /// everything.
+    ///
+    /// Waits while a backup is being restored or conflicts are being
+    /// resolved (see `SyncLock`).
+    pub fn sync(&self, direction: SyncDirection, dirty: Option<&BTreeSet<String>>) -> Result<SyncResult> {
+        let _lock = SyncLock::acquire(&self.config.local_sync_lock_path(), || {
+            log::info!("Waiting for a backup restore or conflict resolution to finish...")
+        })?;
+
+        match dirty {
+            Some(dirty) => log::info!("Starting {:?} sync of {} changed path(s)...", direction, dirty.len()),
+            None => log::info!("Starting full {:?} sync...", direction),
+        }
 
         // NOTE: No distributed lock - it cannot work with Dropbox's eventual consistency.
         // Conflict resolution is handled by mtime comparison and checksum verification.
 
         // 1. CREATE BACKUP FIRST (mandatory!)
-        let backup_path = self.create_backup()?;
-        log::info!("Backup created: {:?}", backup_path);
+        let previous_backup = self.backups.last_id();
+        let (mut backup, backup_created) = self.backups.snapshot(&self.config.claude_dir, &self.sync_set)?;
+        log::info!("Backup created: {}", backup.id);
 
         // 2. Ensure directories exist
         fs::create_dir_all(&self.config.claude_dir)?;
-        fs::create_dir_all(&self.config.dropbox_claude_dir)?;
+        if let Some(remote_dir) = self.remote.local_dir() {
+            // Creating the provider folder itself would turn a missing
+            // mount into an empty remote
+            if remote_dir.parent().is_some_and(|parent| !parent.exists()) {
+                bail!("{} folder not found (not mounted?): {:?}", self.remote.name(), remote_dir);
+            }
+            fs::create_dir_all(remote_dir)?;
+        }
 
         // 3. Load sync state (from local storage, not Dropbox)
         let mut state = SyncState::load(&self.state_path).unwrap_or_default();
         state.machine_id = Config::machine_id();
+        let previous_target = state.target.clone();
+        if state.bind_target(&self.remote.location()) {
+            log::warn!(
+                "Sync target changed from {} to {}; starting without a baseline",
+                previous_target.unwrap_or_default(),
+                self.remote.location()
+            );
+        }
+        let mut warnings = Vec::new();
+
+        // Merge other machines' commits first (git provider). Whatever the
+        // merge brought in has to reach this machine too.
+        let mut direction = direction;
+        let mut dirty = dirty;
+        if let Some(git) = &self.git {
+            match git.pull() {
+                Ok(pull) => {
+                    if pull.updated {
+                        log::info!("Merged remote commits from {}", self.config.git.remote);
+                        direction = SyncDirection::Bidirectional;
+                        dirty = None;
+                    }
+                    for conflict in &pull.conflicts {
+                        self.expect_conflict(conflict, &mut state);
+                    }
+                }
+                Err(e) => {
+                    let warning = format!("Failed to pull from git remote: {:#}", e);
+                    log::warn!("{}", warning);
+                    warnings.push(warning);
+                }
+            }
+        }
 
         // 4. Detect changes
+        let tracked = state.files.len();
+        let scope = dirty.and_then(|dirty| self.scope(dirty, &state));
         let changes = detect_changes(
             &self.config.claude_dir,
-            &self.config.dropbox_claude_dir,
-            &self.config.sync_files,
-            &self.config.sync_dirs,
-            &state,
-        );
+            self.remote.as_ref(),
+            &self.sync_set,
+            &mut state,
+            &self.transforms,
+            scope.as_ref(),
+        )?;
 
         log::info!("Detected {} change(s)", changes.len());
 
-        // 5. Apply changes based on direction
         let mut copied = 0;
+        let mut deleted = 0;
         let mut skipped = 0;
-        let mut warnings = Vec::new();
+        let mut conflicts = Vec::new();
+        let mut deferred = Vec::new();
+
+        // Files the provider hasn't finished downloading look missing or
+        // stale; leave them for a later sync. Provider conflict copies are
+        // left in place for `resolve`.
+        let changes: Vec<Change> = changes
+            .into_iter()
+            .filter(|change| {
+                if self.remote.is_pending(&change.rel_path) {
+                    log::info!(
+                        "Skipping {}: still syncing from {}",
+                        change.rel_path,
+                        self.remote.name()
+                    );
+                    skipped += 1;
+                    return false;
+                }
+                if self.remote.is_conflict_copy(&change.rel_path) {
+                    log::debug!("Skipping {} conflict copy: {}", self.remote.name(), change.rel_path);
+                    return false;
+                }
+                true
+            })
+            .collect();
+
+        // 5. Resolve conflicts (both sides changed) using the configured strategy.
+        // These are applied regardless of direction: which watcher fired says
+        // nothing about which side should win.
+        let (conflicting, changes): (Vec<_>, Vec<_>) = changes
+            .into_iter()
+            .partition(|c| c.change_type == ChangeType::Conflict);
+
+        for change in &conflicting {
+            match self.resolve_conflict(change, &mut state) {
+                Ok(resolved) => {
+                    if let Some(file_state) = self.synced_state(&change.rel_path) {
+                        state.update_file(&change.rel_path, file_state);
+                    }
+                    copied += 1;
+                    conflicts.push(resolved);
+                }
+                Err(e) if e.is::<Unstable>() => {
+                    log::info!("Deferring conflict in {}: {}", change.rel_path, e);
+                    deferred.push((change.rel_path.clone(), change.source));
+                    skipped += 1;
+                }
+                Err(e) => {
+                    let warning = format!("Failed to resolve conflict in {}: {}", change.rel_path, e);
+                    log::warn!("{}", warning);
+                    warnings.push(warning);
+                    skipped += 1;
+                }
+            }
+        }
 
+        // Hold back deletions that look like a missing or replaced side
+        // rather than files deleted on purpose
+        let changes = match self.mass_deletion(&changes, tracked)? {
+            Some(reason) => {
+                let (deletions, changes): (Vec<_>, Vec<_>) = changes
+                    .into_iter()
+                    .partition(|c| c.change_type == ChangeType::Deleted);
+                let warning = format!(
+                    "Refusing to delete {} file(s): {}; run with --allow-mass-delete if this is intended",
+                    deletions.len(),
+                    reason
+                );
+                log::warn!("{}", warning);
+                warnings.push(warning);
+                skipped += deletions.len();
+                changes
+            }
+            None => changes,
+        };
+
+        // 6. Apply changes based on direction
         for change in &changes {
             // Determine if this change should be applied based on direction
             let should_apply = match direction {
                 SyncDirection::Push => {
                     // Only push: local -> remote
-                    change.src.starts_with(&self.config.claude_dir)
+                    change.source == ConflictSide::Local
                 }
                 SyncDirection::Pull => {
                     // Only pull: remote -> local
-                    change.src.starts_with(&self.config.dropbox_claude_dir)
+                    change.source == ConflictSide::Remote
                 }
                 SyncDirection::Bidirectional => {
                     // Apply all changes
@@ -114,26 +357,56 @@ impl SyncEngine {
                 continue;
             }
 
+            // Propagate deletions (keeping a copy in the pre-sync backup)
+            if change.change_type == ChangeType::Deleted {
+                match self.safe_delete_file(change, &mut backup) {
+                    Ok(sha256) => {
+                        log::info!("Deleted: {} ({})", change.rel_path, self.side_name(change.source.other()));
+                        state.record_deletion(&change.rel_path, &sha256);
+                        deleted += 1;
+                    }
+                    Err(e) => {
+                        let warning = format!("Failed to delete {}: {}", change.rel_path, e);
+                        log::warn!("{}", warning);
+                        warnings.push(warning);
+                        skipped += 1;
+                    }
+                }
+                continue;
+            }
+
             // Validate and copy
-            match self.safe_copy_file(&change.src, &change.dst) {
+            let copy = match (change.source, &change.remote) {
+                (ConflictSide::Local, expected) => self.push_file(&change.rel_path, expected.as_ref()).map(|_| ()),
+                (ConflictSide::Remote, Some(remote)) => self.pull_file(&change.rel_path, remote),
+                (ConflictSide::Remote, None) => Err(anyhow!("Source does not exist in {}", self.remote.name())),
+            };
+            match copy {
                 Ok(()) => {
                     log::info!(
-                        "{:?}: {} -> {}",
+                        "{:?}: {} ({} -> {})",
                         change.change_type,
-                        change.src.display(),
-                        change.dst.display()
+                        change.rel_path,
+                        self.side_name(change.source),
+                        self.side_name(change.source.other())
                     );
 
                     // Update state
-                    if let Ok(file_state) = SyncState::get_file_state(&change.dst) {
+                    if let Some(file_state) = self.synced_state(&change.rel_path) {
                         state.update_file(&change.rel_path, file_state);
                     }
 
                     copied += 1;
                 }
+                // Still being written: try again once it has settled
+                Err(e) if e.is::<Unstable>() => {
+                    log::info!("Deferring {}: {}", change.rel_path, e);
+                    deferred.push((change.rel_path.clone(), change.source));
+                    skipped += 1;
+                }
                 Err(e) => {
                     let warning = format!(
-                        "Failed to copy {}: {}",
+                        "Failed to copy {}: {:#}",
                         change.rel_path,
                         e
                     );
@@ -144,164 +417,618 @@ impl SyncEngine {
             }
         }
 
-        // 6. Save updated state (to local storage, not Dropbox)
+        // 7. Save updated state (to local storage, not Dropbox)
+        state.prune_tombstones();
         state.save(&self.state_path)?;
+        hashcache::save();
+
+        // Keep baseline contents in step with the saved state
+        if let Err(e) = self.baseline.refresh(&state, |rel_path| self.remote.read(rel_path).ok()) {
+            log::warn!("Failed to update baseline store: {}", e);
+        }
+
+        // Record this sync as a commit and share it (git provider)
+        if let Some(git) = &self.git {
+            match git.commit("Sync") {
+                Ok(Some(paths)) => log::info!("Committed {} changed path(s)", paths.len()),
+                Ok(None) => {}
+                Err(e) => {
+                    let warning = format!("Failed to commit sync: {:#}", e);
+                    log::warn!("{}", warning);
+                    warnings.push(warning);
+                }
+            }
+            if let Err(e) = git.push() {
+                let warning = format!("Failed to push to git remote: {:#}", e);
+                log::warn!("{}", warning);
+                warnings.push(warning);
+            }
+        }
 
         log::info!(
-            "Sync complete: {} copied, {} skipped",
+            "Sync complete: {} copied, {} deleted, {} skipped",
             copied,
+            deleted,
             skipped
         );
 
         // Cleanup backup if no synced files changed
-        let final_backup_path = if cleanup_backup_if_unchanged(
-            &backup_path,
-            &self.config.claude_dir,
-            &self.config.sync_files,
-            &self.config.sync_dirs,
-        ) {
-            None // Backup was removed
+        let backup_id = if backup_created && self.backups.matches(&backup, &self.config.claude_dir, &self.sync_set) {
+            log::info!("No synced files changed, removing unnecessary backup: {}", backup.id);
+            match self.backups.remove(&backup.id) {
+                Ok(()) => {
+                    // Undo still goes back to before the last sync that
+                    // changed local files
+                    if let Some(previous) = previous_backup {
+                        if let Err(e) = self.backups.set_last(&previous) {
+                            log::warn!("Failed to update last backup marker: {}", e);
+                        }
+                    }
+                    None
+                }
+                Err(e) => {
+                    log::warn!("Failed to remove backup: {}", e);
+                    Some(backup.id)
+                }
+            }
         } else {
-            Some(backup_path) // Backup was kept
+            Some(backup.id)
         };
 
+        // Drop old backups the retention policy doesn't keep
+        match self.backups.prune(&self.config.retention) {
+            Ok(pruned) if !pruned.is_empty() => log::info!("Pruned {} old backup(s)", pruned.len()),
+            Ok(_) => {}
+            Err(e) => log::warn!("Failed to prune backups: {:#}", e),
+        }
+
         Ok(SyncResult {
             copied,
+            deleted,
             skipped,
-            backup_path: final_backup_path,
+            backup_id,
             warnings,
+            deferred,
+            conflicts,
         })
     }
 
-    /// Create a timestamped backup of ~/.claude
-    /// Skips creating backup if synced files haven't changed since last backup
-    fn create_backup(&self) -> Result<PathBuf> {
-        let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
-        let backup_path = PathBuf::from(format!(
-            "{}/.claude_backup.{}",
-            dirs::home_dir()
-                .map(|p| p.to_string_lossy().to_string())
-                .unwrap_or_else(|| "/tmp".to_string()),
-            timestamp
-        ));
-
-        if !self.config.claude_dir.exists() {
-            log::info!("~/.claude does not exist, skipping backup");
-            return Ok(backup_path);
-        }
-
-        // Check if synced files have changed since last backup
-        let last_backup_file = dirs::home_dir()
-            .unwrap_or_else(|| PathBuf::from("/tmp"))
-            .join(".claude_sync_last_backup");
-
-        if last_backup_file.exists() {
-            if let Ok(last_backup_path_str) = fs::read_to_string(&last_backup_file) {
-                let last_backup_path = PathBuf::from(last_backup_path_str.trim());
-                if last_backup_path.exists() {
-                    // Compare only synced files
-                    if synced_files_are_identical(
-                        &self.config.claude_dir,
-                        &last_backup_path,
-                        &self.config.sync_files,
-                        &self.config.sync_dirs,
-                    ) {
-                        log::info!(
-                            "Synced files unchanged since last backup, skipping new backup"
-                        );
-                        // Return the existing backup path for reference
-                        return Ok(last_backup_path);
-                    }
-                }
-            }
+    /// Why the deletions among `changes` look like a missing or replaced side
+    /// (None if they can be applied)
+    ///
+    /// That is the case when local files would be deleted because the
+    /// remote is empty, or when more than `max_delete_percent` of the
+    /// `tracked` files would be deleted.
+    fn mass_deletion(&self, changes: &[Change], tracked: usize) -> Result<Option<String>> {
+        let deletions: Vec<&Change> = changes
+            .iter()
+            .filter(|c| c.change_type == ChangeType::Deleted)
+            .collect();
+        if deletions.is_empty() || self.config.allow_mass_delete {
+            return Ok(None);
         }
 
-        // Create new backup - synced files have changed
-        log::info!("Creating backup: {:?}", backup_path);
-        copy_dir_all(&self.config.claude_dir, &backup_path)?;
+        let deletes_local = deletions.iter().any(|c| c.source == ConflictSide::Remote);
+        if deletes_local && tracked > 0 && self.remote.list(&self.sync_set)?.is_empty() {
+            return Ok(Some(format!("{} is empty", self.remote.location())));
+        }
 
-        // Save path for undo capability
-        fs::write(&last_backup_file, backup_path.to_string_lossy().as_bytes())?;
+        let limit = tracked * self.config.max_delete_percent as usize;
+        if deletions.len() >= MASS_DELETE_MIN_FILES && deletions.len() * 100 > limit {
+            return Ok(Some(format!(
+                "that is {} of {} tracked files (more than {}%)",
+                deletions.len(),
+                tracked,
+                self.config.max_delete_percent
+            )));
+        }
 
-        Ok(backup_path)
+        Ok(None)
     }
 
-    /// Copy a file with validation
-    fn safe_copy_file(&self, src: &Path, dst: &Path) -> Result<()> {
-        // Check source exists
-        if !src.exists() {
-            bail!("Source does not exist: {:?}", src);
+    /// Synced files a set of changed paths stands for, or None if everything
+    /// has to be scanned
+    ///
+    /// A deleted directory stands for the baseline files under it. A
+    /// directory that exists needs a full scan: files moved into it don't
+    /// produce events of their own.
+    fn scope(&self, dirty: &BTreeSet<String>, state: &SyncState) -> Option<BTreeSet<String>> {
+        let mut scope = BTreeSet::new();
+        for rel_path in dirty {
+            let local_path = self.config.claude_dir.join(rel_path);
+            let remote_path = self.remote.local_path(rel_path);
+            if local_path.is_dir() || remote_path.is_some_and(|path| path.is_dir()) {
+                log::debug!("{} is a directory, scanning everything", rel_path);
+                return None;
+            }
+
+            let prefix = format!("{}/", rel_path);
+            scope.extend(state.files.keys().filter(|path| path.starts_with(&prefix)).cloned());
+            scope.insert(rel_path.clone());
         }
+        scope.retain(|rel_path| self.sync_set.is_synced(&self.config.claude_dir, rel_path, false));
+        Some(scope)
+    }
 
-        // Check source is not empty (sign of Dropbox sync in progress)
-        let metadata = fs::metadata(src)?;
+    /// Copy a local file to the remote, provided the remote file is still as
+    /// `expected`
+    ///
+    /// Content is converted to its shared form on the way (see `Transforms`),
+    /// and the checksum verification compares shared forms.
+    fn push_file(&self, rel_path: &str, expected: Option<&RemoteFile>) -> Result<RemoteFile> {
+        let src = self.config.claude_dir.join(rel_path);
+
+        // Check source is settled and not empty (sign of a write in progress)
+        let (metadata, content) = read_stable(&src)?;
         if metadata.len() == 0 {
-            bail!(
-                "Source file is empty (Dropbox sync in progress?): {:?}",
-                src
-            );
+            bail!("Source file is empty (write in progress?): {:?}", src);
         }
 
         // Validate JSON if applicable
-        if src.extension() == Some(OsStr::new("json")) {
-            self.validate_json(src)?;
-        }
-
-        // Ensure destination directory exists
-        if let Some(parent) = dst.parent() {
-            fs::create_dir_all(parent)?;
+        if is_json(rel_path) {
+            check_json(&content, &src)?;
         }
 
-        // Copy preserving metadata
-        fs::copy(src, dst).with_context(|| format!("Failed to copy {:?} to {:?}", src, dst))?;
+        let shared = if self.transforms.is_identity(rel_path) {
+            content
+        } else {
+            let current = expected.and_then(|_| self.remote.read(rel_path).ok());
+            self.transforms.to_shared(rel_path, &content, current.as_deref())
+        };
+        self.transforms.check_secrets(rel_path, &shared)?;
 
-        // Verify checksum
-        let src_hash = sha256_file(src)?;
-        let dst_hash = sha256_file(dst)?;
+        // Keep permissions (skill scripts need their executable bit)
+        let written = self.remote.write(rel_path, &shared, file_mode(&metadata), expected)?;
+        self.record_remote_write(rel_path);
 
-        if src_hash != dst_hash {
-            fs::remove_file(dst).ok();
+        // Verify checksum (of the shared form on both sides)
+        let local_hash = format!("{:x}", Sha256::digest(&shared));
+        if local_hash != written.state.sha256 {
             bail!(
                 "Checksum mismatch after copy: {} vs {}",
-                src_hash,
-                dst_hash
+                local_hash,
+                written.state.sha256
             );
         }
 
-        log::debug!("Copied and verified: {:?} -> {:?}", src, dst);
+        log::debug!("Copied and verified: {} -> {}", rel_path, self.remote.name());
+        Ok(written)
+    }
+
+    /// Copy a remote file to ~/.claude
+    ///
+    /// Content is converted to its local form on the way (see `Transforms`),
+    /// and the checksum verification compares shared forms.
+    fn pull_file(&self, rel_path: &str, remote: &RemoteFile) -> Result<()> {
+        // Check source is not empty or a placeholder (sign of sync in progress)
+        if remote.state.size == 0 {
+            bail!(
+                "Source file is empty ({} sync in progress?): {}",
+                self.remote.name(),
+                rel_path
+            );
+        }
+        if self.remote.is_pending(rel_path) {
+            bail!(
+                "Source file is still syncing from {}: {}",
+                self.remote.name(),
+                rel_path
+            );
+        }
+
+        let shared = self.read_remote_stable(rel_path, remote)?;
+        let remote_hash = remote.state.sha256.clone();
+
+        // Validate JSON if applicable
+        if is_json(rel_path) {
+            check_json(&shared, Path::new(rel_path))?;
+        }
+
+        let dst = self.config.claude_dir.join(rel_path);
+        let content = if self.transforms.is_identity(rel_path) {
+            shared.clone()
+        } else {
+            let existing = fs::read(&dst).ok();
+            self.transforms.to_local(rel_path, &shared, existing.as_deref())
+        };
+
+        // Verify checksum (of the shared form on both sides) before touching
+        // the destination; `write_atomic` checks the bytes it writes
+        if !self.transforms.is_identity(rel_path) {
+            let shared_form = self.transforms.to_shared(rel_path, &content, Some(&shared));
+            let local_hash = format!("{:x}", Sha256::digest(&shared_form));
+            if local_hash != remote_hash {
+                bail!(
+                    "Checksum mismatch after copy: {} vs {}",
+                    local_hash,
+                    remote_hash
+                );
+            }
+        }
+
+        // Staged and renamed into place, so the previous file survives a
+        // failed write and nothing sees a partial one
+        write_atomic(&dst, &content, remote.mode).with_context(|| format!("Failed to write {:?}", dst))?;
+        self.self_writes.record(&dst, &content);
+
+        log::debug!("Copied and verified: {} -> {:?}", rel_path, dst);
         Ok(())
     }
 
+    /// Read a remote file, provided it is still as listed and has settled
+    ///
+    /// Objects on network remotes are replaced whole, so checking the
+    /// checksum is enough there. Files in a synced folder are stat'ed again
+    /// after reading (after `STABLE_INTERVAL` if they were modified just
+    /// now), as the provider may still be downloading them.
+    fn read_remote_stable(&self, rel_path: &str, listed: &RemoteFile) -> Result<Vec<u8>> {
+        until_stable(rel_path, || {
+            let shared = self.remote.read(rel_path)?;
+            if format!("{:x}", Sha256::digest(&shared)) != listed.state.sha256 {
+                return Ok(None);
+            }
+            if self.remote.local_dir().is_none() {
+                return Ok(Some(shared));
+            }
+
+            if is_recent(listed.state.mtime) {
+                thread::sleep(STABLE_INTERVAL);
+            }
+            let settled = self
+                .remote
+                .stat(rel_path)?
+                .is_some_and(|current| {
+                    current.state.sha256 == listed.state.sha256
+                        && current.state.size == listed.state.size
+                        && current.state.mtime == listed.state.mtime
+                });
+            Ok(settled.then_some(shared))
+        })
+    }
+
+    /// Make the engine treat a file from a conflicted git merge as changed on
+    /// both sides: its baseline becomes the merge base
+    fn expect_conflict(&self, conflict: &MergeConflict, state: &mut SyncState) {
+        log::info!("Git merge conflict in {}", conflict.rel_path);
+
+        let Some(base) = &conflict.base else {
+            // Added on both sides
+            state.remove_file(&conflict.rel_path);
+            return;
+        };
+
+        match self.baseline.put(base) {
+            Ok(sha256) => {
+                let mtime = state.files.get(&conflict.rel_path).map_or(0, |f| f.mtime);
+                state.update_file(
+                    &conflict.rel_path,
+                    FileState {
+                        sha256,
+                        mtime,
+                        size: base.len() as u64,
+                    },
+                );
+            }
+            Err(e) => {
+                log::warn!("Could not store merge base for {}: {}", conflict.rel_path, e);
+                state.remove_file(&conflict.rel_path);
+            }
+        }
+    }
+
+    /// Resolve a file that changed on both sides since the last sync
+    ///
+    /// Picks a winner according to `conflict_strategy` and copies it over the
+    /// other side. With `KeepBoth`, the losing content is first saved next to
+    /// the local file as `name.conflict-<machine_id>-<timestamp>.ext`.
+    ///
+    /// Both sides are kept in the baseline store and the conflict is recorded
+    /// in `state` so it can be reviewed later with `claude-sync-watch resolve`.
+    fn resolve_conflict(&self, change: &Change, state: &mut SyncState) -> Result<ResolvedConflict> {
+        let local_path = self.config.claude_dir.join(&change.rel_path);
+        let remote_file = change
+            .remote
+            .as_ref()
+            .with_context(|| format!("{} is missing in {}", change.rel_path, self.remote.name()))?;
+
+        let remote_content = self.remote.read(&change.rel_path)?;
+        let local = self
+            .transforms
+            .local_state(&change.rel_path, &local_path, || Some(remote_content.clone()))
+            .with_context(|| format!("Failed to read {:?}", local_path))?;
+        let mut remote = remote_file.state.clone();
+
+        // A checkout stamps files with the time of the pull; for git, "newest"
+        // means most recently committed
+        if let Some(mtime) = self.git.as_ref().and_then(|git| git.commit_time(&change.rel_path)) {
+            remote.mtime = mtime;
+        }
+
+        let strategy = self.config.conflict_strategy;
+        let winner = self.pick_winner(&local, &remote);
+        let base_sha256 = state.files.get(&change.rel_path).map(|f| f.sha256.clone());
+
+        // Keep both (shared form) contents around for review before one is overwritten
+        let local_bytes = fs::read(&local_path)?;
+        let local_content = self.transforms.to_shared(&change.rel_path, &local_bytes, Some(&remote_content));
+        self.baseline.put_checked(&local_content, &local.sha256)?;
+        self.baseline.put_checked(&remote_content, &remote.sha256)?;
+
+        // JSON config files: merge key by key, only falling back to a
+        // whole-file resolution when the merge can't be done
+        if self.config.json_merge_files.contains(&change.rel_path) {
+            if let Some(keys) = self.merge_json_conflict(
+                &change.rel_path,
+                &local_content,
+                &remote_content,
+                remote_file,
+                base_sha256.as_deref(),
+                winner,
+            )? {
+                if !keys.is_empty() {
+                    state.conflicts.insert(
+                        change.rel_path.clone(),
+                        PendingConflict {
+                            base_sha256,
+                            local_sha256: local.sha256,
+                            remote_sha256: remote.sha256,
+                            kept: winner,
+                            conflict_copy: None,
+                            detected_at: chrono::Utc::now(),
+                        },
+                    );
+                }
+
+                return Ok(ResolvedConflict {
+                    rel_path: change.rel_path.clone(),
+                    strategy,
+                    winner,
+                    conflict_copy: None,
+                    merged: true,
+                    keys,
+                });
+            }
+        }
+
+        let conflict_copy = if strategy == ConflictStrategy::KeepBoth {
+            let copy_path = conflict_copy_path(&local_path);
+            // Saved in ~/.claude, so in local form
+            let saved = match winner {
+                ConflictSide::Local => fs::write(
+                    &copy_path,
+                    self.transforms.to_local(&change.rel_path, &remote_content, Some(&local_bytes)),
+                ),
+                ConflictSide::Remote => fs::copy(&local_path, &copy_path).map(|_| ()),
+            };
+            saved.with_context(|| format!("Failed to save conflict copy {:?}", copy_path))?;
+            Some(copy_path)
+        } else {
+            None
+        };
+
+        match winner {
+            ConflictSide::Local => self.push_file(&change.rel_path, Some(remote_file)).map(|_| ())?,
+            ConflictSide::Remote => self.pull_file(&change.rel_path, remote_file)?,
+        }
+
+        log::debug!("Conflict in {}: {:?} wins", change.rel_path, winner);
+
+        state.conflicts.insert(
+            change.rel_path.clone(),
+            PendingConflict {
+                base_sha256,
+                local_sha256: local.sha256,
+                remote_sha256: remote.sha256,
+                kept: winner,
+                conflict_copy: conflict_copy.clone(),
+                detected_at: chrono::Utc::now(),
+            },
+        );
+
+        Ok(ResolvedConflict {
+            rel_path: change.rel_path.clone(),
+            strategy,
+            winner,
+            conflict_copy,
+            merged: false,
+            keys: Vec::new(),
+        })
+    }
+
+    /// Pick the side that wins a conflict according to `conflict_strategy`
+    fn pick_winner(&self, local: &FileState, remote: &FileState) -> ConflictSide {
+        match self.config.conflict_strategy {
+            ConflictStrategy::Local => ConflictSide::Local,
+            ConflictStrategy::Remote => ConflictSide::Remote,
+            ConflictStrategy::Newest | ConflictStrategy::KeepBoth => {
+                if local.mtime != remote.mtime {
+                    if local.mtime > remote.mtime {
+                        ConflictSide::Local
+                    } else {
+                        ConflictSide::Remote
+                    }
+                } else if local.sha256 > remote.sha256 {
+                    // Same mtime: break the tie on content so every machine
+                    // picks the same winner
+                    ConflictSide::Local
+                } else {
+                    ConflictSide::Remote
+                }
+            }
+        }
+    }
+
+    /// Three-way merge a JSON file changed on both sides and write the result
+    /// to both sides
+    ///
+    /// Works on shared forms: the merge result becomes the shared content and
+    /// is pulled back so local overrides are re-applied. Returns the JSON
+    /// pointers of keys that conflicted (resolved in favour of `winner`), or
+    /// None if the file has to be resolved as a whole instead: either side
+    /// isn't valid JSON, or keys conflicted and the strategy is KeepBoth
+    /// (which needs a conflict copy of the whole file).
+    fn merge_json_conflict(
+        &self,
+        rel_path: &str,
+        local_content: &[u8],
+        remote_content: &[u8],
+        remote_file: &RemoteFile,
+        base_sha256: Option<&str>,
+        winner: ConflictSide,
+    ) -> Result<Option<Vec<String>>> {
+        let parse = |content: &[u8]| serde_json::from_slice::<serde_json::Value>(content).ok();
+
+        let (Some(local), Some(remote)) = (parse(local_content), parse(remote_content)) else {
+            return Ok(None);
+        };
+
+        let base = base_sha256
+            .and_then(|sha| self.baseline.get(sha))
+            .and_then(|bytes| parse(&bytes));
+
+        let merge = merge_json(base.as_ref(), &local, &remote, winner);
+        if !merge.conflicts.is_empty() && self.config.conflict_strategy == ConflictStrategy::KeepBoth {
+            return Ok(None);
+        }
+
+        let mut content = serde_json::to_vec_pretty(&merge.merged)?;
+        if local_content.ends_with(b"\n") {
+            content.push(b'\n');
+        }
+
+        let written = self
+            .remote
+            .write(rel_path, &content, remote_file.mode, Some(remote_file))
+            .with_context(|| format!("Failed to write merged {}", rel_path))?;
+        self.record_remote_write(rel_path);
+        self.pull_file(rel_path, &written)?;
+
+        Ok(Some(merge.conflicts))
+    }
+
+    /// Baseline state of a file that is in sync: its shared-form checksum
+    fn synced_state(&self, rel_path: &str) -> Option<FileState> {
+        self.transforms.local_state(
+            rel_path,
+            &self.config.claude_dir.join(rel_path),
+            || self.remote.read(rel_path).ok(),
+        )
+    }
+
+    /// Name of a side for log messages
+    fn side_name(&self, side: ConflictSide) -> &str {
+        match side {
+            ConflictSide::Local => "local",
+            ConflictSide::Remote => self.remote.name(),
+        }
+    }
+
+    /// Delete a file whose counterpart was deleted on the other side
+    ///
+    /// The file is first added to the pre-sync backup (if not already
+    /// there) so the deletion can be undone. Returns the deleted file's
+    /// checksum for the tombstone.
+    fn safe_delete_file(&self, change: &Change, backup: &mut Snapshot) -> Result<String> {
+        let rel_path = change.rel_path.as_str();
+        let mut save_backup = |content: &[u8], mode: Option<u32>| -> Result<()> {
+            self.backups
+                .add_file(backup, rel_path, content, mode)
+                .with_context(|| format!("Failed to back up {} before deleting", rel_path))
+        };
+
+        match change.source {
+            // Deleted remotely: delete the local file
+            ConflictSide::Remote => {
+                let path = self.config.claude_dir.join(rel_path);
+                let sha256 = self
+                    .synced_state(rel_path)
+                    .map(|s| s.sha256)
+                    .with_context(|| format!("Failed to read {:?}", path))?;
+
+                let mode = fs::metadata(&path).ok().and_then(|m| file_mode(&m));
+                save_backup(&fs::read(&path)?, mode)?;
+                fs::remove_file(&path).with_context(|| format!("Failed to delete {:?}", path))?;
+                self.self_writes.record_deletion(&path);
+
+                // Clean up directories left empty inside the synced tree
+                remove_empty_parents(&path, &self.config.claude_dir);
+
+                Ok(sha256)
+            }
+            // Deleted locally: delete the remote file
+            ConflictSide::Local => {
+                let remote = change
+                    .remote
+                    .as_ref()
+                    .with_context(|| format!("{} is missing in {}", rel_path, self.remote.name()))?;
+
+                // Backups hold local forms
+                let content = self.transforms.to_local(rel_path, &self.remote.read(rel_path)?, None);
+                save_backup(&content, remote.mode)?;
+                self.remote.delete(rel_path, remote)?;
+                if let Some(path) = self.remote.local_path(rel_path) {
+                    self.self_writes.record_deletion(&path);
+                }
+
+                Ok(remote.state.sha256.clone())
+            }
+        }
+    }
+
+    /// Remember a file written to the remote's local folder, if it has one
+    fn record_remote_write(&self, rel_path: &str) {
+        if let Some(path) = self.remote.local_path(rel_path) {
+            self.self_writes.record_file(&path);
+        }
+    }
+
     /// Validate a JSON file
     fn validate_json(&self, path: &Path) -> Result<()> {
-        let content = fs::read_to_string(path)
+        let content = fs::read(path)
             .with_context(|| format!("Failed to read JSON file: {:?}", path))?;
 
-        serde_json::from_str::<serde_json::Value>(&content)
-            .with_context(|| format!("Invalid JSON in file: {:?}", path))?;
-
-        Ok(())
+        check_json(&content, path)
     }
 
     /// Validate source files before sync (pre-flight check)
+    ///
+    /// Only local folders holding plaintext are checked; object storage and
+    /// encrypted files are checked as files are downloaded.
     pub fn validate_sources(&self, direction: SyncDirection) -> Result<Vec<String>> {
         let mut errors = Vec::new();
 
-        let check_dir = match direction {
-            SyncDirection::Push => &self.config.claude_dir,
-            SyncDirection::Pull => &self.config.dropbox_claude_dir,
-            SyncDirection::Bidirectional => {
-                // Check both
-                errors.extend(self.validate_directory(&self.config.claude_dir)?);
-                &self.config.dropbox_claude_dir
+        if direction != SyncDirection::Pull {
+            errors.extend(self.validate_directory(&self.config.claude_dir)?);
+            if self.config.secrets.mode == SecretsMode::Block {
+                errors.extend(self.validate_secrets());
             }
-        };
-
-        errors.extend(self.validate_directory(check_dir)?);
+        }
+        if direction != SyncDirection::Push && !self.config.encryption.enabled {
+            if let Some(remote_dir) = self.remote.local_dir() {
+                errors.extend(self.validate_directory(remote_dir)?);
+            }
+        }
 
         Ok(errors)
     }
 
+    /// Find local files that would be blocked for containing secrets
+    fn validate_secrets(&self) -> Vec<String> {
+        let claude_dir = &self.config.claude_dir;
+        self.sync_set
+            .collect_files(claude_dir)
+            .into_iter()
+            .filter_map(|rel_path| {
+                let local = fs::read(claude_dir.join(&rel_path)).ok()?;
+                let shared = self.transforms.to_shared(&rel_path, &local, None);
+                let locations = self.transforms.find_secrets(&rel_path, &shared);
+                (!locations.is_empty()).then(|| {
+                    format!("{} contains what look like secrets ({})", rel_path, locations.join(", "))
+                })
+            })
+            .collect()
+    }
+
     /// Validate a directory for empty/invalid files
     fn validate_directory(&self, dir: &Path) -> Result<Vec<String>> {
         let mut errors = Vec::new();
@@ -310,54 +1037,29 @@ impl SyncEngine {
             return Ok(errors);
         }
 
-        // Check sync files
-        for file_name in &self.config.sync_files {
-            let file_path = dir.join(file_name);
-            if file_path.exists() {
-                // Check for empty file
-                if let Ok(metadata) = fs::metadata(&file_path) {
-                    if metadata.len() == 0 {
+        for rel_path in self.sync_set.collect_files(dir) {
+            let file_path = dir.join(&rel_path);
+
+            // Check for empty file
+            if let Ok(metadata) = fs::metadata(&file_path) {
+                if metadata.len() == 0 {
+                    if self.sync_set.files().contains(&rel_path) {
                         errors.push(format!(
-                            "{} is empty (Dropbox may still be syncing)",
-                            file_name
+                            "{} is empty ({} may still be syncing)",
+                            rel_path,
+                            self.config.target.name()
                         ));
-                        continue;
-                    }
-                }
-
-                // Validate JSON files
-                if file_path.extension() == Some(OsStr::new("json")) {
-                    if let Err(e) = self.validate_json(&file_path) {
-                        errors.push(format!("{}: {}", file_name, e));
+                    } else {
+                        errors.push(format!("{} is empty", rel_path));
                     }
+                    continue;
                 }
             }
-        }
-
-        // Check sync directories for empty files
-        for dir_name in &self.config.sync_dirs {
-            let dir_path = dir.join(dir_name);
-            if dir_path.exists() && dir_path.is_dir() {
-                for entry in walkdir(&dir_path) {
-                    if entry.is_file() {
-                        if let Ok(metadata) = fs::metadata(&entry) {
-                            if metadata.len() == 0 {
-                                let rel_path = entry.strip_prefix(dir).unwrap_or(&entry);
-                                errors.push(format!(
-                                    "{} is empty",
-                                    rel_path.display()
-                                ));
-                            }
-                        }
 
-                        // Validate JSON files
-                        if entry.extension() == Some(OsStr::new("json")) {
-                            if let Err(e) = self.validate_json(&entry) {
-                                let rel_path = entry.strip_prefix(dir).unwrap_or(&entry);
-                                errors.push(format!("{}: {}", rel_path.display(), e));
-                            }
-                        }
-                    }
+            // Validate JSON files
+            if file_path.extension() == Some(OsStr::new("json")) {
+                if let Err(e) = self.validate_json(&file_path) {
+                    errors.push(format!("{}: {}", rel_path, e));
                 }
             }
         }
@@ -366,240 +1068,481 @@ impl SyncEngine {
     }
 }
 
-/// Calculate SHA-256 of a file
-fn sha256_file(path: &Path) -> Result<String> {
-    let file = File::open(path)?;
-    let mut reader = BufReader::new(file);
-    let mut hasher = Sha256::new();
-    let mut buffer = [0u8; 8192];
+fn is_json(rel_path: &str) -> bool {
+    Path::new(rel_path).extension() == Some(OsStr::new("json"))
+}
 
-    loop {
-        let bytes_read = reader.read(&mut buffer)?;
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
-    }
+/// Check that content parses as JSON
+fn check_json(content: &[u8], path: &Path) -> Result<()> {
+    serde_json::from_slice::<serde_json::Value>(content)
+        .with_context(|| format!("Invalid JSON in file: {:?}", path))?;
 
-    Ok(format!("{:x}", hasher.finalize()))
+    Ok(())
 }
 
-/// Recursively copy a directory
-fn copy_dir_all(src: &Path, dst: &Path) -> Result<()> {
-    fs::create_dir_all(dst)?;
+/// Read a local file once it has stopped changing
+///
+/// The file is stat'ed before and after reading; if it was modified just
+/// now, the second stat and a checksum of the file happen after
+/// `STABLE_INTERVAL`. A different size, mtime or checksum means it is still
+/// being written.
+fn read_stable(path: &Path) -> Result<(fs::Metadata, Vec<u8>)> {
+    until_stable(&format!("{:?}", path), || {
+        let before = fs::metadata(path).with_context(|| format!("Source does not exist: {:?}", path))?;
+        let content = fs::read(path)?;
+
+        let recent = before.modified().ok().and_then(unix_secs).is_none_or(is_recent);
+        if recent {
+            thread::sleep(STABLE_INTERVAL);
+        }
+        let after = fs::metadata(path)?;
+        if after.len() != before.len() || after.modified().ok() != before.modified().ok() {
+            return Ok(None);
+        }
+        if after.len() != content.len() as u64 {
+            return Ok(None);
+        }
+        // Hashed from disk: the checksum cache would trust the unchanged stat
+        if recent && Sha256::digest(fs::read(path)?) != Sha256::digest(&content) {
+            return Ok(None);
+        }
 
-    for entry in fs::read_dir(src)? {
-        let entry = entry?;
-        let file_type = entry.file_type()?;
-        let src_path = entry.path();
-        let dst_path = dst.join(entry.file_name());
+        Ok(Some((after, content)))
+    })
+}
 
-        if file_type.is_dir() {
-            copy_dir_all(&src_path, &dst_path)?;
-        } else if file_type.is_file() {
-            fs::copy(&src_path, &dst_path)?;
-        } else if file_type.is_symlink() {
-            // Copy symlink target
-            let target = fs::read_link(&src_path)?;
-            #[cfg(unix)]
-            std::os::unix::fs::symlink(&target, &dst_path)?;
+/// Retry `attempt` with a growing delay until it returns a value, failing
+/// with `Unstable` after `STABLE_ATTEMPTS` tries
+fn until_stable<T>(name: &str, mut attempt: impl FnMut() -> Result<Option<T>>) -> Result<T> {
+    let mut delay = STABLE_INTERVAL;
+    for _ in 0..STABLE_ATTEMPTS {
+        if let Some(value) = attempt()? {
+            return Ok(value);
         }
+        log::debug!("{} changed while it was being read, retrying in {:?}", name, delay);
+        thread::sleep(delay);
+        delay *= 2;
     }
+    Err(Unstable(name.to_string()).into())
+}
 
-    Ok(())
+/// Whether a Unix timestamp is recent enough that a write may still be going on
+fn is_recent(mtime: i64) -> bool {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+    now - mtime < STABLE_AGE_SECS
 }
 
-/// Simple directory walker
-fn walkdir(dir: &Path) -> Vec<PathBuf> {
-    let mut results = Vec::new();
-
-    if let Ok(entries) = fs::read_dir(dir) {
-        for entry in entries.flatten() {
-            let path = entry.path();
-            if path.is_dir() {
-                results.extend(walkdir(&path));
-            } else {
-                results.push(path);
-            }
-        }
-    }
+fn unix_secs(time: SystemTime) -> Option<i64> {
+    time.duration_since(UNIX_EPOCH).ok().map(|d| d.as_secs() as i64)
+}
 
-    results
+/// Build the path a losing conflict side is saved to:
+/// `name.conflict-<machine_id>-<timestamp>.ext` next to the original
+fn conflict_copy_path(path: &Path) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let timestamp = chrono::Local::now().format("%Y%m%d_%H%M%S");
+    let mut name = format!("{}{}{}-{}", stem, CONFLICT_COPY_MARKER, Config::machine_id(), timestamp);
+    if let Some(ext) = path.extension() {
+        name.push('.');
+        name.push_str(&ext.to_string_lossy());
+    }
+    path.with_file_name(name)
 }
 
-/// Check if two directories have identical content
-fn dirs_are_identical(dir1: &Path, dir2: &Path) -> bool {
-    if !dir1.exists() || !dir2.exists() {
-        return false;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{write_at, TempDir};
+    use std::io::{Seek, SeekFrom, Write};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    /// Engine syncing `home`/.claude with the folder `home`/share/Sync
+    fn folder_engine(home: &TempDir) -> SyncEngine {
+        folder_engine_with(home, "")
     }
 
-    let files1 = walkdir(dir1);
-    let files2 = walkdir(dir2);
+    /// Same, with `extra` settings added to the config
+    fn folder_engine_with(home: &TempDir, extra: &str) -> SyncEngine {
+        SyncEngine::new(folder_config(home, extra)).unwrap()
+    }
 
-    // Quick check: file counts
-    if files1.len() != files2.len() {
-        return false;
+    fn folder_config(home: &TempDir, extra: &str) -> Config {
+        let share = home.path().join("share");
+        fs::create_dir_all(&share).unwrap();
+        Config::for_test(
+            home.path(),
+            &format!(
+                "[paths]\nprovider = \"directory\"\nbase = {:?}\nfolder = \"Sync\"\n\n{}",
+                share.to_string_lossy(),
+                extra
+            ),
+        )
     }
 
-    // Compare each file's checksum
-    for file1 in &files1 {
-        let rel_path = match file1.strip_prefix(dir1) {
-            Ok(p) => p,
-            Err(_) => return false,
-        };
-        let file2 = dir2.join(rel_path);
+    const T: u64 = 1_700_000_000;
 
-        if !file2.exists() {
-            return false;
-        }
+    /// Sync CLAUDE.md, then edit it on both sides and sync again
+    fn conflict(home: &TempDir, engine: &SyncEngine, local: (&str, u64), remote: (&str, u64)) -> SyncResult {
+        write_at(&home.path().join(".claude/CLAUDE.md"), "base\n", T);
+        engine.sync(SyncDirection::Bidirectional, None).unwrap();
 
-        // Compare checksums
-        let hash1 = match sha256_file(file1) {
-            Ok(h) => h,
-            Err(_) => return false,
-        };
-        let hash2 = match sha256_file(&file2) {
-            Ok(h) => h,
-            Err(_) => return false,
-        };
+        write_at(&home.path().join(".claude/CLAUDE.md"), local.0, local.1);
+        write_at(&home.path().join("share/Sync/CLAUDE.md"), remote.0, remote.1);
+        engine.sync(SyncDirection::Bidirectional, None).unwrap()
+    }
 
-        if hash1 != hash2 {
-            return false;
-        }
+    /// Conflict copies saved in ~/.claude, with their contents
+    fn conflict_copies(home: &TempDir) -> Vec<String> {
+        let mut copies: Vec<String> = fs::read_dir(home.path().join(".claude"))
+            .unwrap()
+            .flatten()
+            .filter(|entry| entry.file_name().to_string_lossy().contains(CONFLICT_COPY_MARKER))
+            .map(|entry| fs::read_to_string(entry.path()).unwrap())
+            .collect();
+        copies.sort();
+        copies
     }
 
-    true
-}
+    #[test]
+    fn round_trip_through_folder() {
+        let home = TempDir::new();
+        home.write(".claude/settings.json", "{\"model\": \"opus\"}\n");
+        home.write(".claude/skills/review/SKILL.md", "Review the diff\n");
+        home.write(".claude/notes.txt", "not synced\n");
+        let engine = folder_engine(&home);
+        let local = home.path().join(".claude");
+        let shared = home.path().join("share/Sync");
+
+        // Push
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(result.copied, 2);
+        assert!(result.conflicts.is_empty());
+        assert_eq!(
+            fs::read_to_string(shared.join("settings.json")).unwrap(),
+            "{\"model\": \"opus\"}\n"
+        );
+        assert_eq!(
+            fs::read_to_string(shared.join("skills/review/SKILL.md")).unwrap(),
+            "Review the diff\n"
+        );
+        assert!(!shared.join("notes.txt").exists());
+
+        // Pull an edit, a new file and a deletion made on another machine
+        fs::write(shared.join("settings.json"), "{\"model\": \"sonnet\"}\n").unwrap();
+        home.write("share/Sync/CLAUDE.md", "Be brief\n");
+        fs::remove_file(shared.join("skills/review/SKILL.md")).unwrap();
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(result.copied, 2);
+        assert_eq!(result.deleted, 1);
+        assert_eq!(
+            fs::read_to_string(local.join("settings.json")).unwrap(),
+            "{\"model\": \"sonnet\"}\n"
+        );
+        assert_eq!(fs::read_to_string(local.join("CLAUDE.md")).unwrap(), "Be brief\n");
+        assert!(!local.join("skills/review/SKILL.md").exists());
 
-/// Check if only the synced files are identical between two directories
-/// This ignores non-synced files like debug/, file-history/, todos/, etc.
-fn synced_files_are_identical(
-    dir1: &Path,
-    dir2: &Path,
-    sync_files: &[String],
-    sync_dirs: &[String],
-) -> bool {
-    if !dir1.exists() || !dir2.exists() {
-        return false;
-    }
-
-    // Compare individual sync files
-    for file in sync_files {
-        let file1 = dir1.join(file);
-        let file2 = dir2.join(file);
-
-        // If file exists in one but not the other, they differ
-        if file1.exists() != file2.exists() {
-            return false;
-        }
-
-        // If both exist, compare checksums
-        if file1.exists() {
-            let hash1 = match sha256_file(&file1) {
-                Ok(h) => h,
-                Err(_) => return false,
-            };
-            let hash2 = match sha256_file(&file2) {
-                Ok(h) => h,
-                Err(_) => return false,
-            };
+        // Nothing left to do
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!((result.copied, result.deleted), (0, 0));
+        assert_eq!(result.backup_id, None);
+    }
 
-            if hash1 != hash2 {
-                return false;
+    #[test]
+    fn conflict_strategies() {
+        for (strategy, local_mtime, remote_mtime, winner) in [
+            ("newest", T + 200, T + 100, ConflictSide::Local),
+            ("newest", T + 100, T + 200, ConflictSide::Remote),
+            ("local", T + 100, T + 200, ConflictSide::Local),
+            ("remote", T + 200, T + 100, ConflictSide::Remote),
+        ] {
+            let home = TempDir::new();
+            let engine = folder_engine_with(&home, &format!("[conflicts]\nstrategy = {:?}\n", strategy));
+            let result = conflict(&home, &engine, ("local edit\n", local_mtime), ("remote edit!\n", remote_mtime));
+
+            let case = format!("{} (local at +{}s)", strategy, local_mtime - T);
+            assert_eq!(result.conflicts.len(), 1, "{}", case);
+            assert_eq!(result.conflicts[0].winner, winner, "{}", case);
+            let kept = match winner {
+                ConflictSide::Local => "local edit\n",
+                ConflictSide::Remote => "remote edit!\n",
+            };
+            for side in [".claude/CLAUDE.md", "share/Sync/CLAUDE.md"] {
+                assert_eq!(fs::read_to_string(home.path().join(side)).unwrap(), kept, "{}: {}", case, side);
+            }
+            assert!(conflict_copies(&home).is_empty(), "{}", case);
+
+            // Recorded for review, with both sides' contents kept
+            let state = SyncState::load(&engine.state_path).unwrap();
+            let pending = &state.conflicts["CLAUDE.md"];
+            assert_eq!(pending.kept, winner, "{}", case);
+            for sha256 in [&pending.local_sha256, &pending.remote_sha256] {
+                assert!(engine.baseline.contains(sha256), "{}", case);
             }
         }
     }
 
-    // Compare sync directories
-    for dir in sync_dirs {
-        let dir1_path = dir1.join(dir);
-        let dir2_path = dir2.join(dir);
+    #[test]
+    fn keep_both_saves_the_losing_side_in_local_form() {
+        let home_dir = dirs::home_dir().unwrap().to_string_lossy().to_string();
+
+        // Local wins: the remote side is saved with its placeholders expanded
+        let home = TempDir::new();
+        let engine = folder_engine_with(&home, "[conflicts]\nstrategy = \"keep_both\"\n");
+        let result = conflict(&home, &engine, ("local edit\n", T + 200), ("Run ${HOME}/bin/tool\n", T + 100));
+        assert_eq!(result.conflicts[0].winner, ConflictSide::Local);
+        assert!(result.conflicts[0].conflict_copy.is_some());
+        assert_eq!(conflict_copies(&home), [format!("Run {}/bin/tool\n", home_dir)]);
+        assert_eq!(fs::read_to_string(home.path().join("share/Sync/CLAUDE.md")).unwrap(), "local edit\n");
+
+        // Remote wins: the local side is saved as it was
+        let home = TempDir::new();
+        let engine = folder_engine_with(&home, "[conflicts]\nstrategy = \"keep_both\"\n");
+        let result = conflict(&home, &engine, ("local edit\n", T + 100), ("Run ${HOME}/bin/tool\n", T + 200));
+        assert_eq!(result.conflicts[0].winner, ConflictSide::Remote);
+        assert_eq!(conflict_copies(&home), ["local edit\n"]);
+        assert_eq!(
+            fs::read_to_string(home.path().join(".claude/CLAUDE.md")).unwrap(),
+            format!("Run {}/bin/tool\n", home_dir)
+        );
+    }
 
-        // If directory exists in one but not the other, they differ
-        if dir1_path.exists() != dir2_path.exists() {
-            return false;
-        }
+    #[test]
+    fn newest_breaks_ties_on_content() {
+        let home = TempDir::new();
+        let engine = folder_engine(&home);
+        let file = |sha256: &str, mtime| FileState {
+            sha256: sha256.to_string(),
+            mtime,
+            size: 1,
+        };
 
-        // If both exist, walk and compare all files within
-        if dir1_path.exists() {
-            let files1 = walkdir(&dir1_path);
-            let files2 = walkdir(&dir2_path);
+        assert_eq!(engine.pick_winner(&file("aa", 2), &file("bb", 1)), ConflictSide::Local);
+        assert_eq!(engine.pick_winner(&file("bb", 1), &file("aa", 2)), ConflictSide::Remote);
 
-            // Check file counts
-            if files1.len() != files2.len() {
-                return false;
-            }
+        // Same mtime: the same content wins on either machine
+        assert_eq!(engine.pick_winner(&file("bb", 1), &file("aa", 1)), ConflictSide::Local);
+        assert_eq!(engine.pick_winner(&file("aa", 1), &file("bb", 1)), ConflictSide::Remote);
+    }
 
-            // Compare each file
-            for file1 in &files1 {
-                let rel_path = match file1.strip_prefix(&dir1_path) {
-                    Ok(p) => p,
-                    Err(_) => return false,
-                };
-                let file2 = dir2_path.join(rel_path);
+    #[test]
+    fn scope_of_changed_paths() {
+        let home = TempDir::new();
+        let engine = folder_engine(&home);
+        home.write(".claude/settings.json", "{}");
+        home.write(".claude/skills/review/SKILL.md", "Review the diff\n");
+        home.write(".claude/skills/reviewer/SKILL.md", "Review more\n");
+        engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        let state = SyncState::load(&engine.state_path).unwrap();
+        let paths = |paths: &[&str]| -> BTreeSet<String> { paths.iter().map(|p| p.to_string()).collect() };
+
+        // Files stand for themselves; files outside the sync set are dropped
+        assert_eq!(
+            engine.scope(&paths(&["settings.json", "notes.txt", "CLAUDE.md"]), &state),
+            Some(paths(&["CLAUDE.md", "settings.json"]))
+        );
 
-                if !file2.exists() {
-                    return false;
-                }
+        // A deleted directory stands for the files synced under it
+        fs::remove_dir_all(home.path().join(".claude/skills/review")).unwrap();
+        fs::remove_dir_all(home.path().join("share/Sync/skills/review")).unwrap();
+        assert_eq!(
+            engine.scope(&paths(&["skills/review"]), &state),
+            Some(paths(&["skills/review", "skills/review/SKILL.md"]))
+        );
 
-                // Compare checksums
-                let hash1 = match sha256_file(file1) {
-                    Ok(h) => h,
-                    Err(_) => return false,
-                };
-                let hash2 = match sha256_file(&file2) {
-                    Ok(h) => h,
-                    Err(_) => return false,
-                };
-
-                if hash1 != hash2 {
-                    return false;
-                }
-            }
-        }
+        // A directory on either side needs a full scan
+        assert_eq!(engine.scope(&paths(&["settings.json", "skills/reviewer"]), &state), None);
+        fs::remove_dir_all(home.path().join(".claude/skills/reviewer")).unwrap();
+        assert_eq!(engine.scope(&paths(&["skills/reviewer"]), &state), None);
     }
 
-    true
-}
+    #[test]
+    fn deletions_propagate_and_stay_deleted() {
+        let home = TempDir::new();
+        home.write(".claude/CLAUDE.md", "Be brief\n");
+        home.write(".claude/settings.json", "{}");
+        let engine = folder_engine(&home);
+        let shared = home.path().join("share/Sync/CLAUDE.md");
+        engine.sync(SyncDirection::Bidirectional, None).unwrap();
+
+        // Deleted here, so deleted from the shared copy (and backed up)
+        fs::remove_file(home.path().join(".claude/CLAUDE.md")).unwrap();
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(result.deleted, 1);
+        assert!(!shared.exists());
+        let backup = engine.backups.load(&result.backup_id.unwrap()).unwrap();
+        assert_eq!(engine.backups.read(&backup.files["CLAUDE.md"]).unwrap(), b"Be brief\n");
+
+        // A machine that missed the deletion brings its stale copy back
+        home.write("share/Sync/CLAUDE.md", "Be brief\n");
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!((result.copied, result.deleted), (0, 1));
+        assert!(!shared.exists());
+        assert!(!home.path().join(".claude/CLAUDE.md").exists());
+
+        // A new version is a new file
+        home.write("share/Sync/CLAUDE.md", "Be very brief\n");
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!((result.copied, result.deleted), (1, 0));
+        assert_eq!(
+            fs::read_to_string(home.path().join(".claude/CLAUDE.md")).unwrap(),
+            "Be very brief\n"
+        );
+        assert!(SyncState::load(&engine.state_path).unwrap().tombstones.is_empty());
+    }
 
-/// Remove backup if identical to current state (no changes occurred)
-/// Only compares synced files, ignoring debug/, file-history/, todos/, etc.
-fn cleanup_backup_if_unchanged(
-    backup_path: &Path,
-    claude_dir: &Path,
-    sync_files: &[String],
-    sync_dirs: &[String],
-) -> bool {
-    if !backup_path.exists() {
-        return false;
+    /// Sync `count` skills, returning the engine
+    fn synced_skills(home: &TempDir, config: Config, count: usize) -> SyncEngine {
+        for i in 0..count {
+            home.write(&format!(".claude/skills/s{}/SKILL.md", i), &format!("Skill {}\n", i));
+        }
+        let engine = SyncEngine::new(config).unwrap();
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(result.copied, count);
+        engine
     }
 
-    if synced_files_are_identical(backup_path, claude_dir, sync_files, sync_dirs) {
-        log::info!(
-            "No synced files changed, removing unnecessary backup: {:?}",
-            backup_path
-        );
+    fn skills_left(dir: &Path) -> usize {
+        fs::read_dir(dir.join("skills")).map_or(0, |entries| entries.count())
+    }
 
-        if let Err(e) = fs::remove_dir_all(backup_path) {
-            log::warn!("Failed to remove backup: {}", e);
-            return false;
+    #[test]
+    fn mass_deletions_are_held_back() {
+        // Most of the files deleted at once
+        let home = TempDir::new();
+        let engine = synced_skills(&home, folder_config(&home, ""), 10);
+        for i in 0..6 {
+            fs::remove_dir_all(home.path().join(format!(".claude/skills/s{}", i))).unwrap();
         }
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!((result.deleted, result.skipped), (0, 6));
+        assert!(result.warnings[0].starts_with("Refusing to delete 6 file(s): that is 6 of 10"));
+        assert_eq!(skills_left(&home.path().join("share/Sync")), 10);
+
+        // The shared copy emptied (e.g. a new, empty folder mounted there)
+        let home = TempDir::new();
+        let engine = synced_skills(&home, folder_config(&home, ""), 3);
+        fs::remove_dir_all(home.path().join("share/Sync/skills")).unwrap();
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(result.deleted, 0);
+        assert!(result.warnings[0].contains("is empty"), "{:?}", result.warnings);
+        assert_eq!(skills_left(&home.path().join(".claude")), 3);
+
+        // A few files, or a lower share of them, go through
+        let home = TempDir::new();
+        let engine = synced_skills(&home, folder_config(&home, ""), 10);
+        for i in 0..4 {
+            fs::remove_dir_all(home.path().join(format!("share/Sync/skills/s{}", i))).unwrap();
+        }
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert_eq!(result.deleted, 4);
+        assert_eq!(skills_left(&home.path().join(".claude")), 6);
+    }
 
-        // Clear last backup marker if it pointed to this backup
-        let last_backup_file = dirs::home_dir()
-            .unwrap_or_else(|| PathBuf::from("/tmp"))
-            .join(".claude_sync_last_backup");
+    #[test]
+    fn mass_deletions_can_be_allowed() {
+        for (extra, allow) in [("[sync]\nmax_delete_percent = 100\n", false), ("", true)] {
+            let home = TempDir::new();
+            let mut config = folder_config(&home, extra);
+            config.allow_mass_delete = allow;
+            let engine = synced_skills(&home, config, 10);
+            for i in 0..6 {
+                fs::remove_dir_all(home.path().join(format!(".claude/skills/s{}", i))).unwrap();
+            }
+            let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+            assert_eq!(result.deleted, 6, "{:?}", extra);
+            assert_eq!(skills_left(&home.path().join("share/Sync")), 4);
+        }
+    }
 
-        if last_backup_file.exists() {
-            if let Ok(stored) = fs::read_to_string(&last_backup_file) {
-                if stored.trim() == backup_path.to_string_lossy() {
-                    let _ = fs::remove_file(&last_backup_file);
+    /// Keep rewriting `path` until the returned flag is set: appending, or
+    /// overwriting it in place so only its mtime and content change
+    fn keep_writing(path: PathBuf, same_size: bool) -> (Arc<AtomicBool>, thread::JoinHandle<()>) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let writer = thread::spawn(move || {
+            let mut file = fs::OpenOptions::new().append(!same_size).write(true).open(&path).unwrap();
+            for i in 0.. {
+                if stopped.load(Ordering::SeqCst) {
+                    break;
+                }
+                if same_size {
+                    file.seek(SeekFrom::Start(0)).unwrap();
                 }
+                file.write_all(if i % 2 == 0 { b"aaaa\n" } else { b"bbbb\n" }).unwrap();
+                thread::sleep(Duration::from_millis(20));
             }
-        }
+        });
+        (stop, writer)
+    }
+
+    #[test]
+    fn until_stable_retries_until_a_value() {
+        let mut attempts = 0;
+        let value = until_stable("x", || {
+            attempts += 1;
+            Ok((attempts == 3).then_some(attempts))
+        });
+        assert_eq!(value.unwrap(), 3);
+
+        let mut attempts = 0;
+        let err = until_stable("x", || {
+            attempts += 1;
+            Ok(None::<()>)
+        })
+        .unwrap_err();
+        assert!(err.is::<Unstable>());
+        assert_eq!(attempts, STABLE_ATTEMPTS);
+
+        // Errors are not retried
+        let mut attempts = 0;
+        let err = until_stable("x", || {
+            attempts += 1;
+            Err::<Option<()>, _>(anyhow!("gone"))
+        })
+        .unwrap_err();
+        assert!(!err.is::<Unstable>());
+        assert_eq!(attempts, 1);
+    }
 
-        return true; // Backup was removed
+    #[test]
+    fn read_stable_waits_for_writes_to_finish() {
+        let home = TempDir::new();
+        for same_size in [false, true] {
+            let path = home.write("file.txt", "start\n");
+            let (stop, writer) = keep_writing(path.clone(), same_size);
+
+            let err = read_stable(&path).unwrap_err();
+            assert!(err.is::<Unstable>(), "same size: {}: {:#}", same_size, err);
+
+            stop.store(true, Ordering::SeqCst);
+            writer.join().unwrap();
+            let (metadata, content) = read_stable(&path).unwrap();
+            assert_eq!(content, fs::read(&path).unwrap());
+            assert_eq!(metadata.len(), content.len() as u64);
+        }
     }
 
-    false // Backup was kept
+    #[test]
+    fn files_being_written_are_deferred() {
+        let home = TempDir::new();
+        let path = home.write(".claude/CLAUDE.md", "start\n");
+        let engine = folder_engine(&home);
+        let shared = home.path().join("share/Sync/CLAUDE.md");
+
+        let (stop, writer) = keep_writing(path.clone(), false);
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        stop.store(true, Ordering::SeqCst);
+        writer.join().unwrap();
+        assert_eq!(result.deferred, [("CLAUDE.md".to_string(), ConflictSide::Local)]);
+        assert!(!shared.exists());
+
+        // Retried once the writer is done
+        let result = engine.sync(SyncDirection::Bidirectional, None).unwrap();
+        assert!(result.deferred.is_empty());
+        assert_eq!(fs::read(&shared).unwrap(), fs::read(&path).unwrap());
+    }
 }
@@ -0,0 +1,499 @@
+//! Sync targets: the cloud-synced folder that holds the shared copy
+//!
+//! Most providers are a local folder kept in sync by their own client; what
+//! differs is where that folder usually lives, how the client names the
+//! copies it saves when two machines edit a file at once, and how it marks
+//! files that haven't finished downloading. An S3 bucket, WebDAV server or
+//! SSH server has no local folder and is accessed directly (see `s3`,
+//! `webdav` and `sftp`).
+
+use anyhow::{bail, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Service that syncs the target folder between machines
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Dropbox,
+    #[serde(alias = "icloud_drive")]
+    Icloud,
+    Onedrive,
+    GoogleDrive,
+    Syncthing,
+    /// Any folder (network share, another sync client, ...)
+    Directory,
+    /// Git working tree, synced by pulling and pushing (see `git`)
+    Git,
+    /// S3-compatible object storage (see `s3`)
+    S3,
+    /// WebDAV server such as Nextcloud or ownCloud (see `webdav`)
+    Webdav,
+    /// Directory on a server reached with ssh (see `sftp`)
+    #[serde(alias = "ssh")]
+    Sftp,
+}
+
+impl ProviderKind {
+    /// Providers probed, in order, when none is configured
+    const DETECT_ORDER: [ProviderKind; 5] = [
+        ProviderKind::Dropbox,
+        ProviderKind::Icloud,
+        ProviderKind::Onedrive,
+        ProviderKind::GoogleDrive,
+        ProviderKind::Syncthing,
+    ];
+
+    /// Display name
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::Dropbox => "Dropbox",
+            ProviderKind::Icloud => "iCloud Drive",
+            ProviderKind::Onedrive => "OneDrive",
+            ProviderKind::GoogleDrive => "Google Drive",
+            ProviderKind::Syncthing => "Syncthing",
+            ProviderKind::Directory => "Sync directory",
+            ProviderKind::Git => "Git",
+            ProviderKind::S3 => "S3",
+            ProviderKind::Webdav => "WebDAV",
+            ProviderKind::Sftp => "SFTP",
+        }
+    }
+
+    /// Check if the shared copy is accessed over the network rather than
+    /// through a local folder
+    pub fn is_network(self) -> bool {
+        matches!(self, ProviderKind::S3 | ProviderKind::Webdav | ProviderKind::Sftp)
+    }
+
+    /// Usual locations of the provider's root folder
+    fn default_locations(self, home: &Path) -> Vec<PathBuf> {
+        let cloud_storage = home.join("Library/CloudStorage");
+        match self {
+            ProviderKind::Dropbox => vec![
+                home.join("Dropbox"),
+                cloud_storage.join("Dropbox"),
+                PathBuf::from("/Users/Shared/Dropbox"),
+            ],
+            ProviderKind::Icloud => vec![home.join("Library/Mobile Documents/com~apple~CloudDocs")],
+            ProviderKind::Onedrive => {
+                let mut locations = vec![home.join("OneDrive")];
+                // OneDrive-Personal, OneDrive-<Company>
+                locations.extend(subdirs_with_prefix(&cloud_storage, "OneDrive"));
+                locations
+            }
+            ProviderKind::GoogleDrive => {
+                // GoogleDrive-<account>/My Drive
+                let mut locations: Vec<PathBuf> = subdirs_with_prefix(&cloud_storage, "GoogleDrive")
+                    .into_iter()
+                    .map(|d| d.join("My Drive"))
+                    .collect();
+                locations.push(home.join("Google Drive/My Drive"));
+                locations.push(home.join("Google Drive"));
+                locations
+            }
+            ProviderKind::Syncthing => vec![home.join("Sync")],
+            ProviderKind::Directory
+            | ProviderKind::Git
+            | ProviderKind::S3
+            | ProviderKind::Webdav
+            | ProviderKind::Sftp => Vec::new(),
+        }
+    }
+
+    /// If `file_name` looks like a conflict copy made by this provider, the
+    /// possible names of the original file
+    ///
+    /// - Dropbox: `name (Joe's MacBook's conflicted copy 2025-01-28).ext`
+    /// - iCloud Drive: `name 2.ext`
+    /// - OneDrive: `name-MacBook-Pro.ext` (the machine name may contain `-`,
+    ///   so every split is a candidate)
+    /// - Google Drive: `name (1).ext`
+    /// - Syncthing: `name.sync-conflict-20250128-101500-ABCDEFG.ext`
+    fn conflict_originals(self, file_name: &str) -> Vec<String> {
+        let (stem, ext) = split_extension(file_name);
+        let stems: Vec<&str> = match self {
+            ProviderKind::Dropbox => stem
+                .find("conflicted copy")
+                .filter(|_| stem.ends_with(')'))
+                .and_then(|marker| stem[..marker].rfind(" ("))
+                .map(|open| &stem[..open])
+                .into_iter()
+                .collect(),
+            ProviderKind::Icloud => stem
+                .rsplit_once(' ')
+                .filter(|(_, number)| is_number(number) && *number != "1")
+                .map(|(original, _)| original)
+                .into_iter()
+                .collect(),
+            ProviderKind::Onedrive => stem
+                .match_indices('-')
+                .map(|(dash, _)| &stem[..dash])
+                .collect(),
+            ProviderKind::GoogleDrive => stem
+                .strip_suffix(')')
+                .and_then(|s| s.rsplit_once(" ("))
+                .filter(|(_, number)| is_number(number))
+                .map(|(original, _)| original)
+                .into_iter()
+                .collect(),
+            ProviderKind::Syncthing => stem
+                .find(".sync-conflict-")
+                .map(|marker| &stem[..marker])
+                .into_iter()
+                .collect(),
+            // Git conflicts are handled during the merge, writes to network
+            // backends are conditional
+            ProviderKind::Directory
+            | ProviderKind::Git
+            | ProviderKind::S3
+            | ProviderKind::Webdav
+            | ProviderKind::Sftp => Vec::new(),
+        };
+
+        stems
+            .into_iter()
+            .filter(|s| !s.is_empty())
+            .map(|s| format!("{}{}", s, ext))
+            .collect()
+    }
+
+    /// Check if the provider marks `name` in `dir` as still downloading
+    ///
+    /// iCloud Drive replaces evicted files with `.name.icloud`, and Syncthing
+    /// writes incoming files to `.syncthing.name.tmp` first.
+    fn has_pending_marker(self, dir: &Path, name: &str) -> bool {
+        match self {
+            ProviderKind::Icloud => dir.join(format!(".{}.icloud", name)).exists(),
+            ProviderKind::Syncthing => {
+                dir.join(format!(".syncthing.{}.tmp", name)).exists()
+                    || dir.join(format!("~syncthing~{}.tmp", name)).exists()
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Where the shared copy lives
+#[derive(Debug, Clone)]
+pub struct SyncTarget {
+    /// Provider syncing the folder
+    pub kind: ProviderKind,
+    /// Provider root folder (e.g., ~/Dropbox; empty for network backends)
+    pub base: PathBuf,
+    /// Claude sync directory inside it (e.g., ~/Dropbox/ClaudeCodeSync;
+    /// empty for network backends)
+    pub dir: PathBuf,
+}
+
+impl SyncTarget {
+    /// Resolve the target from configuration
+    ///
+    /// Without a configured base, the provider's usual locations are probed;
+    /// without a provider either, every provider's locations are.
+    pub fn resolve(
+        kind: Option<ProviderKind>,
+        base: Option<PathBuf>,
+        folder: &str,
+        home: &Path,
+    ) -> Result<Self> {
+        let (kind, base) = match (kind, base) {
+            (Some(kind), _) if kind.is_network() => {
+                return Ok(Self {
+                    kind,
+                    base: PathBuf::new(),
+                    dir: PathBuf::new(),
+                })
+            }
+            (kind, Some(base)) => (kind.unwrap_or(ProviderKind::Dropbox), base),
+            (Some(kind @ (ProviderKind::Directory | ProviderKind::Git)), None) => {
+                bail!("paths.base must be set for provider \"{}\"", kind.name().to_lowercase())
+            }
+            (Some(kind), None) => {
+                let base = kind
+                    .default_locations(home)
+                    .into_iter()
+                    .find(|p| p.is_dir())
+                    .with_context(|| {
+                        format!(
+                            "{} folder not found. Set paths.base or run claude-sync-setup.sh --config.",
+                            kind.name()
+                        )
+                    })?;
+                (kind, base)
+            }
+            (None, None) => ProviderKind::DETECT_ORDER
+                .into_iter()
+                .find_map(|kind| {
+                    kind.default_locations(home)
+                        .into_iter()
+                        .find(|p| p.is_dir())
+                        .map(|base| (kind, base))
+                })
+                .context("Sync folder not configured. Run claude-sync-setup.sh --config first.")?,
+        };
+
+        // A git working tree may still have to be cloned
+        if !base.exists() && kind != ProviderKind::Git {
+            bail!("{} directory does not exist: {:?}", kind.name(), base);
+        }
+
+        let dir = base.join(folder);
+        Ok(Self { kind, base, dir })
+    }
+
+    /// Display name of the provider
+    pub fn name(&self) -> &'static str {
+        self.kind.name()
+    }
+
+    /// Local folder holding the shared copy (None for network backends)
+    pub fn local_dir(&self) -> Option<&Path> {
+        (!self.kind.is_network()).then_some(self.dir.as_path())
+    }
+
+    /// If `path` is a provider conflict copy whose original exists next to
+    /// it, the path of the original
+    pub fn conflict_original(&self, path: &Path) -> Option<PathBuf> {
+        let name = path.file_name()?.to_string_lossy();
+        self.kind
+            .conflict_originals(&name)
+            .into_iter()
+            .map(|original| path.with_file_name(original))
+            .find(|original| original.is_file())
+    }
+
+    /// Find provider conflict copies in the sync directory
+    pub fn find_conflicts(&self) -> Vec<PathBuf> {
+        let mut conflicts = Vec::new();
+        self.collect_conflicts(&self.dir, &mut conflicts);
+        conflicts
+    }
+
+    fn collect_conflicts(&self, dir: &Path, conflicts: &mut Vec<PathBuf>) {
+        if let Ok(entries) = fs::read_dir(dir) {
+            for entry in entries.flatten() {
+                let path = entry.path();
+
+                if path.is_dir() {
+                    self.collect_conflicts(&path, conflicts);
+                } else if self.conflict_original(&path).is_some() {
+                    conflicts.push(path);
+                }
+            }
+        }
+    }
+
+    /// Check if a file in the sync directory hasn't finished syncing: a
+    /// provider download marker, or a dataless placeholder on macOS
+    pub fn is_pending(&self, path: &Path) -> bool {
+        let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+            return false;
+        };
+
+        self.kind.has_pending_marker(dir, &name.to_string_lossy()) || is_dataless(path)
+    }
+}
+
+/// Check if a file is a File Provider placeholder whose content is not on
+/// disk (Dropbox, OneDrive and Google Drive on macOS use these)
+#[cfg(target_os = "macos")]
+fn is_dataless(path: &Path) -> bool {
+    use std::os::macos::fs::MetadataExt;
+
+    /// `SF_DATALESS` from <sys/stat.h>
+    const SF_DATALESS: u32 = 0x4000_0000;
+
+    fs::symlink_metadata(path).is_ok_and(|m| m.st_flags() & SF_DATALESS != 0)
+}
+
+#[cfg(not(target_os = "macos"))]
+fn is_dataless(_path: &Path) -> bool {
+    false
+}
+
+/// Split `name.ext` into `name` and `.ext` (no extension for dotfiles)
+fn split_extension(file_name: &str) -> (&str, &str) {
+    match file_name.rfind('.') {
+        Some(dot) if dot > 0 => file_name.split_at(dot),
+        _ => (file_name, ""),
+    }
+}
+
+fn is_number(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit())
+}
+
+/// Subdirectories of `dir` whose name starts with `prefix`
+fn subdirs_with_prefix(dir: &Path, prefix: &str) -> Vec<PathBuf> {
+    let mut dirs: Vec<PathBuf> = fs::read_dir(dir)
+        .map(|entries| {
+            entries
+                .flatten()
+                .filter(|e| e.file_name().to_string_lossy().starts_with(prefix))
+                .map(|e| e.path())
+                .filter(|p| p.is_dir())
+                .collect()
+        })
+        .unwrap_or_default();
+    dirs.sort();
+    dirs
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::TempDir;
+
+    #[test]
+    fn resolve_uses_configured_base() {
+        let home = TempDir::new();
+        let base = home.path().join("share");
+        fs::create_dir(&base).unwrap();
+
+        let target = SyncTarget::resolve(Some(ProviderKind::Directory), Some(base.clone()), "Sync", home.path()).unwrap();
+        assert_eq!(target.kind, ProviderKind::Directory);
+        assert_eq!(target.dir, base.join("Sync"));
+
+        // A base without a provider is taken to be Dropbox
+        let target = SyncTarget::resolve(None, Some(base), "Sync", home.path()).unwrap();
+        assert_eq!(target.kind, ProviderKind::Dropbox);
+    }
+
+    #[test]
+    fn resolve_detects_provider_folder() {
+        let home = TempDir::new();
+        assert!(SyncTarget::resolve(None, None, "Sync", home.path()).is_err());
+
+        fs::create_dir(home.path().join("Sync")).unwrap();
+        let target = SyncTarget::resolve(None, None, "ClaudeCodeSync", home.path()).unwrap();
+        assert_eq!(target.kind, ProviderKind::Syncthing);
+
+        // Dropbox comes first in the detection order
+        fs::create_dir(home.path().join("Dropbox")).unwrap();
+        let target = SyncTarget::resolve(None, None, "ClaudeCodeSync", home.path()).unwrap();
+        assert_eq!(target.kind, ProviderKind::Dropbox);
+        assert_eq!(target.dir, home.path().join("Dropbox/ClaudeCodeSync"));
+    }
+
+    #[test]
+    fn resolve_rejects_missing_folders() {
+        let home = TempDir::new();
+        let missing = home.path().join("missing");
+
+        assert!(SyncTarget::resolve(Some(ProviderKind::Directory), None, "Sync", home.path()).is_err());
+        assert!(SyncTarget::resolve(Some(ProviderKind::Onedrive), None, "Sync", home.path()).is_err());
+        assert!(SyncTarget::resolve(Some(ProviderKind::Directory), Some(missing.clone()), "Sync", home.path()).is_err());
+
+        // A git working tree is cloned on first use
+        let target = SyncTarget::resolve(Some(ProviderKind::Git), Some(missing.clone()), "Sync", home.path()).unwrap();
+        assert_eq!(target.dir, missing.join("Sync"));
+    }
+
+    #[test]
+    fn resolve_network_backends_have_no_folder() {
+        let home = TempDir::new();
+        for kind in [ProviderKind::S3, ProviderKind::Webdav, ProviderKind::Sftp] {
+            let target = SyncTarget::resolve(Some(kind), None, "Sync", home.path()).unwrap();
+            assert_eq!(target.local_dir(), None);
+            assert!(target.find_conflicts().is_empty());
+        }
+    }
+
+    /// Conflict copies `find_conflicts` reports in a folder holding
+    /// `settings.json`, `CLAUDE.md` and `agents/review.md` plus `files`
+    fn conflicts(kind: ProviderKind, files: &[&str]) -> Vec<String> {
+        let home = TempDir::new();
+        for file in ["Sync/settings.json", "Sync/CLAUDE.md", "Sync/agents/review.md"] {
+            home.write(file, "original");
+        }
+        for file in files {
+            home.write(&format!("Sync/{}", file), "copy");
+        }
+
+        let target = SyncTarget::resolve(Some(kind), Some(home.path().to_path_buf()), "Sync", home.path()).unwrap();
+        let mut found: Vec<String> = target
+            .find_conflicts()
+            .iter()
+            .map(|p| p.strip_prefix(&target.dir).unwrap().to_string_lossy().to_string())
+            .collect();
+        found.sort();
+        found
+    }
+
+    #[test]
+    fn find_conflicts_dropbox() {
+        let found = conflicts(
+            ProviderKind::Dropbox,
+            &[
+                "settings (Joe's MacBook's conflicted copy 2025-01-28).json",
+                "agents/review (Joe's MacBook's conflicted copy 2025-01-28 (1)).md",
+                "notes (Joe's MacBook's conflicted copy 2025-01-28).md",
+                "settings (copy).json",
+            ],
+        );
+        assert_eq!(
+            found,
+            [
+                "agents/review (Joe's MacBook's conflicted copy 2025-01-28 (1)).md",
+                "settings (Joe's MacBook's conflicted copy 2025-01-28).json",
+            ]
+        );
+    }
+
+    #[test]
+    fn find_conflicts_icloud() {
+        let found = conflicts(
+            ProviderKind::Icloud,
+            &["settings 2.json", "CLAUDE 3.md", "settings 1.json", "notes 2.md", "settings two.json"],
+        );
+        assert_eq!(found, ["CLAUDE 3.md", "settings 2.json"]);
+    }
+
+    #[test]
+    fn find_conflicts_onedrive() {
+        let found = conflicts(
+            ProviderKind::Onedrive,
+            &["settings-MacBook-Pro.json", "agents/review-Desktop.md", "notes-MacBook.md"],
+        );
+        assert_eq!(found, ["agents/review-Desktop.md", "settings-MacBook-Pro.json"]);
+    }
+
+    #[test]
+    fn find_conflicts_google_drive() {
+        let found = conflicts(
+            ProviderKind::GoogleDrive,
+            &["settings (1).json", "CLAUDE (12).md", "settings (a).json", "notes (1).md"],
+        );
+        assert_eq!(found, ["CLAUDE (12).md", "settings (1).json"]);
+    }
+
+    #[test]
+    fn find_conflicts_syncthing() {
+        let found = conflicts(
+            ProviderKind::Syncthing,
+            &[
+                "settings.sync-conflict-20250128-101500-ABCDEFG.json",
+                "agents/review.sync-conflict-20250128-101500-ABCDEFG.md",
+                "notes.sync-conflict-20250128-101500-ABCDEFG.md",
+            ],
+        );
+        assert_eq!(
+            found,
+            [
+                "agents/review.sync-conflict-20250128-101500-ABCDEFG.md",
+                "settings.sync-conflict-20250128-101500-ABCDEFG.json",
+            ]
+        );
+    }
+
+    #[test]
+    fn find_conflicts_ignores_other_providers_names() {
+        let found = conflicts(
+            ProviderKind::Directory,
+            &["settings 2.json", "settings (1).json", "settings-MacBook.json"],
+        );
+        assert!(found.is_empty());
+    }
+}
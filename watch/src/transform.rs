@@ -0,0 +1,337 @@
+//! Conversion between the local and shared forms of a synced file
+//!
+//! The copy in the sync directory is the "shared" form. The local copy may
+//! differ from it on purpose (home directory paths, machine-specific
+//! overrides), so change detection and copy verification compare shared
+//! forms: the local file is converted to what it would look like once pushed,
+//! and that is what gets hashed.
+//!
+//! Conversions return the existing bytes of the target whenever the content is
+//! semantically unchanged, so formatting differences never look like edits.
+
+use crate::config::Config;
+use crate::overrides::{self, Overrides};
+use crate::secrets::Secrets;
+use crate::state::{FileState, SyncState};
+use anyhow::Result;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::Path;
+
+/// Local <-> shared content conversions for this machine
+pub struct Transforms {
+    overrides: Overrides,
+    /// Local home directory, rewritten to `${HOME}` in shared content
+    home: Option<String>,
+    /// Extensions of text files whose home paths are rewritten
+    path_rewrite_extensions: Vec<String>,
+    /// Secret scanning, and redaction of secrets in shared content
+    secrets: Secrets,
+}
+
+/// Placeholder for the home directory in shared content
+const HOME_PLACEHOLDER: &str = "${HOME}";
+
+impl Transforms {
+    /// Load the transforms configured for this machine
+    pub fn load(config: &Config) -> Result<Self> {
+        let overrides = Overrides::load(&config.local_overrides_path())?;
+        if overrides.len() > 0 {
+            log::info!("Loaded machine overrides for {} file(s)", overrides.len());
+        }
+
+        let home = dirs::home_dir()
+            .map(|h| h.to_string_lossy().trim_end_matches('/').to_string())
+            .filter(|h| !h.is_empty());
+
+        Ok(Self {
+            overrides,
+            home,
+            path_rewrite_extensions: config.path_rewrite_extensions.clone(),
+            secrets: Secrets::load(config)?,
+        })
+    }
+
+    /// Check if local and shared forms of a file are always identical
+    pub fn is_identity(&self, rel_path: &str) -> bool {
+        self.overrides.for_file(rel_path).is_none() && !self.rewrites_paths(rel_path) && !self.secrets.redacts()
+    }
+
+    /// Check the shared form of a file before it is pushed: fails if it
+    /// contains secrets and `secrets.mode` is "block"
+    pub fn check_secrets(&self, rel_path: &str, shared: &[u8]) -> Result<()> {
+        self.secrets.check(rel_path, shared)
+    }
+
+    /// Locations of secrets in the shared form of a file
+    pub fn find_secrets(&self, rel_path: &str, shared: &[u8]) -> Vec<String> {
+        self.secrets.scan(rel_path, shared)
+    }
+
+    /// Check if home directory paths are rewritten in this file
+    fn rewrites_paths(&self, rel_path: &str) -> bool {
+        self.home.is_some()
+            && Path::new(rel_path)
+                .extension()
+                .is_some_and(|ext| self.path_rewrite_extensions.iter().any(|e| ext == e.as_str()))
+    }
+
+    /// Convert local content to its shared form (push)
+    ///
+    /// `current_shared` is the content currently in the sync directory, used
+    /// to restore the shared values of overridden keys.
+    pub fn to_shared(&self, rel_path: &str, local: &[u8], current_shared: Option<&[u8]>) -> Vec<u8> {
+        let normalized = match (&self.home, self.rewrites_paths(rel_path)) {
+            (Some(home), true) => normalize_home(local, home),
+            _ => local.to_vec(),
+        };
+
+        let stripped = self.strip_overrides(rel_path, normalized, current_shared);
+        self.secrets.redact(rel_path, stripped)
+    }
+
+    /// Convert shared content to its local form (pull)
+    ///
+    /// `current_local` is the existing local content, returned unchanged if it
+    /// already matches.
+    pub fn to_local(&self, rel_path: &str, shared: &[u8], current_local: Option<&[u8]>) -> Vec<u8> {
+        let injected = self.secrets.inject(rel_path, shared.to_vec());
+        let expanded = match (&self.home, self.rewrites_paths(rel_path)) {
+            (Some(home), true) => expand_home(&injected, home),
+            _ => injected,
+        };
+
+        self.apply_overrides(rel_path, expanded, current_local)
+    }
+
+    /// Put overridden keys back to their shared values
+    fn strip_overrides(&self, rel_path: &str, local: Vec<u8>, current_shared: Option<&[u8]>) -> Vec<u8> {
+        let Some(overlay) = self.overrides.for_file(rel_path) else {
+            return local;
+        };
+        let Ok(local_json) = serde_json::from_slice::<Value>(&local) else {
+            return local;
+        };
+
+        let shared_json = current_shared.and_then(|s| serde_json::from_slice::<Value>(s).ok());
+        let stripped = overrides::strip(&local_json, overlay, shared_json.as_ref());
+
+        if stripped == local_json {
+            return local;
+        }
+        if let (Some(shared), Some(shared_json)) = (current_shared, &shared_json) {
+            if &stripped == shared_json {
+                return shared.to_vec();
+            }
+        }
+
+        to_json_bytes(&stripped, &local)
+    }
+
+    /// Deep-merge this machine's overrides over shared content
+    fn apply_overrides(&self, rel_path: &str, shared: Vec<u8>, current_local: Option<&[u8]>) -> Vec<u8> {
+        let Some(overlay) = self.overrides.for_file(rel_path) else {
+            return shared;
+        };
+        let Ok(shared_json) = serde_json::from_slice::<Value>(&shared) else {
+            return shared;
+        };
+
+        let applied = overrides::apply(&shared_json, overlay);
+
+        if let Some(local) = current_local {
+            if serde_json::from_slice::<Value>(local).ok().as_ref() == Some(&applied) {
+                return local.to_vec();
+            }
+        }
+
+        to_json_bytes(&applied, &shared)
+    }
+
+    /// State of a local file as seen from the sync directory: the checksum
+    /// and size are those of its shared form
+    ///
+    /// `current_shared` reads the remote content; it is only called for files
+    /// with overrides.
+    pub fn local_state(
+        &self,
+        rel_path: &str,
+        local_path: &Path,
+        current_shared: impl FnOnce() -> Option<Vec<u8>>,
+    ) -> Option<FileState> {
+        let file_state = SyncState::current_file_state(local_path)?;
+        if self.is_identity(rel_path) {
+            return Some(file_state);
+        }
+
+        let local = fs::read(local_path).ok()?;
+        let current_shared = match self.overrides.for_file(rel_path) {
+            Some(_) => current_shared(),
+            None => None,
+        };
+        let shared = self.to_shared(rel_path, &local, current_shared.as_deref());
+
+        Some(FileState {
+            sha256: format!("{:x}", Sha256::digest(&shared)),
+            size: shared.len() as u64,
+            ..file_state
+        })
+    }
+}
+
+/// Replace the home directory prefix of absolute paths with `${HOME}`
+///
+/// Only whole paths match: with home `/Users/al`, `/Users/al/x` is rewritten
+/// but `/Users/alice` and `/mnt/Users/al` are not. A literal `${HOME}` already
+/// in the content gets an extra `$` so that `expand_home` restores it.
+/// Non-UTF-8 content is left alone.
+fn normalize_home(content: &[u8], home: &str) -> Vec<u8> {
+    let Ok(text) = std::str::from_utf8(content) else {
+        return content.to_vec();
+    };
+    let text = replace_placeholders(text, |dollars| format!("{}{{HOME}}", "$".repeat(dollars + 1)));
+
+    let mut out = String::with_capacity(text.len());
+    let mut start = 0;
+    for (pos, _) in text.match_indices(home) {
+        let before = &text[..pos];
+        let starts_path = before.ends_with("file://") || before.chars().next_back().is_none_or(|c| !is_path_char(c));
+        let ends_component = text[pos + home.len()..]
+            .chars()
+            .next()
+            .is_none_or(|c| !(c.is_alphanumeric() || matches!(c, '.' | '_' | '-')));
+
+        if starts_path && ends_component {
+            out.push_str(&text[start..pos]);
+            out.push_str(HOME_PLACEHOLDER);
+            start = pos + home.len();
+        }
+    }
+    out.push_str(&text[start..]);
+
+    out.into_bytes()
+}
+
+/// Replace `${HOME}` with the local home directory, and drop the `$` that
+/// `normalize_home` added to literal ones
+fn expand_home(content: &[u8], home: &str) -> Vec<u8> {
+    match std::str::from_utf8(content) {
+        Ok(text) => replace_placeholders(text, |dollars| match dollars {
+            1 => home.to_string(),
+            _ => format!("{}{{HOME}}", "$".repeat(dollars - 1)),
+        })
+        .into_bytes(),
+        Err(_) => content.to_vec(),
+    }
+}
+
+/// Replace each `{HOME}` preceded by one or more `$` (and those `$`) with
+/// `replace(number of $)`
+fn replace_placeholders(text: &str, replace: impl Fn(usize) -> String) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(pos) = rest.find("{HOME}") {
+        let before = &rest[..pos];
+        let unescaped = before.trim_end_matches('$');
+        let dollars = before.len() - unescaped.len();
+
+        if dollars == 0 {
+            out.push_str(&rest[..pos + "{HOME}".len()]);
+        } else {
+            out.push_str(unescaped);
+            out.push_str(&replace(dollars));
+        }
+        rest = &rest[pos + "{HOME}".len()..];
+    }
+    out.push_str(rest);
+    out
+}
+
+/// Check if `c` can be part of a path, so a home directory path right after
+/// it is really the tail of a longer word or path
+fn is_path_char(c: char) -> bool {
+    c.is_alphanumeric() || matches!(c, '.' | '_' | '-' | '/' | '\\' | '~' | '$' | '@' | '+' | '%')
+}
+
+/// Serialize JSON the way Claude Code writes it, keeping the trailing newline
+/// convention of `like`
+fn to_json_bytes(value: &Value, like: &[u8]) -> Vec<u8> {
+    let mut bytes = serde_json::to_vec_pretty(value).unwrap_or_default();
+    if like.ends_with(b"\n") {
+        bytes.push(b'\n');
+    }
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HOME: &str = "/Users/al";
+
+    fn normalize(text: &str) -> String {
+        String::from_utf8(normalize_home(text.as_bytes(), HOME)).unwrap()
+    }
+
+    fn expand(text: &str) -> String {
+        String::from_utf8(expand_home(text.as_bytes(), HOME)).unwrap()
+    }
+
+    #[test]
+    fn normalize_rewrites_whole_paths() {
+        for (local, shared) in [
+            ("/Users/al", "${HOME}"),
+            ("/Users/al/.claude/hooks/lint.sh", "${HOME}/.claude/hooks/lint.sh"),
+            ("\"command\": \"/Users/al/bin/x\"", "\"command\": \"${HOME}/bin/x\""),
+            ("'/Users/al/x'", "'${HOME}/x'"),
+            ("cd /Users/al && ls\n\t/Users/al", "cd ${HOME} && ls\n\t${HOME}"),
+            ("PATH=/Users/al/bin:/Users/al/.local/bin", "PATH=${HOME}/bin:${HOME}/.local/bin"),
+            ("[/Users/al/x](file:///Users/al/x)", "[${HOME}/x](file://${HOME}/x)"),
+        ] {
+            assert_eq!(normalize(local), shared, "{}", local);
+        }
+    }
+
+    #[test]
+    fn normalize_leaves_other_paths() {
+        for local in [
+            "/Users/alice/x",
+            "/Users/al.old",
+            "/mnt/Users/al/x",
+            "backup/Users/al",
+            "~/Users/al",
+            "$/Users/al",
+            "C:\\Users/al",
+        ] {
+            assert_eq!(normalize(local), local);
+        }
+    }
+
+    #[test]
+    fn expand_restores_local_paths() {
+        assert_eq!(expand("\"${HOME}/bin/x\" ${HOME}"), "\"/Users/al/bin/x\" /Users/al");
+        assert_eq!(expand("{HOME} $HOME ${HOMEDIR}"), "{HOME} $HOME ${HOMEDIR}");
+    }
+
+    #[test]
+    fn literal_placeholders_survive_round_trip() {
+        for local in [
+            "export X=${HOME}/bin",
+            "echo $${HOME} $$$${HOME}",
+            "${HOME}/Users/al /Users/al/${HOME}",
+            "${HOME}{HOME}",
+        ] {
+            let shared = normalize(local);
+            assert_eq!(expand(&shared), local, "{} -> {}", local, shared);
+        }
+        assert_eq!(normalize("${HOME} /Users/al"), "$${HOME} ${HOME}");
+
+        // Another machine with a different home reads its own paths
+        let shared = normalize("X=${HOME} Y=/Users/al/x");
+        assert_eq!(
+            String::from_utf8(expand_home(shared.as_bytes(), "/home/bo")).unwrap(),
+            "X=${HOME} Y=/home/bo/x"
+        );
+    }
+}
@@ -1,11 +1,14 @@
 //! File system watching with notify crate
 
 use crate::config::Config;
-use crate::sync::{SyncDirection, SyncEngine};
+use crate::encryption;
+use crate::filter::SyncSet;
+use crate::state::{ConflictSide, CONFLICT_COPY_MARKER};
+use crate::sync::{ResolvedConflict, SyncDirection, SyncEngine};
 use anyhow::Result;
 use notify::{Config as NotifyConfig, Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
-use std::collections::HashMap;
-use std::path::PathBuf;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
 use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::{Arc, Mutex};
 use std::time::{Duration, Instant};
@@ -71,6 +74,10 @@ impl ChangeBuffer {
 /// File system watcher for bidirectional sync
 pub struct SyncWatcher {
     config: Config,
+    sync_set: SyncSet,
+    /// Sync set of the watched remote folder (differs when file names are
+    /// encrypted)
+    remote_sync_set: SyncSet,
     buffer: Arc<Mutex<ChangeBuffer>>,
     tx: Sender<WatchEvent>,
     rx: Receiver<WatchEvent>,
@@ -87,9 +94,17 @@ impl SyncWatcher {
     pub fn new(config: Config) -> Result<Self> {
         let (tx, rx) = mpsc::channel();
         let buffer = Arc::new(Mutex::new(ChangeBuffer::new()));
+        let sync_set = SyncSet::new(&config);
+        let remote_sync_set = if config.encryption.enabled && config.encryption.encrypt_names {
+            SyncSet::for_dir(encryption::NAMES_DIR)
+        } else {
+            sync_set.clone()
+        };
 
         Ok(Self {
             config,
+            sync_set,
+            remote_sync_set,
             buffer,
             tx,
             rx,
@@ -98,12 +113,13 @@ impl SyncWatcher {
 
     /// Start watching and processing changes
     pub fn run(&self) -> Result<()> {
+        // Create sync engine
+        let sync_engine = SyncEngine::new(self.config.clone())?;
+        let remote = sync_engine.remote();
+
         log::info!("Starting file system watchers...");
         log::info!("  Local:   {:?}", self.config.claude_dir);
-        log::info!("  Dropbox: {:?}", self.config.dropbox_claude_dir);
-
-        // Create sync engine
-        let sync_engine = SyncEngine::new(self.config.clone());
+        log::info!("  Remote:  {} ({})", remote.location(), remote.name());
 
         // Create watchers
         let local_tx = self.tx.clone();
@@ -131,16 +147,28 @@ impl SyncWatcher {
             log::warn!("Local directory does not exist yet: {:?}", self.config.claude_dir);
         }
 
-        if self.config.dropbox_claude_dir.exists() {
-            dropbox_watcher.watch(&self.config.dropbox_claude_dir, RecursiveMode::Recursive)?;
-            log::info!("Watching Dropbox directory");
-        } else {
-            log::warn!("Dropbox directory does not exist yet: {:?}", self.config.dropbox_claude_dir);
+        match remote.local_dir() {
+            Some(dir) if dir.exists() => {
+                dropbox_watcher.watch(dir, RecursiveMode::Recursive)?;
+                log::info!("Watching {} directory", remote.name());
+            }
+            Some(dir) => {
+                log::warn!("{} directory does not exist yet: {:?}", remote.name(), dir);
+            }
+            None => {}
         }
 
-        log::info!("Watchers started. Waiting for changes...");
+        log::info!("Watchers started");
+
+        // Catch up on changes made while the daemon wasn't running
+        log::info!("Running initial full sync");
+        self.run_sync(&sync_engine, SyncDirection::Bidirectional, None);
+        log::info!("Waiting for changes...");
 
         // Process events
+        let mut last_poll = Instant::now();
+        let mut last_full = Instant::now();
+        let full_sync = Duration::from_secs_f64(self.config.full_sync_secs);
         loop {
             // Check for new events (non-blocking with timeout)
             match self.rx.recv_timeout(Duration::from_millis(100)) {
@@ -150,6 +178,12 @@ impl SyncWatcher {
                         continue;
                     }
 
+                    // Skip files the last sync wrote itself
+                    if sync_engine.is_self_write(&path) {
+                        log::debug!("Ignoring own write: {:?}", path);
+                        continue;
+                    }
+
                     log::debug!(
                         "Change detected: {:?} ({})",
                         path,
@@ -196,24 +230,41 @@ impl SyncWatcher {
                         _ => SyncDirection::Bidirectional,
                     };
 
-                    // Perform sync
-                    match sync_engine.sync(direction) {
-                        Ok(result) => {
-                            log::info!(
-                                "Sync complete: {} copied, {} skipped",
-                                result.copied,
-                                result.skipped
-                            );
-                            for warning in &result.warnings {
-                                log::warn!("{}", warning);
-                            }
-                        }
-                        Err(e) => {
-                            log::error!("Sync failed: {}", e);
+                    // Only look at the reported paths, unless a full scan is
+                    // due (every full_sync_secs)
+                    if last_full.elapsed() >= full_sync {
+                        last_full = Instant::now();
+                        self.run_sync(&sync_engine, SyncDirection::Bidirectional, None);
+                    } else {
+                        let dirty: Option<BTreeSet<String>> =
+                            changes.iter().map(|(path, _)| self.rel_path(path)).collect();
+                        self.run_sync(&sync_engine, direction, dirty.as_ref());
+                    }
+                }
+            }
+
+            // Git remotes and network backends don't produce file events;
+            // check them periodically
+            if let Some(poll_secs) = self.config.poll_secs() {
+                if last_poll.elapsed() >= Duration::from_secs_f64(poll_secs) {
+                    last_poll = Instant::now();
+                    match sync_engine.remote_has_updates() {
+                        Ok(true) => {
+                            log::info!("{} remote has new changes", remote.name());
+                            self.run_sync(&sync_engine, SyncDirection::Pull, None);
                         }
+                        Ok(false) => {}
+                        Err(e) => log::warn!("Failed to check {} remote: {:#}", remote.name(), e),
                     }
                 }
             }
+
+            // Rescan everything now and then, in case a watcher missed an event
+            if last_full.elapsed() >= full_sync {
+                last_full = Instant::now();
+                log::info!("Running periodic full sync");
+                self.run_sync(&sync_engine, SyncDirection::Bidirectional, None);
+            }
         }
 
         Ok(())
@@ -252,13 +303,11 @@ impl SyncWatcher {
             .map(|s| s.to_string_lossy())
             .unwrap_or_default();
 
-        // Ignore hidden files
-        if file_name.starts_with('.') {
-            return true;
-        }
-
         // Ignore sync state and lock files (both old and new names)
-        if file_name == ".sync_state.json" || file_name == ".sync_lock" || file_name == ".sync.pid" {
+        if matches!(
+            file_name.as_ref(),
+            ".sync_state.json" | ".sync_lock" | ".sync.pid" | ".sync.lock"
+        ) {
             return true;
         }
 
@@ -267,9 +316,15 @@ impl SyncWatcher {
             return true;
         }
 
-        // Ignore Dropbox conflict files (they should be handled manually)
-        if file_name.contains("conflicted copy") {
-            log::warn!("Dropbox conflict detected: {:?}", path);
+        // Ignore our own conflict copies (kept local for manual resolution)
+        if file_name.contains(CONFLICT_COPY_MARKER) {
+            return true;
+        }
+
+        // Ignore provider conflict copies (they should be handled manually)
+        let remote_dir = self.config.target.local_dir();
+        if remote_dir.is_some_and(|dir| path.starts_with(dir)) && self.config.target.conflict_original(path).is_some() {
+            log::warn!("{} conflict detected: {:?}", self.config.target.name(), path);
             return true;
         }
 
@@ -278,14 +333,74 @@ impl SyncWatcher {
             return true;
         }
 
-        false
+        // Ignore anything outside the sync set (hidden files, sync.exclude,
+        // .claudesyncignore)
+        if let Ok(rel_path) = path.strip_prefix(&self.config.claude_dir) {
+            return !self.sync_set.is_synced(&self.config.claude_dir, &rel_path.to_string_lossy(), path.is_dir());
+        }
+        if let Some(root) = remote_dir {
+            if let Ok(rel_path) = path.strip_prefix(root) {
+                return !self.remote_sync_set.is_synced(root, &rel_path.to_string_lossy(), path.is_dir());
+            }
+        }
+
+        // Outside both trees: ignore hidden files
+        file_name.starts_with('.')
+    }
+
+    /// Run a sync from the watch loop and log the outcome
+    ///
+    /// Files still being written are queued again, so they are synced with
+    /// the next batch once they have settled.
+    fn run_sync(&self, sync_engine: &SyncEngine, direction: SyncDirection, dirty: Option<&BTreeSet<String>>) {
+        match sync_engine.sync(direction, dirty) {
+            Ok(result) => {
+                log::info!(
+                    "Sync complete: {} copied, {} deleted, {} skipped",
+                    result.copied,
+                    result.deleted,
+                    result.skipped
+                );
+                for conflict in &result.conflicts {
+                    log_resolved_conflict(conflict);
+                }
+                for warning in &result.warnings {
+                    log::warn!("{}", warning);
+                }
+                if !result.deferred.is_empty() {
+                    log::info!("{} file(s) still being written, deferred to the next batch", result.deferred.len());
+                    let mut buffer = self.buffer.lock().unwrap();
+                    for (rel_path, source) in result.deferred {
+                        buffer.add(self.config.claude_dir.join(rel_path), source == ConflictSide::Local);
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("Sync failed: {}", e);
+            }
+        }
+    }
+
+    /// Relative path of a changed file in either watched directory (None if
+    /// it can't be told, e.g. file names are encrypted in the remote folder)
+    fn rel_path(&self, path: &Path) -> Option<String> {
+        if let Ok(rel_path) = path.strip_prefix(&self.config.claude_dir) {
+            return Some(rel_path.to_string_lossy().to_string());
+        }
+        if self.config.encryption.enabled && self.config.encryption.encrypt_names {
+            return None;
+        }
+        let root = self.config.target.local_dir()?;
+        path.strip_prefix(root)
+            .ok()
+            .map(|rel_path| rel_path.to_string_lossy().to_string())
     }
 
     /// Perform a single sync pass without watching
     pub fn sync_once(&self) -> Result<()> {
         log::info!("Performing one-time sync...");
 
-        let sync_engine = SyncEngine::new(self.config.clone());
+        let sync_engine = SyncEngine::new(self.config.clone())?;
 
         // Validate sources first
         let errors = sync_engine.validate_sources(SyncDirection::Bidirectional)?;
@@ -297,22 +412,65 @@ impl SyncWatcher {
         }
 
         // Perform bidirectional sync
-        let result = sync_engine.sync(SyncDirection::Bidirectional)?;
+        let result = sync_engine.sync(SyncDirection::Bidirectional, None)?;
 
         log::info!(
-            "Sync complete: {} copied, {} skipped",
+            "Sync complete: {} copied, {} deleted, {} skipped",
             result.copied,
+            result.deleted,
             result.skipped
         );
 
-        if let Some(backup) = &result.backup_path {
-            log::info!("Backup created: {:?}", backup);
+        if let Some(backup) = &result.backup_id {
+            log::info!("Backup created: {}", backup);
+        }
+
+        for conflict in &result.conflicts {
+            log_resolved_conflict(conflict);
         }
 
         for warning in &result.warnings {
             log::warn!("{}", warning);
         }
 
+        for (rel_path, _) in &result.deferred {
+            log::warn!("Not synced, still being written: {}", rel_path);
+        }
+
         Ok(())
     }
 }
+
+/// Log a conflict resolved during sync
+fn log_resolved_conflict(conflict: &ResolvedConflict) {
+    if conflict.merged {
+        if conflict.keys.is_empty() {
+            log::info!("Merged changes from both sides into {}", conflict.rel_path);
+        } else {
+            log::warn!(
+                "Merged {} ({:?}): kept {:?} value for {}",
+                conflict.rel_path,
+                conflict.strategy,
+                conflict.winner,
+                conflict.keys.join(", ")
+            );
+        }
+        return;
+    }
+
+    match &conflict.conflict_copy {
+        Some(copy) => log::warn!(
+            "Conflict resolved ({:?}): {} kept {:?}, other side saved to {:?}",
+            conflict.strategy,
+            conflict.rel_path,
+            conflict.winner,
+            copy
+        ),
+        None => log::warn!(
+            "Conflict resolved ({:?}): {} kept {:?}",
+            conflict.strategy,
+            conflict.rel_path,
+            conflict.winner
+        ),
+    }
+}
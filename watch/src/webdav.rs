@@ -0,0 +1,678 @@
+//! WebDAV backend (Nextcloud, ownCloud, Apache mod_dav, ...)
+//!
+//! Files are stored under `<webdav.url>/<paths.folder>/`, which is listed
+//! with PROPFIND one collection at a time (many servers refuse
+//! `Depth: infinity`). Like an S3 listing, PROPFIND only returns ETags; the
+//! checksum of a version we haven't seen yet is computed by downloading it,
+//! and cached in ~/.claude/.sync_webdav_index.json (see
+//! `remote::VersionIndex`). Permission bits are stored in a custom property.
+//!
+//! Uploads and deletes are conditional on the ETag the engine last saw
+//! (`If-Match`, or `If-None-Match: *` for new files), so a change another
+//! machine made in the meantime is never overwritten. The daemon polls the
+//! server every `webdav.poll_secs`.
+
+use crate::config::Config;
+use crate::filter::SyncSet;
+use crate::remote::{Remote, RemoteFile, VersionIndex};
+use crate::s3::uri_encode;
+use crate::state::FileState;
+use anyhow::{anyhow, bail, Context, Result};
+use base64::prelude::{Engine, BASE64_STANDARD};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::io::Read;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// XML namespace of our own properties
+const NAMESPACE: &str = "urn:claude-sync";
+
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:" xmlns:cs="urn:claude-sync">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getetag/>
+    <d:getcontentlength/>
+    <d:getlastmodified/>
+    <cs:mode/>
+  </d:prop>
+</d:propfind>"#;
+
+/// A file or collection from a PROPFIND response
+struct Entry {
+    /// Path relative to the sync directory
+    rel_path: String,
+    is_dir: bool,
+    etag: Option<String>,
+    size: u64,
+    mtime: i64,
+    mode: Option<u32>,
+}
+
+/// Outcome of a request that reached the server
+enum Reply {
+    Ok(Box<ureq::Response>),
+    Status(u16),
+}
+
+/// Shared copy on a WebDAV server
+pub struct WebdavRemote {
+    agent: ureq::Agent,
+    /// URL of the sync directory, with a trailing `/`
+    base_url: String,
+    /// Decoded path of `base_url`, which hrefs in PROPFIND responses start
+    /// with
+    base_path: String,
+    /// `Authorization` header, if credentials are configured
+    authorization: Option<String>,
+    /// Local config directory, whose ignore files decide what is synced
+    claude_dir: PathBuf,
+    /// Last seen version of each synced file
+    index: VersionIndex,
+}
+
+impl WebdavRemote {
+    pub fn new(config: &Config) -> Result<Self> {
+        let webdav = &config.webdav;
+
+        let url = webdav.url.trim_end_matches('/');
+        let path_start = url
+            .split_once("://")
+            .filter(|(scheme, authority)| matches!(*scheme, "http" | "https") && !authority.is_empty())
+            .map(|(scheme, authority)| scheme.len() + 3 + authority.find('/').unwrap_or(authority.len()))
+            .with_context(|| format!("webdav.url must be an http(s) URL: {}", webdav.url))?;
+        let base_url = format!("{}/{}/", url, uri_encode(&config.folder, false));
+        let base_path = percent_decode(&base_url[path_start..]);
+
+        let authorization = match &webdav.username {
+            Some(username) => {
+                let password = webdav
+                    .password
+                    .clone()
+                    .or_else(|| std::env::var("CLAUDE_SYNC_WEBDAV_PASSWORD").ok())
+                    .context("WebDAV password not set (webdav.password or CLAUDE_SYNC_WEBDAV_PASSWORD)")?;
+                Some(format!(
+                    "Basic {}",
+                    BASE64_STANDARD.encode(format!("{}:{}", username, password))
+                ))
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(10))
+                .timeout(Duration::from_secs(120))
+                .build(),
+            base_url,
+            base_path,
+            authorization,
+            claude_dir: config.claude_dir.clone(),
+            index: VersionIndex::load(config.local_webdav_index_path()),
+        })
+    }
+
+    fn url(&self, rel_path: &str) -> String {
+        format!("{}{}", self.base_url, uri_encode(rel_path, false))
+    }
+
+    /// List every synced file under the sync directory
+    fn list_entries(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, Entry>> {
+        let mut files = BTreeMap::new();
+        let mut collections = vec![String::new()];
+
+        while let Some(dir) = collections.pop() {
+            let url = if dir.is_empty() {
+                self.base_url.clone()
+            } else {
+                format!("{}/", self.url(&dir))
+            };
+            // The sync directory doesn't exist before the first push, and a
+            // collection may be deleted after its parent was listed
+            let Some(entries) = self.propfind(&url, "1")? else {
+                continue;
+            };
+
+            for entry in entries {
+                if entry.rel_path == dir {
+                    continue;
+                }
+                if entry.is_dir {
+                    if sync_set.is_synced(&self.claude_dir, &entry.rel_path, true) {
+                        collections.push(entry.rel_path);
+                    }
+                } else if sync_set.is_synced(&self.claude_dir, &entry.rel_path, false) {
+                    files.insert(entry.rel_path.clone(), entry);
+                }
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Properties of a resource and, with `depth` 1, its members (None if
+    /// it doesn't exist)
+    fn propfind(&self, url: &str, depth: &str) -> Result<Option<Vec<Entry>>> {
+        let headers = vec![
+            ("Depth", depth.to_string()),
+            ("Content-Type", "application/xml; charset=utf-8".to_string()),
+        ];
+        let body = match self.send("PROPFIND", url, headers, Some(PROPFIND_BODY.as_bytes()))? {
+            Reply::Ok(response) => read_string(*response)?,
+            Reply::Status(404) => return Ok(None),
+            Reply::Status(status) => bail!("WebDAV PROPFIND {} failed: {}", url, status),
+        };
+        let doc = roxmltree::Document::parse(&body)
+            .with_context(|| format!("Invalid PROPFIND response for {}", url))?;
+
+        let mut entries = Vec::new();
+        for response in doc.descendants().filter(|n| n.has_tag_name(("DAV:", "response"))) {
+            let Some(rel_path) = dav_text(response, "href").and_then(|href| self.rel_path(href)) else {
+                continue;
+            };
+
+            // Properties the server has (others come back with a 404 status)
+            let props: Vec<roxmltree::Node> = response
+                .children()
+                .filter(|n| n.has_tag_name(("DAV:", "propstat")))
+                .filter(|n| dav_text(*n, "status").is_some_and(|s| s.contains(" 200 ")))
+                .filter_map(|n| n.children().find(|c| c.has_tag_name(("DAV:", "prop"))))
+                .flat_map(|prop| prop.children().filter(|c| c.is_element()))
+                .collect();
+            let prop = |namespace: &str, name: &str| {
+                props
+                    .iter()
+                    .find(|p| p.has_tag_name((namespace, name)))
+                    .map(|p| p.text().unwrap_or_default().trim())
+            };
+
+            let is_dir = props.iter().any(|p| {
+                p.has_tag_name(("DAV:", "resourcetype"))
+                    && p.children().any(|c| c.has_tag_name(("DAV:", "collection")))
+            });
+            entries.push(Entry {
+                rel_path,
+                is_dir,
+                etag: prop("DAV:", "getetag").filter(|e| !e.is_empty()).map(|e| e.to_string()),
+                size: prop("DAV:", "getcontentlength")
+                    .and_then(|s| s.parse().ok())
+                    .unwrap_or(0),
+                mtime: prop("DAV:", "getlastmodified")
+                    .and_then(|t| DateTime::parse_from_rfc2822(t).ok())
+                    .map_or(0, |t| t.timestamp()),
+                mode: prop(NAMESPACE, "mode").and_then(|m| u32::from_str_radix(m, 8).ok()),
+            });
+        }
+
+        Ok(Some(entries))
+    }
+
+    /// Path relative to the sync directory of an href in a PROPFIND
+    /// response (a full URL or an absolute path)
+    fn rel_path(&self, href: &str) -> Option<String> {
+        let path = match href.split_once("://") {
+            Some((_, rest)) => &rest[rest.find('/')?..],
+            None => href,
+        };
+        let path = percent_decode(path);
+        let rel_path = path.strip_prefix(&self.base_path).or_else(|| {
+            // The sync directory itself, without its trailing slash
+            (path == self.base_path.trim_end_matches('/')).then_some("")
+        })?;
+        Some(rel_path.trim_end_matches('/').to_string())
+    }
+
+    /// Turn a listed file into a `RemoteFile`, downloading it if we haven't
+    /// seen this version yet (None if it was deleted since the listing)
+    fn remote_file(&self, entry: Entry) -> Result<Option<RemoteFile>> {
+        let cached = entry
+            .etag
+            .as_deref()
+            .and_then(|etag| self.index.get(&entry.rel_path, etag));
+        let sha256 = match cached {
+            Some(cached) => cached.sha256,
+            None => match self.get(&entry.rel_path)? {
+                Some(content) => format!("{:x}", Sha256::digest(content)),
+                None => return Ok(None),
+            },
+        };
+
+        Ok(Some(RemoteFile {
+            state: FileState {
+                sha256,
+                mtime: entry.mtime,
+                size: entry.size,
+            },
+            version: entry.etag,
+            mode: entry.mode,
+        }))
+    }
+
+    /// Content of a file, None if it doesn't exist
+    fn get(&self, rel_path: &str) -> Result<Option<Vec<u8>>> {
+        let url = self.url(rel_path);
+        match self.send("GET", &url, Vec::new(), None)? {
+            Reply::Ok(response) => {
+                let mut content = Vec::new();
+                response
+                    .into_reader()
+                    .read_to_end(&mut content)
+                    .with_context(|| format!("Failed to download {}", url))?;
+                Ok(Some(content))
+            }
+            Reply::Status(404) => Ok(None),
+            Reply::Status(status) => bail!("WebDAV GET {} failed: {}", url, status),
+        }
+    }
+
+    /// Create the sync directory and the parent collections of a file
+    fn create_collections(&self, rel_path: &str) -> Result<()> {
+        let mut url = self.base_url.clone();
+        self.mkcol(&url)?;
+        if let Some((parents, _)) = rel_path.rsplit_once('/') {
+            for name in parents.split('/') {
+                url = format!("{}{}/", url, uri_encode(name, true));
+                self.mkcol(&url)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn mkcol(&self, url: &str) -> Result<()> {
+        match self.send("MKCOL", url, Vec::new(), None)? {
+            // Created, or already there
+            Reply::Ok(_) | Reply::Status(405) => Ok(()),
+            Reply::Status(status) => bail!("WebDAV MKCOL {} failed: {}", url, status),
+        }
+    }
+
+    /// Store the permission bits of a file in our custom property
+    fn set_mode(&self, rel_path: &str, mode: u32) -> Result<()> {
+        let url = self.url(rel_path);
+        let body = format!(
+            r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propertyupdate xmlns:d="DAV:" xmlns:cs="{}">
+  <d:set><d:prop><cs:mode>{:o}</cs:mode></d:prop></d:set>
+</d:propertyupdate>"#,
+            NAMESPACE, mode
+        );
+        let headers = vec![("Content-Type", "application/xml; charset=utf-8".to_string())];
+        match self.send("PROPPATCH", &url, headers, Some(body.as_bytes()))? {
+            Reply::Ok(_) => Ok(()),
+            Reply::Status(status) => bail!("WebDAV PROPPATCH {} failed: {}", url, status),
+        }
+    }
+
+    fn send(
+        &self,
+        method: &str,
+        url: &str,
+        headers: Vec<(&str, String)>,
+        body: Option<&[u8]>,
+    ) -> Result<Reply> {
+        let mut request = self.agent.request(method, url);
+        if let Some(authorization) = &self.authorization {
+            request = request.set("Authorization", authorization);
+        }
+        for (name, value) in &headers {
+            request = request.set(name, value);
+        }
+
+        let result = match body {
+            Some(body) => request.send_bytes(body),
+            None => request.call(),
+        };
+        match result {
+            Ok(response) => Ok(Reply::Ok(Box::new(response))),
+            Err(ureq::Error::Status(status, _)) => Ok(Reply::Status(status)),
+            Err(e) => Err(anyhow!("WebDAV request to {} failed: {}", url, e)),
+        }
+    }
+}
+
+impl Remote for WebdavRemote {
+    fn name(&self) -> &str {
+        "WebDAV"
+    }
+
+    fn location(&self) -> String {
+        self.base_url.clone()
+    }
+
+    fn list(&self, sync_set: &SyncSet) -> Result<BTreeMap<String, RemoteFile>> {
+        let mut files = BTreeMap::new();
+        for (rel_path, entry) in self.list_entries(sync_set)? {
+            if let Some(file) = self.remote_file(entry)? {
+                files.insert(rel_path, file);
+            }
+        }
+
+        self.index.replace(&files);
+        Ok(files)
+    }
+
+    fn stat(&self, rel_path: &str) -> Result<Option<RemoteFile>> {
+        let entry = self
+            .propfind(&self.url(rel_path), "0")?
+            .into_iter()
+            .flatten()
+            .find(|entry| !entry.is_dir);
+        match entry {
+            Some(entry) => self.remote_file(entry),
+            None => Ok(None),
+        }
+    }
+
+    fn read(&self, rel_path: &str) -> Result<Vec<u8>> {
+        self.get(rel_path)?
+            .with_context(|| format!("{} does not exist on the WebDAV server", rel_path))
+    }
+
+    fn write(
+        &self,
+        rel_path: &str,
+        content: &[u8],
+        mode: Option<u32>,
+        expected: Option<&RemoteFile>,
+    ) -> Result<RemoteFile> {
+        let url = self.url(rel_path);
+
+        let mut headers = vec![("Content-Type", "application/octet-stream".to_string())];
+        match expected {
+            Some(expected) => {
+                if let Some(etag) = &expected.version {
+                    headers.push(("If-Match", etag.clone()));
+                }
+            }
+            None => headers.push(("If-None-Match", "*".to_string())),
+        }
+
+        let mut reply = self.send("PUT", &url, headers.clone(), Some(content))?;
+        if let Reply::Status(409) = reply {
+            // Parent collection missing
+            self.create_collections(rel_path)?;
+            reply = self.send("PUT", &url, headers, Some(content))?;
+        }
+        let etag = match reply {
+            Reply::Ok(response) => response.header("etag").map(|e| e.to_string()),
+            Reply::Status(412) => {
+                bail!("{} was changed on the WebDAV server by another machine since it was read", rel_path)
+            }
+            Reply::Status(status) => bail!("WebDAV PUT {} failed: {}", url, status),
+        };
+
+        if let Some(mode) = mode {
+            if let Err(e) = self.set_mode(rel_path, mode) {
+                log::debug!("Permissions of {} not stored: {}", rel_path, e);
+            }
+        }
+
+        // Not every server returns the new ETag
+        let etag = match etag {
+            Some(etag) => Some(etag),
+            None => self
+                .propfind(&url, "0")?
+                .into_iter()
+                .flatten()
+                .find_map(|entry| entry.etag),
+        };
+
+        let file = RemoteFile {
+            state: FileState {
+                sha256: format!("{:x}", Sha256::digest(content)),
+                mtime: Utc::now().timestamp(),
+                size: content.len() as u64,
+            },
+            version: etag,
+            mode,
+        };
+        self.index.remember(rel_path, &file);
+        Ok(file)
+    }
+
+    fn delete(&self, rel_path: &str, expected: &RemoteFile) -> Result<()> {
+        let url = self.url(rel_path);
+        let headers = expected
+            .version
+            .iter()
+            .map(|etag| ("If-Match", etag.clone()))
+            .collect();
+
+        match self.send("DELETE", &url, headers, None)? {
+            // Gone either way
+            Reply::Ok(_) | Reply::Status(404) => {}
+            Reply::Status(412) => {
+                bail!("{} was changed on the WebDAV server by another machine since it was read", rel_path)
+            }
+            Reply::Status(status) => bail!("WebDAV DELETE {} failed: {}", url, status),
+        }
+
+        self.index.forget(rel_path);
+        Ok(())
+    }
+
+    fn has_changes(&self, sync_set: &SyncSet) -> Result<bool> {
+        let listed: HashMap<String, String> = self
+            .list_entries(sync_set)?
+            .into_iter()
+            .map(|(rel_path, entry)| (rel_path, entry.etag.unwrap_or_default()))
+            .collect();
+        Ok(self.index.differs(&listed))
+    }
+}
+
+/// Text of the first `DAV:` child element named `name`
+fn dav_text<'a>(node: roxmltree::Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.children()
+        .find(|n| n.has_tag_name(("DAV:", name)))
+        .and_then(|n| n.text())
+        .map(|t| t.trim())
+}
+
+fn read_string(response: ureq::Response) -> Result<String> {
+    let mut body = String::new();
+    response.into_reader().read_to_string(&mut body)?;
+    Ok(body)
+}
+
+/// Decode `%XX` escapes in a URL path
+fn percent_decode(s: &str) -> String {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = (bytes[i] == b'%')
+            .then(|| s.get(i + 1..i + 3))
+            .flatten()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match escaped {
+            Some(b) => {
+                out.push(b);
+                i += 3;
+            }
+            None => {
+                out.push(bytes[i]);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testutil::{HttpStub, Reply, Request, TempDir};
+
+    fn remote(home: &TempDir, server: &HttpStub) -> WebdavRemote {
+        let config = Config::for_test(
+            home.path(),
+            &format!(
+                "[paths]\nprovider = \"webdav\"\nfolder = \"Claude Sync\"\n\n[webdav]\nurl = \"{}/dav/\"\n",
+                server.url
+            ),
+        );
+        WebdavRemote::new(&config).unwrap()
+    }
+
+    const MULTISTATUS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:multistatus xmlns:d="DAV:" xmlns:cs="urn:claude-sync">
+  <d:response>
+    <d:href>/dav/Claude%20Sync/</d:href>
+    <d:propstat>
+      <d:prop><d:resourcetype><d:collection/></d:resourcetype></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>http://example.com/dav/Claude%20Sync/settings.json</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:resourcetype/>
+        <d:getetag>"abc123"</d:getetag>
+        <d:getcontentlength>42</d:getcontentlength>
+        <d:getlastmodified>Tue, 28 Jan 2025 10:15:00 GMT</d:getlastmodified>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+    <d:propstat>
+      <d:prop><cs:mode/></d:prop>
+      <d:status>HTTP/1.1 404 Not Found</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/Claude%20Sync/skills/</d:href>
+    <d:propstat>
+      <d:prop><d:resourcetype><d:collection/></d:resourcetype><d:getetag/></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/Claude%20Sync/hooks/pre%20commit.sh</d:href>
+    <d:propstat>
+      <d:prop>
+        <d:getetag>W/"def"</d:getetag>
+        <d:getcontentlength>7</d:getcontentlength>
+        <cs:mode>755</cs:mode>
+      </d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+  <d:response>
+    <d:href>/dav/elsewhere.txt</d:href>
+    <d:propstat>
+      <d:prop><d:getetag>"x"</d:getetag></d:prop>
+      <d:status>HTTP/1.1 200 OK</d:status>
+    </d:propstat>
+  </d:response>
+</d:multistatus>"#;
+
+    #[test]
+    fn propfind_parses_multistatus() {
+        let home = TempDir::new();
+        let server = HttpStub::new(|_| (207, Vec::new(), MULTISTATUS.to_string()));
+        let remote = remote(&home, &server);
+
+        let entries = remote.propfind(&remote.base_url, "1").unwrap().unwrap();
+        let request = &server.requests()[0];
+        assert_eq!((request.method.as_str(), request.path.as_str()), ("PROPFIND", "/dav/Claude%20Sync/"));
+        assert_eq!(request.header("Depth"), Some("1"));
+
+        let paths: Vec<&str> = entries.iter().map(|e| e.rel_path.as_str()).collect();
+        assert_eq!(paths, ["", "settings.json", "skills", "hooks/pre commit.sh"]);
+
+        let root = &entries[0];
+        assert!(root.is_dir);
+
+        let settings = &entries[1];
+        assert!(!settings.is_dir);
+        assert_eq!(settings.etag.as_deref(), Some("\"abc123\""));
+        assert_eq!(settings.size, 42);
+        assert_eq!(settings.mtime, 1738059300);
+        assert_eq!(settings.mode, None);
+
+        let skills = &entries[2];
+        assert!(skills.is_dir);
+        assert_eq!(skills.etag, None);
+
+        let hook = &entries[3];
+        assert_eq!(hook.etag.as_deref(), Some("W/\"def\""));
+        assert_eq!((hook.size, hook.mtime), (7, 0));
+        assert_eq!(hook.mode, Some(0o755));
+    }
+
+    #[test]
+    fn propfind_of_missing_collection_is_none() {
+        let home = TempDir::new();
+        let server = HttpStub::new(|_| (404, Vec::new(), String::new()));
+        let remote = remote(&home, &server);
+
+        assert!(remote.propfind(&remote.base_url, "1").unwrap().is_none());
+    }
+
+    /// `RemoteFile` last seen with ETag `etag`
+    fn seen(etag: &str) -> RemoteFile {
+        RemoteFile {
+            state: FileState {
+                sha256: String::new(),
+                mtime: 0,
+                size: 0,
+            },
+            version: Some(etag.to_string()),
+            mode: None,
+        }
+    }
+
+    /// Reject conditional requests whose ETag isn't `"current"`
+    fn conditional(request: &Request) -> Reply {
+        let matches = match (request.header("If-Match"), request.header("If-None-Match")) {
+            (Some(etag), _) => etag == "\"current\"",
+            (None, Some("*")) => false,
+            (None, _) => true,
+        };
+        match matches {
+            true => (201, vec![("ETag", "\"next\"".to_string())], String::new()),
+            false => (412, Vec::new(), String::new()),
+        }
+    }
+
+    #[test]
+    fn write_is_conditional_on_etag() {
+        let home = TempDir::new();
+        let server = HttpStub::new(conditional);
+        let remote = remote(&home, &server);
+
+        let written = remote.write("CLAUDE.md", b"Be brief\n", None, Some(&seen("\"current\""))).unwrap();
+        assert_eq!(written.version.as_deref(), Some("\"next\""));
+        assert_eq!(written.state.size, 9);
+
+        let e = remote
+            .write("CLAUDE.md", b"Be very brief\n", None, Some(&seen("\"stale\"")))
+            .unwrap_err();
+        assert!(e.to_string().contains("changed on the WebDAV server by another machine"));
+
+        // A file we think is new must not exist yet
+        assert!(remote.write("CLAUDE.md", b"New\n", None, None).is_err());
+
+        let requests = server.requests();
+        assert_eq!(requests.len(), 3);
+        assert!(requests.iter().all(|r| r.method == "PUT" && r.path == "/dav/Claude%20Sync/CLAUDE.md"));
+        assert_eq!(requests[0].body, b"Be brief\n");
+        assert_eq!(requests[1].header("If-Match"), Some("\"stale\""));
+        assert_eq!(requests[2].header("If-None-Match"), Some("*"));
+    }
+
+    #[test]
+    fn delete_is_conditional_on_etag() {
+        let home = TempDir::new();
+        let server = HttpStub::new(conditional);
+        let remote = remote(&home, &server);
+
+        remote.delete("CLAUDE.md", &seen("\"current\"")).unwrap();
+        let e = remote.delete("CLAUDE.md", &seen("\"stale\"")).unwrap_err();
+        assert!(e.to_string().contains("changed on the WebDAV server by another machine"));
+        assert!(server.requests().iter().all(|r| r.method == "DELETE"));
+    }
+}
@@ -367,6 +367,18 @@ claude-sync-pull() {
     fi
 }
 
+# Snapshots taken by the watch daemon live in its own store
+# (<backups.dir>/.claude_backups), so they are handled by its backups command
+_claude_sync_watch_backups() {
+    if [[ ! -x "$CLAUDE_SYNC_WATCH_BINARY" ]]; then
+        echo "\033[0;31m[ERROR]\033[0m claude-sync-watch not built: $CLAUDE_SYNC_WATCH_BINARY"
+        echo "Build it with 'claude-sync-watch build', or set CLAUDE_SYNC_WATCH_BINARY."
+        return 1
+    fi
+
+    "$CLAUDE_SYNC_WATCH_BINARY" backups "$@"
+}
+
 # ─────────────────────────────────────────────────────────────────────────────
 # claude-sync-undo: Restore previous state after a pull
 # ─────────────────────────────────────────────────────────────────────────────
@@ -382,6 +394,12 @@ claude-sync-undo() {
     local backup_path
     backup_path=$(cat "$last_backup_file")
 
+    # The watch daemon points the marker at its latest snapshot manifest
+    if [[ "$backup_path" == *.json ]]; then
+        _claude_sync_watch_backups undo
+        return
+    fi
+
     if [[ ! -d "$backup_path" ]]; then
         echo "\033[0;31m[ERROR]\033[0m Backup not found: $backup_path"
         echo "Run 'claude-sync-backups' to see available backups."
@@ -438,7 +456,17 @@ claude-sync-backups() {
     else
         echo ""
         echo "To restore a specific backup, run:"
-        echo "  rm -rf ~/.claude && cp -a <backup_path> ~/.claude"
+        echo "  claude-sync-restore <backup_path>"
+    fi
+
+    # Snapshots taken by the watch daemon
+    if [[ -x "$CLAUDE_SYNC_WATCH_BINARY" ]]; then
+        echo ""
+        echo "\033[0;36mWatch daemon snapshots:\033[0m"
+        "$CLAUDE_SYNC_WATCH_BINARY" backups list
+        echo ""
+        echo "To restore a snapshot, run:"
+        echo "  claude-sync-restore <snapshot_id>"
     fi
 }
 
@@ -449,13 +477,19 @@ claude-sync-restore() {
     local backup_path="$1"
 
     if [[ -z "$backup_path" ]]; then
-        echo "Usage: claude-sync-restore <backup_path>"
+        echo "Usage: claude-sync-restore <backup_path|snapshot_id>"
         echo ""
         echo "Available backups:"
         claude-sync-backups
         return 1
     fi
 
+    # Anything but a path is a watch daemon snapshot id
+    if [[ "$backup_path" != */* && -x "$CLAUDE_SYNC_WATCH_BINARY" ]]; then
+        _claude_sync_watch_backups restore "$backup_path"
+        return
+    fi
+
     if [[ ! -d "$backup_path" ]]; then
         echo "\033[0;31m[ERROR]\033[0m Backup not found: $backup_path"
         return 1
@@ -516,6 +550,8 @@ claude-sync-conflicts() {
 
 # Path to daemon control script (update this if installed elsewhere)
 CLAUDE_SYNC_DAEMON_SCRIPT="${CLAUDE_SYNC_DAEMON_SCRIPT:-$HOME/dev/apps/util/claude-mac-sync/claude-sync-daemon.sh}"
+# The daemon binary, built next to the daemon script
+CLAUDE_SYNC_WATCH_BINARY="${CLAUDE_SYNC_WATCH_BINARY:-$(dirname "$CLAUDE_SYNC_DAEMON_SCRIPT")/watch/target/release/claude-sync-watch}"
 
 # Main watcher control function
 claude-sync-watch() {